@@ -0,0 +1,124 @@
+//! Throughput and latency benchmarks for the HTTP server.
+//!
+//! Run with `cargo bench`. Each benchmark is run against the event loop, and
+//! against the older design without it (use_event_loop turned off), where
+//! each of a fixed pool of NUM_THREADS workers reads its own connection from
+//! start to finish.
+
+#![feature(test, io)]
+extern crate test;
+extern crate ros_rust;
+
+use std::old_io::{TcpListener, TcpStream};
+use std::old_io::net::ip::SocketAddr;
+use std::time::Duration;
+use test::Bencher;
+use ros_rust::http;
+
+const NUM_THREADS: usize = 4;
+
+/// Short enough that the stalled client benchmarks finish in a reasonable
+/// time without the event loop, where each iteration has to wait for the
+/// stalled clients to time out.
+const REQUEST_TIMEOUT_MS: u64 = 200;
+
+#[derive(Clone)]
+struct EchoHandler;
+
+impl http::HandlesHttpRequests for EchoHandler {
+    fn handle_request(&self, _: &http::RequestHeader, body: &str) -> (i32, String) {
+        (200, body.to_string())
+    }
+}
+
+fn start_server(use_event_loop: bool) -> http::ServerHandle {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut config = http::ServerConfig::new(NUM_THREADS);
+    config.request_timeout_ms = REQUEST_TIMEOUT_MS;
+    config.use_event_loop = use_event_loop;
+    http::run_http_server(listener, config, EchoHandler).unwrap()
+}
+
+fn make_request(addr: SocketAddr) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.write_all("POST /RPC2 HTTP/1.0\r\nContent-Length: 5\r\n\r\nhello".as_bytes()).unwrap();
+    let response = stream.read_to_string().unwrap();
+    assert!(response.as_slice().ends_with("hello"));
+}
+
+/// Latency of a single request to an otherwise idle server.
+fn request(b: &mut Bencher, use_event_loop: bool) {
+    let server = start_server(use_event_loop);
+    let addr = server.socket_addr();
+    b.iter(|| make_request(addr));
+    server.shutdown(Duration::seconds(5)).unwrap();
+}
+
+/// Throughput with several clients making requests at the same time.
+fn concurrent_requests(b: &mut Bencher, use_event_loop: bool) {
+    let server = start_server(use_event_loop);
+    let addr = server.socket_addr();
+    b.iter(|| {
+        let clients: Vec<_> = (0..4 * NUM_THREADS).map(|_| {
+            std::thread::scoped(move || {
+                for _ in 0..10 {
+                    make_request(addr);
+                }
+            })
+        }).collect();
+        drop(clients);
+    });
+    server.shutdown(Duration::seconds(5)).unwrap();
+}
+
+/// Latency of a request while more clients than there are worker threads
+/// have connected but not finished sending their requests.
+fn request_with_stalled_clients(b: &mut Bencher, use_event_loop: bool) {
+    let server = start_server(use_event_loop);
+    let addr = server.socket_addr();
+
+    b.iter(|| {
+        // New stalled clients each time, since the server drops them once
+        // they time out
+        let mut stalled_streams = vec![];
+        for _ in 0..2 * NUM_THREADS {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all("POST /RPC2 HTTP/1.0\r\n".as_bytes()).unwrap();
+            stalled_streams.push(stream);
+        }
+        make_request(addr);
+        stalled_streams
+    });
+
+    server.shutdown(Duration::seconds(15)).unwrap();
+}
+
+#[bench]
+fn bench_request(b: &mut Bencher) {
+    request(b, true);
+}
+
+#[bench]
+fn bench_request_worker_pool(b: &mut Bencher) {
+    request(b, false);
+}
+
+#[bench]
+fn bench_concurrent_requests(b: &mut Bencher) {
+    concurrent_requests(b, true);
+}
+
+#[bench]
+fn bench_concurrent_requests_worker_pool(b: &mut Bencher) {
+    concurrent_requests(b, false);
+}
+
+#[bench]
+fn bench_request_with_stalled_clients(b: &mut Bencher) {
+    request_with_stalled_clients(b, true);
+}
+
+#[bench]
+fn bench_request_with_stalled_clients_worker_pool(b: &mut Bencher) {
+    request_with_stalled_clients(b, false);
+}
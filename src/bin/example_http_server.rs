@@ -31,7 +31,11 @@ fn main() {
     };
 
     let handler = Handler {request_count: Arc::new(Mutex::new(0))};
-    match http::run_http_server(listener, 3, handler) {
+    let server = match http::run_http_server(listener, http::ServerConfig::new(3), handler) {
+        Ok(server) => server,
+        Err(err) => panic!("Unable to start server: {}", err),
+    };
+    match server.wait() {
         Ok(_) => (),
         Err(_) => panic!("Server died!"),
     }
@@ -1,16 +1,17 @@
 #![feature(io)]
 extern crate ros_rust;
+use ros_rust::http;
 use ros_rust::xmlrpc;
+use ros_rust::ros;
 
 use std::old_io::TcpListener;
 
 #[derive(Clone)]
-struct Handler;
+struct StatusHandler;
 
-impl xmlrpc::HandlesXmlrpcRequests for Handler {
-    fn handle_request(&self, _: &xmlrpc::Request) -> xmlrpc::Response {
-        println!("User got XMLRPC request");
-        xmlrpc::Response::Success {param: xmlrpc::Value::Boolean(true)}
+impl http::HandlesHttpRequests for StatusHandler {
+    fn handle_request(&self, _: &http::RequestHeader, _: &str) -> (i32, String) {
+        (200, "<html><header></header><body>Node is running</body></html>".to_string())
     }
 }
 
@@ -19,35 +20,39 @@ fn main() {
     println!("Starting xmlrpc server");
 
     // Parameters
-    let ros_master_uri = "localhost:11311";
+    let ros_master_uri = "http://localhost:11311/";
     let node_port: i32 = 1212;
     let caller_api = format!("http://127.0.0.1:{}", node_port);
     println!("caller_api: {}", caller_api);
 
-    println!("Registering published topic");
-    let request = xmlrpc::Request {
-        method_name: "registerPublisher".to_string(), params: vec![
-            xmlrpc::Value::String("/me".to_string()),
-            xmlrpc::Value::String("/foo".to_string()),
-            xmlrpc::Value::String("std_msgs/String".to_string()),
-            xmlrpc::Value::String(caller_api),
-            ]};
-
-    let c = xmlrpc::Client {server_uri: ros_master_uri.to_string()};
-    let response = match c.execute_request(&request) {
-            Ok(response) => response,
-            Err(err) => panic!("Err: {}", err),
-        };
-    println!("response: {:?}", response);
-
-    // Run a ROS Slave XMLRPC server
+    // The slave API reports publisher updates for our subscription
+    let registry = ros::Registry::new();
+    registry.add_subscription("/foo", "std_msgs/String", |uris: &[String]| {
+        println!("Publishers of /foo: {:?}", uris);
+    });
+    let slave_api = ros::SlaveApi::new(ros_master_uri, "127.0.0.1", registry.clone());
+
+    println!("Registering subscriber");
+    let master = ros::MasterProxy::new(ros_master_uri, "/me", caller_api.as_slice());
+    match master.register_subscriber("/foo", "std_msgs/String") {
+        Ok(uris) => println!("Publishers of /foo: {:?}", uris),
+        Err(err) => panic!("Err: {}", err),
+    };
+
+    // Run a ROS Slave XMLRPC server, with a status page on the same port
     println!("Starting server");
-    let handler = Handler;
+    let mut router = http::Router::new();
+    router.add_method_route("POST", "/", xmlrpc::RequestHandler::new(slave_api));
+    router.add_method_route("GET", "/status", StatusHandler);
     let listener = match TcpListener::bind(format!("127.0.0.1:{}", node_port).as_slice()) {
         Ok(l) => l,
         Err(err) => panic!(format!("Unable to bind to port: {}", err)),
     };
-    match xmlrpc::run_xmlrpc_server(listener, 4, handler) {
+    let server = match http::run_http_server(listener, http::ServerConfig::new(4), router) {
+        Ok(server) => server,
+        Err(err) => panic!("Unable to start server: {}", err),
+    };
+    match server.wait() {
         Ok(_) => {println!("Exiting happily")},
         Err(_) => {println!("Exiting on error")}
     };
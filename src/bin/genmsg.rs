@@ -0,0 +1,21 @@
+extern crate ros_rust;
+
+use std::os;
+use ros_rust::genmsg;
+
+/// Generate Rust code for the messages and services of some ROS packages.
+/// Usage: genmsg OUT_FILE PACKAGE_DIR...
+#[allow(dead_code)]
+fn main() {
+    let args: Vec<String> = os::args();
+    if args.len() < 3 {
+        panic!("Usage: {} OUT_FILE PACKAGE_DIR...", args[0]);
+    }
+    let out_file = Path::new(args[1].as_slice());
+    let package_dirs: Vec<Path> = args[2..].iter().map(|dir| Path::new(dir.as_slice())).collect();
+
+    match genmsg::generate_file(package_dirs.as_slice(), &out_file) {
+        Ok(_) => println!("Wrote {}", out_file.display()),
+        Err(err) => panic!("Failed to generate messages: {}", err),
+    };
+}
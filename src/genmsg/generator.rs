@@ -0,0 +1,451 @@
+use std::old_io::fs::File;
+use genmsg::parser::{ArrayLength, BaseType, Constant, Field, MsgSpec, PrimitiveType, SrvSpec};
+use genmsg::package::{load_package, MsgSet, Package};
+
+/// Path of this crate in generated code.
+const CRATE: &'static str = "::ros_rust";
+
+/// Longest fixed length array which implements RosField, and so can be
+/// used for fields of Copy types. Longer fixed length arrays are Vecs.
+const MAX_NATIVE_ARRAY: usize = 32;
+
+/// Words which can't be used as field names, which get an underscore added.
+const KEYWORDS: [&'static str; 52] = [
+    "abstract", "alignof", "as", "be", "box", "break", "const", "continue", "crate", "do", "else", "enum",
+    "extern", "false", "final", "fn", "for", "if", "impl", "in", "let", "loop", "macro", "match", "mod",
+    "move", "mut", "offsetof", "override", "priv", "proc", "pub", "pure", "ref", "return", "self", "Self",
+    "sizeof", "static", "struct", "super", "trait", "true", "type", "typeof", "unsafe", "unsized", "use",
+    "virtual", "where", "while", "yield",
+];
+
+/// Generate the code for the packages in a set of directories, and write it
+/// to out_file. This is meant to be called by build scripts; see generate.
+pub fn generate_file(package_dirs: &[Path], out_file: &Path) -> Result<(), String> {
+    let mut packages = vec![];
+    for dir in package_dirs.iter() {
+        packages.push(try!(load_package(dir)));
+    }
+    let code = try!(generate(packages.as_slice()));
+    match File::create(out_file).write_str(code.as_slice()) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("Failed to write {}: {}", out_file.display(), err)),
+    }
+}
+
+/// Generate Rust code for the messages and services of some packages. Each
+/// package becomes a module, e.g. std_msgs::Header, and each message a
+/// struct which implements RosMessage. Constants are module level, named
+/// after the message, e.g. GOAL_STATUS_PENDING for GoalStatus' PENDING.
+/// Each service is a unit struct which implements RosService.
+///
+/// Every message type used must be in one of the packages. The code
+/// refers to other packages with super::, so the modules should be
+/// included side by side, e.g.
+///
+/// ```ignore
+/// mod msgs {
+///     include!(concat!(env!("OUT_DIR"), "/msgs.rs"));
+/// }
+/// ```
+pub fn generate(packages: &[Package]) -> Result<String, String> {
+    let set = try!(MsgSet::new(packages));
+    let mut code = Code::new();
+    code.line("// Generated from ROS message and service definitions, do not edit.");
+    for package in packages.iter() {
+        code.line("");
+        code.line("#[allow(dead_code, non_upper_case_globals, non_camel_case_types)]");
+        code.open(format!("pub mod {} {{", package.name).as_slice());
+        for spec in package.messages.iter() {
+            write_message(&mut code, &set, spec);
+        }
+        for srv in package.services.iter() {
+            write_message(&mut code, &set, &srv.request);
+            write_message(&mut code, &set, &srv.response);
+            write_service(&mut code, &set, srv);
+        }
+        code.close("}");
+    }
+    Ok(code.text)
+}
+
+/// Lines of code, indented according to how many blocks are open.
+struct Code {
+    text: String,
+    indent: usize,
+}
+
+impl Code {
+    fn new() -> Code {
+        Code {text: String::new(), indent: 0}
+    }
+
+    fn line(&mut self, line: &str) {
+        if !line.is_empty() {
+            for _ in 0..self.indent {
+                self.text.push_str("    ");
+            }
+        }
+        self.text.push_str(line);
+        self.text.push('\n');
+    }
+
+    /// Add a line which opens a block.
+    fn open(&mut self, line: &str) {
+        self.line(line);
+        self.indent += 1;
+    }
+
+    /// Add a line which closes a block.
+    fn close(&mut self, line: &str) {
+        self.indent -= 1;
+        self.line(line);
+    }
+}
+
+fn write_message(code: &mut Code, set: &MsgSet, spec: &MsgSpec) {
+    let name = spec.name.name.as_slice();
+    code.line("");
+    for constant in spec.constants.iter() {
+        write_constant(code, name, constant);
+    }
+
+    code.line("#[derive(Debug, PartialEq, Clone)]");
+    if spec.fields.is_empty() {
+        code.line(format!("pub struct {};", name).as_slice());
+    } else {
+        code.open(format!("pub struct {} {{", name).as_slice());
+        for field in spec.fields.iter() {
+            code.line(format!("pub {}: {},", field_name(field), field_type(spec, field)).as_slice());
+        }
+        code.close("}");
+    }
+
+    code.line("");
+    code.open(format!("impl {}::msg::RosField for {} {{", CRATE, name).as_slice());
+    if spec.fields.is_empty() {
+        code.open("fn encode<W: ::std::old_io::Writer>(&self, _: &mut W) -> ::std::old_io::IoResult<()> {");
+    } else {
+        code.open("fn encode<W: ::std::old_io::Writer>(&self, stream: &mut W) -> ::std::old_io::IoResult<()> {");
+    }
+    for field in spec.fields.iter() {
+        write_encode_field(code, spec, field);
+    }
+    code.line("Ok(())");
+    code.close("}");
+    code.line("");
+    if spec.fields.is_empty() {
+        code.open(format!("fn decode<R: ::std::old_io::Reader>(_: &mut R) \
+            -> ::std::result::Result<{}, ::std::string::String> {{", name).as_slice());
+        code.line(format!("Ok({})", name).as_slice());
+    } else {
+        code.open(format!("fn decode<R: ::std::old_io::Reader>(stream: &mut R) \
+            -> ::std::result::Result<{}, ::std::string::String> {{", name).as_slice());
+        code.open(format!("Ok({} {{", name).as_slice());
+        for field in spec.fields.iter() {
+            write_decode_field(code, field);
+        }
+        code.close("})");
+    }
+    code.close("}");
+    code.close("}");
+
+    code.line("");
+    code.open(format!("impl {}::msg::RosMessage for {} {{", CRATE, name).as_slice());
+    write_str_fn(code, "msg_type", format!("{}", spec.name).as_slice());
+    code.line("");
+    // The set has every message, so these can't fail
+    write_str_fn(code, "md5sum", set.md5sum(&spec.name).unwrap().as_slice());
+    code.line("");
+    write_str_fn(code, "msg_definition", set.full_definition(&spec.name).unwrap().as_slice());
+    code.close("}");
+}
+
+fn write_service(code: &mut Code, set: &MsgSet, srv: &SrvSpec) {
+    let name = srv.name.name.as_slice();
+    code.line("");
+    code.line(format!("pub struct {};", name).as_slice());
+    code.line("");
+    code.open(format!("impl {}::msg::RosService for {} {{", CRATE, name).as_slice());
+    code.line(format!("type Request = {};", srv.request.name.name).as_slice());
+    code.line(format!("type Response = {};", srv.response.name.name).as_slice());
+    code.line("");
+    write_str_fn(code, "srv_type", format!("{}", srv.name).as_slice());
+    code.line("");
+    write_str_fn(code, "md5sum", set.srv_md5sum(srv).unwrap().as_slice());
+    code.close("}");
+}
+
+/// Write a function which returns a string.
+fn write_str_fn(code: &mut Code, name: &str, value: &str) {
+    code.open(format!("fn {}() -> &'static str {{", name).as_slice());
+    code.line(format!("{:?}", value).as_slice());
+    code.close("}");
+}
+
+fn write_constant(code: &mut Code, msg_name: &str, constant: &Constant) {
+    let value = constant.value.as_slice();
+    let (rust_type, rust_value) = match constant.constant_type {
+        PrimitiveType::String => ("&'static str".to_string(), format!("{:?}", value)),
+        PrimitiveType::Bool => ("bool".to_string(), match value {
+            "true" | "True" | "1" => "true".to_string(),
+            _ => "false".to_string(),
+        }),
+        PrimitiveType::Float32 | PrimitiveType::Float64 => {
+            let rust_type = primitive_type(constant.constant_type);
+            (rust_type.to_string(), float_literal(rust_type, value))
+        },
+        other => (primitive_type(other).to_string(), value.to_string()),
+    };
+    code.line(format!("pub const {}_{}: {} = {};", screaming_snake_case(msg_name), constant.name, rust_type,
+        rust_value).as_slice());
+}
+
+/// A float literal for a float constant's value, which is either a number or
+/// something like "inf" or "nan".
+fn float_literal(rust_type: &str, value: &str) -> String {
+    let is_number = value.chars().all(|c| match c {
+        '0'...'9' | '-' | '+' | '.' | 'e' | 'E' => true,
+        _ => false,
+    });
+    if is_number {
+        let value = value.trim_left_matches('+');
+        let (sign, value) = match value.starts_with("-") {
+            true => ("-", value.slice_from(1)),
+            false => ("", value),
+        };
+        let (mantissa, exponent) = match value.find(|c: char| c == 'e' || c == 'E') {
+            Some(e) => (value.slice_to(e), value.slice_from(e)),
+            None => (value, ""),
+        };
+        // Rust needs digits on both sides of the point, e.g. 0.5 for .5
+        let mantissa = if !mantissa.contains(".") {
+            format!("{}.0", mantissa)
+        } else if mantissa.starts_with(".") {
+            format!("0{}", mantissa)
+        } else if mantissa.ends_with(".") {
+            format!("{}0", mantissa)
+        } else {
+            mantissa.to_string()
+        };
+        format!("{}{}{}", sign, mantissa, exponent)
+    } else {
+        let parsed: f64 = value.parse().unwrap_or(0.0);
+        let name = if parsed.is_nan() {
+            "NAN"
+        } else if parsed > 0.0 {
+            "INFINITY"
+        } else {
+            "NEG_INFINITY"
+        };
+        format!("::std::{}::{}", rust_type, name)
+    }
+}
+
+/// Convert e.g. "GoalStatus" to "GOAL_STATUS".
+fn screaming_snake_case(name: &str) -> String {
+    let mut result = String::new();
+    let mut previous_lower = false;
+    for c in name.chars() {
+        if c.is_uppercase() && previous_lower {
+            result.push('_');
+        }
+        previous_lower = c.is_lowercase() || c.is_numeric();
+        result.push(c.to_uppercase());
+    }
+    result
+}
+
+fn field_name(field: &Field) -> String {
+    match KEYWORDS.contains(&field.name.as_slice()) {
+        true => format!("{}_", field.name),
+        false => field.name.clone(),
+    }
+}
+
+fn primitive_type(primitive: PrimitiveType) -> &'static str {
+    match primitive {
+        PrimitiveType::Bool => "bool",
+        PrimitiveType::Int8 | PrimitiveType::Byte => "i8",
+        PrimitiveType::UInt8 | PrimitiveType::Char => "u8",
+        PrimitiveType::Int16 => "i16",
+        PrimitiveType::UInt16 => "u16",
+        PrimitiveType::Int32 => "i32",
+        PrimitiveType::UInt32 => "u32",
+        PrimitiveType::Int64 => "i64",
+        PrimitiveType::UInt64 => "u64",
+        PrimitiveType::Float32 => "f32",
+        PrimitiveType::Float64 => "f64",
+        PrimitiveType::String => "::std::string::String",
+        // These are relative to CRATE
+        PrimitiveType::Time => "::ros_rust::msg::Time",
+        PrimitiveType::Duration => "::ros_rust::msg::Duration",
+    }
+}
+
+/// The type of a field, or of its elements if it's an array.
+fn base_type(spec: &MsgSpec, field: &Field) -> String {
+    match field.base_type {
+        BaseType::Primitive(primitive) => primitive_type(primitive).to_string(),
+        BaseType::Message(ref name) if name.package == spec.name.package => name.name.clone(),
+        BaseType::Message(ref name) => format!("super::{}::{}", name.package, name.name),
+    }
+}
+
+fn field_type(spec: &MsgSpec, field: &Field) -> String {
+    let base_type = base_type(spec, field);
+    match field.array {
+        None => base_type,
+        Some(ArrayLength::Fixed(length)) if is_native_array(field, length) => format!("[{}; {}]", base_type, length),
+        Some(_) => format!("::std::vec::Vec<{}>", base_type),
+    }
+}
+
+/// Whether a fixed length array field is a Rust array, rather than a Vec.
+fn is_native_array(field: &Field, length: usize) -> bool {
+    let copyable = match field.base_type {
+        BaseType::Primitive(PrimitiveType::String) | BaseType::Message(_) => false,
+        BaseType::Primitive(_) => true,
+    };
+    copyable && length <= MAX_NATIVE_ARRAY
+}
+
+/// A fixed length array which is a Vec, and so can't use the RosField impl
+/// for Vecs, which sends the length.
+fn fixed_vec_length(field: &Field) -> Option<usize> {
+    match field.array {
+        Some(ArrayLength::Fixed(length)) if !is_native_array(field, length) => Some(length),
+        _ => None,
+    }
+}
+
+fn write_encode_field(code: &mut Code, spec: &MsgSpec, field: &Field) {
+    let name = field_name(field);
+    let length = match fixed_vec_length(field) {
+        None => {
+            code.line(format!("try!({}::msg::RosField::encode(&self.{}, stream));", CRATE, name).as_slice());
+            return;
+        },
+        Some(length) => length,
+    };
+    code.open(format!("if self.{}.len() != {} {{", name, length).as_slice());
+    code.open("return Err(::std::old_io::IoError {");
+    code.line("kind: ::std::old_io::IoErrorKind::InvalidInput,");
+    code.line("desc: \"Fixed length array has the wrong length\",");
+    code.line(format!("detail: Some(format!(\"{}.{} has {{}} elements instead of {}\", self.{}.len())),",
+        spec.name.name, field.name, length, name).as_slice());
+    code.close("});");
+    code.close("}");
+    code.open(format!("for element in self.{}.iter() {{", name).as_slice());
+    code.line(format!("try!({}::msg::RosField::encode(element, stream));", CRATE).as_slice());
+    code.close("}");
+}
+
+fn write_decode_field(code: &mut Code, field: &Field) {
+    let name = field_name(field);
+    let length = match fixed_vec_length(field) {
+        None => {
+            code.line(format!("{}: try!({}::msg::RosField::decode(stream)),", name, CRATE).as_slice());
+            return;
+        },
+        Some(length) => length,
+    };
+    code.open(format!("{}: {{", name).as_slice());
+    code.line(format!("let mut elements = ::std::vec::Vec::with_capacity({});", length).as_slice());
+    code.open(format!("for _ in 0..{} {{", length).as_slice());
+    code.line(format!("elements.push(try!({}::msg::RosField::decode(stream)));", CRATE).as_slice());
+    code.close("}");
+    code.line("elements");
+    code.close("},");
+}
+
+#[cfg(test)]
+mod tests {
+    use genmsg::parser::{parse_msg, parse_srv};
+    use genmsg::package::Package;
+    use super::{float_literal, generate, screaming_snake_case};
+
+    fn packages() -> Vec<Package> {
+        vec![
+            Package {
+                name: "std_msgs".to_string(),
+                messages: vec![parse_msg("std_msgs", "Header", "uint32 seq\ntime stamp\nstring frame_id\n").unwrap()],
+                services: vec![],
+            },
+            Package {
+                name: "test_msgs".to_string(),
+                messages: vec![
+                    parse_msg("test_msgs", "Empty", "").unwrap(),
+                    parse_msg("test_msgs", "Test", "int32 X=5\nfloat32 F=1\nstring S=a\"b\n\
+                        Header header\nEmpty[] empties\nuint8[4] bytes\nstring[2] names\nbool type\n").unwrap(),
+                ],
+                services: vec![parse_srv("test_msgs", "Add", "int32 a\n---\nint32 sum\n").unwrap()],
+            },
+        ]
+    }
+
+    fn lines(code: &str) -> Vec<&str> {
+        code.lines().map(|line| line.trim()).collect()
+    }
+
+    #[test]
+    fn test_generate() {
+        let code = generate(packages().as_slice()).unwrap();
+        let lines = lines(code.as_slice());
+        let expected = [
+            "pub mod std_msgs {",
+            "pub struct Header {",
+            "pub stamp: ::ros_rust::msg::Time,",
+            "pub mod test_msgs {",
+            "pub struct Empty;",
+            "Ok(Empty)",
+            "pub const TEST_X: i32 = 5;",
+            "pub const TEST_F: f32 = 1.0;",
+            "pub const TEST_S: &'static str = \"a\\\"b\";",
+            "pub header: super::std_msgs::Header,",
+            "pub empties: ::std::vec::Vec<Empty>,",
+            "pub bytes: [u8; 4],",
+            "pub names: ::std::vec::Vec<::std::string::String>,",
+            "pub type_: bool,",
+            "try!(::ros_rust::msg::RosField::encode(&self.header, stream));",
+            "if self.names.len() != 2 {",
+            "type_: try!(::ros_rust::msg::RosField::decode(stream)),",
+            "for _ in 0..2 {",
+            "\"test_msgs/Test\"",
+            "pub struct AddRequest {",
+            "pub struct AddResponse {",
+            "pub struct Add;",
+            "impl ::ros_rust::msg::RosService for Add {",
+            "type Request = AddRequest;",
+            "\"test_msgs/Add\"",
+            "\"2176decaecbce78abc3b96ef049fabed\"",
+            "\"uint32 seq\\ntime stamp\\nstring frame_id\\n\"",
+        ];
+        for line in expected.iter() {
+            assert!(lines.contains(line), "Missing line: {}", line);
+        }
+
+        let missing = generate(&packages()[1..]);
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn test_float_literal() {
+        assert_eq!(float_literal("f32", "1").as_slice(), "1.0");
+        assert_eq!(float_literal("f32", "+2.5").as_slice(), "2.5");
+        assert_eq!(float_literal("f32", ".5").as_slice(), "0.5");
+        assert_eq!(float_literal("f32", "-.5").as_slice(), "-0.5");
+        assert_eq!(float_literal("f32", "5.").as_slice(), "5.0");
+        assert_eq!(float_literal("f64", "1e10").as_slice(), "1.0e10");
+        assert_eq!(float_literal("f64", "-.5E-3").as_slice(), "-0.5E-3");
+        assert_eq!(float_literal("f64", "inf").as_slice(), "::std::f64::INFINITY");
+        assert_eq!(float_literal("f64", "-inf").as_slice(), "::std::f64::NEG_INFINITY");
+        assert_eq!(float_literal("f32", "nan").as_slice(), "::std::f32::NAN");
+    }
+
+    #[test]
+    fn test_screaming_snake_case() {
+        assert_eq!(screaming_snake_case("GoalStatus").as_slice(), "GOAL_STATUS");
+        assert_eq!(screaming_snake_case("Vector3Stamped").as_slice(), "VECTOR3_STAMPED");
+        assert_eq!(screaming_snake_case("ABC").as_slice(), "ABC");
+    }
+}
@@ -0,0 +1,97 @@
+//! The MD5 hash, which ROS uses to check that the two ends of a connection
+//! agree on a message definition.
+
+/// Amounts to rotate by in each step, four per round.
+const SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+
+/// floor(abs(sin(i + 1)) * 2^32) for each step i.
+const SINES: [u32; 64] = [
+    0xd76aa478, 0xe8c7b756, 0x242070db, 0xc1bdceee,
+    0xf57c0faf, 0x4787c62a, 0xa8304613, 0xfd469501,
+    0x698098d8, 0x8b44f7af, 0xffff5bb1, 0x895cd7be,
+    0x6b901122, 0xfd987193, 0xa679438e, 0x49b40821,
+    0xf61e2562, 0xc040b340, 0x265e5a51, 0xe9b6c7aa,
+    0xd62f105d, 0x02441453, 0xd8a1e681, 0xe7d3fbc8,
+    0x21e1cde6, 0xc33707d6, 0xf4d50d87, 0x455a14ed,
+    0xa9e3e905, 0xfcefa3f8, 0x676f02d9, 0x8d2a4c8a,
+    0xfffa3942, 0x8771f681, 0x6d9d6122, 0xfde5380c,
+    0xa4beea44, 0x4bdecfa9, 0xf6bb4b60, 0xbebfbc70,
+    0x289b7ec6, 0xeaa127fa, 0xd4ef3085, 0x04881d05,
+    0xd9d4d039, 0xe6db99e5, 0x1fa27cf8, 0xc4ac5665,
+    0xf4292244, 0x432aff97, 0xab9423a7, 0xfc93a039,
+    0x655b59c3, 0x8f0ccc92, 0xffeff47d, 0x85845dd1,
+    0x6fa87e4f, 0xfe2ce6e0, 0xa3014314, 0x4e0811a1,
+    0xf7537e82, 0xbd3af235, 0x2ad7d2bb, 0xeb86d391,
+];
+
+/// The MD5 hash of some bytes, as 32 lower case hex digits.
+pub fn md5_hex(bytes: &[u8]) -> String {
+    // Pad with a 1 bit, then zeros up to 8 bytes short of a whole block,
+    // then the length in bits
+    let mut message = bytes.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    // Writing to a Vec can't fail
+    message.write_le_u64((bytes.len() as u64) << 3).unwrap();
+
+    let mut state: [u32; 4] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476];
+    for block in message.chunks(64) {
+        let mut words = [0u32; 16];
+        for (i, word) in words.iter_mut().enumerate() {
+            *word = (block[i * 4] as u32) | (block[i * 4 + 1] as u32) << 8 |
+                (block[i * 4 + 2] as u32) << 16 | (block[i * 4 + 3] as u32) << 24;
+        }
+
+        let (mut a, mut b, mut c, mut d) = (state[0], state[1], state[2], state[3]);
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let sum = add(add(a, f), add(SINES[i], words[g]));
+            a = d;
+            d = c;
+            c = b;
+            b = add(b, sum.rotate_left(SHIFTS[(i / 16) * 4 + i % 4]));
+        }
+        state[0] = add(state[0], a);
+        state[1] = add(state[1], b);
+        state[2] = add(state[2], c);
+        state[3] = add(state[3], d);
+    }
+
+    let mut hex = String::with_capacity(32);
+    for word in state.iter() {
+        for i in 0..4 {
+            hex.push_str(format!("{:02x}", (*word >> (i * 8)) & 0xff).as_slice());
+        }
+    }
+    hex
+}
+
+/// Addition modulo 2^32.
+fn add(a: u32, b: u32) -> u32 {
+    ((a as u64 + b as u64) & 0xffffffff) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::md5_hex;
+
+    #[test]
+    fn test_md5() {
+        assert_eq!(md5_hex(b"").as_slice(), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(md5_hex(b"a").as_slice(), "0cc175b9c0f1b6a831c399e269772661");
+        assert_eq!(md5_hex(b"abc").as_slice(), "900150983cd24fb0d6963f7d28e17f72");
+        assert_eq!(md5_hex(b"The quick brown fox jumps over the lazy dog").as_slice(),
+            "9e107d9d372bb6826bd81d3542a419d6");
+        // Long enough that the padding needs another block
+        let mut long = vec![b'x'; 64];
+        long.extend(vec![b'y'; 56].into_iter());
+        assert_eq!(md5_hex(long.as_slice()).as_slice(), "6ebbe67a5fbf78e2f8bda117109c25a0");
+    }
+}
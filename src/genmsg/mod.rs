@@ -0,0 +1,13 @@
+//! Tools for ROS message and service definitions, as written in .msg and
+//! .srv files, including generating Rust types from them.
+
+pub use genmsg::parser::{parse_msg, parse_srv, is_valid_name, ParseError, PrimitiveType, BaseType, ArrayLength,
+                         MsgName, Field, Constant, MsgSpec, SrvSpec};
+pub use genmsg::package::{load_package, Package, MsgSet};
+pub use genmsg::generator::{generate, generate_file};
+pub use genmsg::md5::md5_hex;
+
+mod parser;
+mod package;
+mod generator;
+mod md5;
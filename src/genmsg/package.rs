@@ -0,0 +1,359 @@
+use std::collections::BTreeMap;
+use std::old_io::fs;
+use std::old_io::fs::{File, PathExtensions};
+use genmsg::parser::{parse_msg, parse_srv, is_valid_name, ArrayLength, BaseType, Field, MsgName, MsgSpec, SrvSpec};
+use genmsg::md5::md5_hex;
+
+/// The message and service definitions of a ROS package.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Package {
+    pub name: String,
+    pub messages: Vec<MsgSpec>,
+    pub services: Vec<SrvSpec>,
+}
+
+/// Load the definitions in a package directory, from the .msg files in its
+/// msg folder and the .srv files in its srv folder. The package is named
+/// after the directory.
+pub fn load_package(dir: &Path) -> Result<Package, String> {
+    let name = match dir.filename_str() {
+        Some(name) if is_valid_name(name) => name.to_string(),
+        _ => return Err(format!("Bad package directory {}", dir.display())),
+    };
+    let mut package = Package {name: name, messages: vec![], services: vec![]};
+    for (path, msg_name, text) in try!(read_definitions(&dir.join("msg"), "msg")).into_iter() {
+        match parse_msg(package.name.as_slice(), msg_name.as_slice(), text.as_slice()) {
+            Ok(spec) => package.messages.push(spec),
+            Err(err) => return Err(format!("{}:{}", path.display(), err)),
+        };
+    }
+    for (path, srv_name, text) in try!(read_definitions(&dir.join("srv"), "srv")).into_iter() {
+        match parse_srv(package.name.as_slice(), srv_name.as_slice(), text.as_slice()) {
+            Ok(spec) => package.services.push(spec),
+            Err(err) => return Err(format!("{}:{}", path.display(), err)),
+        };
+    }
+    Ok(package)
+}
+
+/// Read the files in dir with an extension, as (path, name, text), sorted
+/// by name. There are none if dir doesn't exist.
+fn read_definitions(dir: &Path, extension: &str) -> Result<Vec<(Path, String, String)>, String> {
+    if !dir.is_dir() {
+        return Ok(vec![]);
+    }
+    let mut paths = match fs::readdir(dir) {
+        Ok(paths) => paths,
+        Err(err) => return Err(format!("Failed to list {}: {}", dir.display(), err)),
+    };
+    paths.sort();
+
+    let mut definitions = vec![];
+    for path in paths.into_iter() {
+        if path.extension_str() != Some(extension) {
+            continue;
+        }
+        let name = match path.filestem_str() {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+        let text = match File::open(&path).read_to_string() {
+            Ok(text) => text,
+            Err(err) => return Err(format!("Failed to read {}: {}", path.display(), err)),
+        };
+        definitions.push((path, name, text));
+    }
+    Ok(definitions)
+}
+
+/// Line between the definitions in a full message definition.
+const DEFINITION_SEPARATOR: &'static str =
+    "================================================================================";
+
+/// All of the message types of a set of packages, including the requests
+/// and responses of services. Every type used by a message is in the set,
+/// and no message contains itself.
+pub struct MsgSet {
+    specs: BTreeMap<MsgName, MsgSpec>,
+}
+
+impl MsgSet {
+    pub fn new(packages: &[Package]) -> Result<MsgSet, String> {
+        let mut specs = BTreeMap::new();
+        for package in packages.iter() {
+            let srv_specs = package.services.iter().flat_map(|srv| vec![&srv.request, &srv.response].into_iter());
+            for spec in package.messages.iter().chain(srv_specs) {
+                if specs.insert(spec.name.clone(), spec.clone()).is_some() {
+                    return Err(format!("{} is defined more than once", spec.name));
+                }
+            }
+        }
+        let set = MsgSet {specs: specs};
+
+        for spec in set.specs.values() {
+            for field in spec.fields.iter() {
+                if let Some(name) = field_message(field) {
+                    if !set.specs.contains_key(name) {
+                        return Err(format!("{} uses unknown type {} on line {}", spec.name, name, field.line));
+                    }
+                }
+            }
+        }
+        for name in set.specs.keys() {
+            try!(set.check_not_recursive(name, &mut vec![]));
+        }
+        Ok(set)
+    }
+
+    pub fn get(&self, name: &MsgName) -> Option<&MsgSpec> {
+        self.specs.get(name)
+    }
+
+    /// The MD5 sum of a message type, as ROS computes it. This is the MD5
+    /// of the definition with comments and whitespace removed, constants
+    /// first, and the types of message fields replaced by their MD5 sums.
+    pub fn md5sum(&self, name: &MsgName) -> Option<String> {
+        self.md5_text(name).map(|text| md5_hex(text.as_bytes()))
+    }
+
+    /// The MD5 sum of a service, which covers the request and response.
+    pub fn srv_md5sum(&self, srv: &SrvSpec) -> Option<String> {
+        match (self.md5_text(&srv.request.name), self.md5_text(&srv.response.name)) {
+            (Some(request), Some(response)) => Some(md5_hex(format!("{}{}", request, response).as_bytes())),
+            _ => None,
+        }
+    }
+
+    /// The text which a message type's MD5 sum is the MD5 of.
+    fn md5_text(&self, name: &MsgName) -> Option<String> {
+        let spec = match self.specs.get(name) {
+            Some(spec) => spec,
+            None => return None,
+        };
+        let mut lines = vec![];
+        for constant in spec.constants.iter() {
+            lines.push(format!("{} {}={}", constant.constant_type.name(), constant.name, constant.value));
+        }
+        for field in spec.fields.iter() {
+            match field.base_type {
+                BaseType::Primitive(primitive) => {
+                    let array = match field.array {
+                        None => "".to_string(),
+                        Some(ArrayLength::Variable) => "[]".to_string(),
+                        Some(ArrayLength::Fixed(length)) => format!("[{}]", length),
+                    };
+                    lines.push(format!("{}{} {}", primitive.name(), array, field.name));
+                },
+                // Arrays of messages are just the MD5 sum too
+                BaseType::Message(ref field_type) => match self.md5sum(field_type) {
+                    Some(md5sum) => lines.push(format!("{} {}", md5sum, field.name)),
+                    None => return None,
+                },
+            }
+        }
+        Some(lines.connect("\n"))
+    }
+
+    /// The full definition of a message type, as sent in connection headers:
+    /// its definition followed by the definitions of all of the types it
+    /// uses, each headed by e.g. "MSG: std_msgs/Header".
+    pub fn full_definition(&self, name: &MsgName) -> Option<String> {
+        let spec = match self.specs.get(name) {
+            Some(spec) => spec,
+            None => return None,
+        };
+        let mut dependencies = vec![];
+        self.add_dependencies(spec, &mut dependencies);
+
+        let mut text = spec.text.clone();
+        text.push('\n');
+        for dependency in dependencies.iter() {
+            text.push_str(format!("{}\nMSG: {}\n", DEFINITION_SEPARATOR, dependency).as_slice());
+            if let Some(dependency_spec) = self.specs.get(dependency) {
+                text.push_str(dependency_spec.text.as_slice());
+            }
+            text.push('\n');
+        }
+        // The definitions are separated by newlines, not terminated by them
+        text.pop();
+        Some(text)
+    }
+
+    /// Add the types spec uses, directly or through other types, to
+    /// dependencies, each after the type which uses it first.
+    fn add_dependencies(&self, spec: &MsgSpec, dependencies: &mut Vec<MsgName>) {
+        for name in spec.dependencies().into_iter() {
+            if dependencies.contains(&name) {
+                continue;
+            }
+            dependencies.push(name.clone());
+            if let Some(dependency_spec) = self.specs.get(&name) {
+                self.add_dependencies(dependency_spec, dependencies);
+            }
+        }
+    }
+
+    /// Check that name doesn't contain any of the messages in path, which
+    /// contain it.
+    fn check_not_recursive(&self, name: &MsgName, path: &mut Vec<MsgName>) -> Result<(), String> {
+        if path.contains(name) {
+            let names: Vec<String> = path.iter().map(|name| format!("{}", name)).collect();
+            return Err(format!("{} contains itself, through {}", name, names.connect(" -> ")));
+        }
+        path.push(name.clone());
+        if let Some(spec) = self.specs.get(name) {
+            for dependency in spec.dependencies().iter() {
+                try!(self.check_not_recursive(dependency, path));
+            }
+        }
+        path.pop();
+        Ok(())
+    }
+}
+
+fn field_message(field: &Field) -> Option<&MsgName> {
+    match field.base_type {
+        BaseType::Message(ref name) => Some(name),
+        BaseType::Primitive(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::old_io::TempDir;
+    use std::old_io::fs;
+    use std::old_io::fs::File;
+    use std::old_io::USER_RWX;
+    use msg::{RosMessage, std_msgs};
+    use genmsg::parser::{parse_msg, parse_srv, MsgName};
+    use super::{load_package, MsgSet, Package, DEFINITION_SEPARATOR};
+
+    fn msg_name(package: &str, name: &str) -> MsgName {
+        MsgName {package: package.to_string(), name: name.to_string()}
+    }
+
+    fn package(name: &str, messages: &[(&str, &str)]) -> Package {
+        Package {
+            name: name.to_string(),
+            messages: messages.iter().map(|&(msg_name, text)| parse_msg(name, msg_name, text).unwrap()).collect(),
+            services: vec![],
+        }
+    }
+
+    #[test]
+    fn test_load_package() {
+        let dir = TempDir::new("genmsg").unwrap();
+        let package_dir = dir.path().join("test_msgs");
+        fs::mkdir_recursive(&package_dir.join("msg"), USER_RWX).unwrap();
+        fs::mkdir_recursive(&package_dir.join("srv"), USER_RWX).unwrap();
+        File::create(&package_dir.join("msg/Point.msg")).write_str("float64 x\nfloat64 y\n").unwrap();
+        File::create(&package_dir.join("msg/README")).write_str("not a message").unwrap();
+        File::create(&package_dir.join("srv/Add.srv")).write_str("int32 a\n---\nint32 sum\n").unwrap();
+
+        let package = load_package(&package_dir).unwrap();
+        assert_eq!(package.name.as_slice(), "test_msgs");
+        assert_eq!(package.messages, vec![parse_msg("test_msgs", "Point", "float64 x\nfloat64 y\n").unwrap()]);
+        assert_eq!(package.services, vec![parse_srv("test_msgs", "Add", "int32 a\n---\nint32 sum\n").unwrap()]);
+
+        File::create(&package_dir.join("msg/Bad.msg")).write_str("float64\n").unwrap();
+        let err = load_package(&package_dir).unwrap_err();
+        assert!(err.contains("Bad.msg:1:8: "), "{}", err);
+    }
+
+    #[test]
+    fn test_msg_set() {
+        let packages = vec![
+            package("std_msgs", &[("Header", "uint32 seq\ntime stamp\nstring frame_id\n")]),
+            package("test_msgs", &[("A", "Header header\nB[] b\n"), ("B", "int32 x\n")]),
+        ];
+        assert!(MsgSet::new(packages.as_slice()).is_ok());
+
+        let err = MsgSet::new(&packages[1..]).err().unwrap();
+        assert_eq!(err.as_slice(), "test_msgs/A uses unknown type std_msgs/Header on line 1");
+
+        let recursive = vec![package("test_msgs", &[("A", "B b\n"), ("B", "A[] a\n")])];
+        let err = MsgSet::new(recursive.as_slice()).err().unwrap();
+        assert_eq!(err.as_slice(), "test_msgs/A contains itself, through test_msgs/A -> test_msgs/B");
+
+        let duplicate = vec![package("test_msgs", &[("A", "")]), package("test_msgs", &[("A", "")])];
+        assert!(MsgSet::new(duplicate.as_slice()).is_err());
+    }
+
+    #[test]
+    fn test_md5sum() {
+        let mut packages = vec![
+            package("std_msgs", &[("Header", "uint32 seq\ntime stamp\nstring frame_id\n")]),
+            package("geometry_msgs", &[
+                ("Point", "# A point\nfloat64 x\nfloat64 y\nfloat64 z\n"),
+                ("PointStamped", "Header header\nPoint point\n"),
+            ]),
+            package("actionlib_msgs", &[
+                ("GoalID", "time stamp\nstring id\n"),
+                ("GoalStatus", "GoalID goal_id\nuint8 status\nuint8 PENDING  = 0 # pending\n\
+                    uint8 ACTIVE=1\nuint8 PREEMPTED=2\nuint8 SUCCEEDED=3\nuint8 ABORTED=4\nuint8 REJECTED=5\n\
+                    uint8 PREEMPTING=6\nuint8 RECALLING=7\nuint8 RECALLED=8\nuint8 LOST=9\nstring text\n"),
+            ]),
+        ];
+        packages.push(Package {
+            name: "std_srvs".to_string(),
+            messages: vec![],
+            services: vec![parse_srv("std_srvs", "Trigger", "---\nbool success\nstring message\n").unwrap()],
+        });
+        let set = MsgSet::new(packages.as_slice()).unwrap();
+
+        let md5sum = |package: &str, name: &str| set.md5sum(&msg_name(package, name)).unwrap();
+        assert_eq!(md5sum("std_msgs", "Header").as_slice(), "2176decaecbce78abc3b96ef049fabed");
+        assert_eq!(md5sum("geometry_msgs", "Point").as_slice(), "4a842b65f413084dc2b10fb484ea7f17");
+        assert_eq!(md5sum("geometry_msgs", "PointStamped").as_slice(), "c63aecb41bfdfd6b7e1fac37c7cbe7bf");
+        assert_eq!(md5sum("actionlib_msgs", "GoalStatus").as_slice(), "d388f9b87b3c471f784434d671988d4a");
+        assert_eq!(set.srv_md5sum(&packages[3].services[0]), Some("937c9679a518e3a18d831e57125ea522".to_string()));
+        assert_eq!(set.md5sum(&msg_name("std_msgs", "Missing")), None);
+    }
+
+    #[test]
+    fn test_std_msgs() {
+        fn definition<M: RosMessage>() -> (&'static str, &'static str) {
+            (M::msg_type().split('/').last().unwrap(), M::msg_definition())
+        }
+        let definitions = [
+            definition::<std_msgs::Bool>(),
+            definition::<std_msgs::Empty>(),
+            definition::<std_msgs::Float64>(),
+            definition::<std_msgs::Header>(),
+            definition::<std_msgs::Int32>(),
+            definition::<std_msgs::String>(),
+        ];
+        let set = MsgSet::new(&[package("std_msgs", &definitions)]).unwrap();
+
+        fn check<M: RosMessage>(set: &MsgSet) {
+            let name = msg_name("std_msgs", M::msg_type().split('/').last().unwrap());
+            assert_eq!(set.md5sum(&name), Some(M::md5sum().to_string()));
+            assert_eq!(set.full_definition(&name), Some(M::msg_definition().to_string()));
+        }
+        check::<std_msgs::Bool>(&set);
+        check::<std_msgs::Empty>(&set);
+        check::<std_msgs::Float64>(&set);
+        check::<std_msgs::Header>(&set);
+        check::<std_msgs::Int32>(&set);
+        check::<std_msgs::String>(&set);
+    }
+
+    #[test]
+    fn test_full_definition() {
+        let packages = vec![
+            package("std_msgs", &[("Header", "uint32 seq\ntime stamp\nstring frame_id\n")]),
+            package("test_msgs", &[
+                ("Point", "float64 x\nfloat64 y\n"),
+                ("Polygon", "Header header\nPoint[] points\n"),
+                ("Polygons", "Polygon[] polygons\nPoint center\n"),
+            ]),
+        ];
+        let set = MsgSet::new(packages.as_slice()).unwrap();
+        assert_eq!(set.full_definition(&msg_name("test_msgs", "Point")), Some("float64 x\nfloat64 y\n".to_string()));
+        assert_eq!(set.full_definition(&msg_name("test_msgs", "Polygons")).unwrap(), format!(
+            "Polygon[] polygons\nPoint center\n\n\
+            {0}\nMSG: test_msgs/Polygon\nHeader header\nPoint[] points\n\n\
+            {0}\nMSG: std_msgs/Header\nuint32 seq\ntime stamp\nstring frame_id\n\n\
+            {0}\nMSG: test_msgs/Point\nfloat64 x\nfloat64 y\n", DEFINITION_SEPARATOR));
+    }
+}
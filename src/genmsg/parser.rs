@@ -0,0 +1,535 @@
+use std::fmt;
+use std::{i8, i16, i32, i64, u8, u16, u32, u64};
+
+/// Error from parsing a message or service definition. Lines and columns
+/// count from 1, and columns are in characters.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+/// The built-in types of the message definition language.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PrimitiveType {
+    Bool,
+    Int8,
+    UInt8,
+    Int16,
+    UInt16,
+    Int32,
+    UInt32,
+    Int64,
+    UInt64,
+    Float32,
+    Float64,
+    String,
+    Time,
+    Duration,
+    /// Deprecated alias for int8
+    Byte,
+    /// Deprecated alias for uint8
+    Char,
+}
+
+impl PrimitiveType {
+    pub fn from_name(name: &str) -> Option<PrimitiveType> {
+        match name {
+            "bool" => Some(PrimitiveType::Bool),
+            "int8" => Some(PrimitiveType::Int8),
+            "uint8" => Some(PrimitiveType::UInt8),
+            "int16" => Some(PrimitiveType::Int16),
+            "uint16" => Some(PrimitiveType::UInt16),
+            "int32" => Some(PrimitiveType::Int32),
+            "uint32" => Some(PrimitiveType::UInt32),
+            "int64" => Some(PrimitiveType::Int64),
+            "uint64" => Some(PrimitiveType::UInt64),
+            "float32" => Some(PrimitiveType::Float32),
+            "float64" => Some(PrimitiveType::Float64),
+            "string" => Some(PrimitiveType::String),
+            "time" => Some(PrimitiveType::Time),
+            "duration" => Some(PrimitiveType::Duration),
+            "byte" => Some(PrimitiveType::Byte),
+            "char" => Some(PrimitiveType::Char),
+            _ => None,
+        }
+    }
+
+    /// The name of the type, as written in definitions.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            PrimitiveType::Bool => "bool",
+            PrimitiveType::Int8 => "int8",
+            PrimitiveType::UInt8 => "uint8",
+            PrimitiveType::Int16 => "int16",
+            PrimitiveType::UInt16 => "uint16",
+            PrimitiveType::Int32 => "int32",
+            PrimitiveType::UInt32 => "uint32",
+            PrimitiveType::Int64 => "int64",
+            PrimitiveType::UInt64 => "uint64",
+            PrimitiveType::Float32 => "float32",
+            PrimitiveType::Float64 => "float64",
+            PrimitiveType::String => "string",
+            PrimitiveType::Time => "time",
+            PrimitiveType::Duration => "duration",
+            PrimitiveType::Byte => "byte",
+            PrimitiveType::Char => "char",
+        }
+    }
+}
+
+/// The full name of a message type, e.g. std_msgs/Header.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone)]
+pub struct MsgName {
+    pub package: String,
+    pub name: String,
+}
+
+impl fmt::Display for MsgName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.package, self.name)
+    }
+}
+
+/// The type of a field, or of the elements of an array field.
+#[derive(Debug, PartialEq, Clone)]
+pub enum BaseType {
+    Primitive(PrimitiveType),
+    /// Another message, with package-relative references resolved
+    Message(MsgName),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub enum ArrayLength {
+    /// T[], which is sent with its length
+    Variable,
+    /// T[N]
+    Fixed(usize),
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct Field {
+    pub name: String,
+    pub base_type: BaseType,
+    /// None unless the field is an array
+    pub array: Option<ArrayLength>,
+    /// Line of the definition the field is on
+    pub line: usize,
+}
+
+/// A constant, e.g. "int32 X=123". The value is the text of the value,
+/// which has been checked to be valid for the type.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Constant {
+    pub name: String,
+    pub constant_type: PrimitiveType,
+    pub value: String,
+    pub line: usize,
+}
+
+/// A parsed .msg file, or one half of a .srv file.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MsgSpec {
+    pub name: MsgName,
+    pub fields: Vec<Field>,
+    pub constants: Vec<Constant>,
+    /// The definition as written, comments and all
+    pub text: String,
+}
+
+impl MsgSpec {
+    /// The message types used by the fields, without duplicates, in the
+    /// order they are first used.
+    pub fn dependencies(&self) -> Vec<MsgName> {
+        let mut names: Vec<MsgName> = vec![];
+        for field in self.fields.iter() {
+            if let BaseType::Message(ref name) = field.base_type {
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+        }
+        names
+    }
+}
+
+/// A parsed .srv file. The request and response are named after the
+/// service, e.g. AddTwoIntsRequest and AddTwoIntsResponse.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SrvSpec {
+    pub name: MsgName,
+    pub request: MsgSpec,
+    pub response: MsgSpec,
+    pub text: String,
+}
+
+/// Line which separates the request and the response in a .srv file.
+const SRV_SEPARATOR: &'static str = "---";
+
+/// Parse the text of a .msg file. Message types without a package are
+/// taken to be in package, except for Header, which is std_msgs/Header.
+pub fn parse_msg(package: &str, name: &str, text: &str) -> Result<MsgSpec, ParseError> {
+    let lines: Vec<&str> = text.split('\n').collect();
+    parse_lines(package, name, lines.as_slice(), 1, text)
+}
+
+/// Parse the text of a .srv file, which is a request definition and a
+/// response definition separated by a "---" line.
+pub fn parse_srv(package: &str, name: &str, text: &str) -> Result<SrvSpec, ParseError> {
+    let lines: Vec<&str> = text.split('\n').collect();
+    let separators: Vec<usize> = lines.iter().enumerate()
+        .filter(|&(_, line)| line.trim() == SRV_SEPARATOR)
+        .map(|(index, _)| index)
+        .collect();
+    if separators.is_empty() {
+        return Err(ParseError {line: lines.len(), column: 1,
+            message: format!("Service has no {} line between the request and the response", SRV_SEPARATOR)});
+    }
+    if separators.len() > 1 {
+        return Err(ParseError {line: separators[1] + 1, column: 1,
+            message: format!("Service has more than one {} line", SRV_SEPARATOR)});
+    }
+    let separator = separators[0];
+
+    let request_lines = lines.slice_to(separator);
+    let response_lines = lines.slice_from(separator + 1);
+    let request_text = request_lines.connect("\n");
+    let response_text = response_lines.connect("\n");
+    let request = try!(parse_lines(package, format!("{}Request", name).as_slice(), request_lines, 1,
+        request_text.as_slice()));
+    let response = try!(parse_lines(package, format!("{}Response", name).as_slice(), response_lines,
+        separator + 2, response_text.as_slice()));
+    Ok(SrvSpec {
+        name: MsgName {package: package.to_string(), name: name.to_string()},
+        request: request,
+        response: response,
+        text: text.to_string(),
+    })
+}
+
+/// Whether a name is valid for a package, message, field or constant: a
+/// letter followed by letters, digits and underscores.
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some('a'...'z') | Some('A'...'Z') => (),
+        _ => return false,
+    }
+    chars.all(|c| match c {
+        'a'...'z' | 'A'...'Z' | '0'...'9' | '_' => true,
+        _ => false,
+    })
+}
+
+fn parse_lines(package: &str, name: &str, lines: &[&str], first_line: usize, text: &str)
+        -> Result<MsgSpec, ParseError> {
+    if !is_valid_name(package) {
+        return Err(ParseError {line: first_line, column: 1, message: format!("Bad package name {}", package)});
+    }
+    if !is_valid_name(name) {
+        return Err(ParseError {line: first_line, column: 1, message: format!("Bad message name {}", name)});
+    }
+    let mut spec = MsgSpec {
+        name: MsgName {package: package.to_string(), name: name.to_string()},
+        fields: vec![],
+        constants: vec![],
+        text: text.to_string(),
+    };
+    let mut names: Vec<String> = vec![];
+    for (index, line_text) in lines.iter().enumerate() {
+        let line = Line {number: first_line + index, text: *line_text};
+        let (name, name_offset) = match try!(line.parse(package)) {
+            None => continue,
+            Some(Item::Field(field, offset)) => {
+                let name = field.name.clone();
+                spec.fields.push(field);
+                (name, offset)
+            },
+            Some(Item::Constant(constant, offset)) => {
+                let name = constant.name.clone();
+                spec.constants.push(constant);
+                (name, offset)
+            },
+        };
+        if names.contains(&name) {
+            return Err(line.error(name_offset, format!("{} is defined more than once", name)));
+        }
+        names.push(name);
+    }
+    Ok(spec)
+}
+
+/// What a line defines, and the offset of its name.
+enum Item {
+    Field(Field, usize),
+    Constant(Constant, usize),
+}
+
+struct Line<'a> {
+    number: usize,
+    text: &'a str,
+}
+
+impl<'a> Line<'a> {
+    fn error(&self, offset: usize, message: String) -> ParseError {
+        ParseError {line: self.number, column: self.text.slice_to(offset).chars().count() + 1, message: message}
+    }
+
+    /// Parse the line, which is blank, a field or a constant.
+    fn parse(&self, package: &str) -> Result<Option<Item>, ParseError> {
+        let code = match self.text.find('#') {
+            Some(comment) => self.text.slice_to(comment),
+            None => self.text,
+        };
+        let (type_start, type_end) = match next_token(code, 0) {
+            Some(token) => token,
+            None => return Ok(None),
+        };
+        let type_name = code.slice(type_start, type_end);
+
+        if let Some(equals) = code.find('=') {
+            if equals < type_end {
+                return Err(self.error(equals, "Expected a constant of the form TYPE NAME=VALUE".to_string()));
+            }
+            return self.parse_constant(type_name, type_start, type_end, equals).map(Some);
+        }
+
+        let (base_type, array) = try!(self.parse_type(package, type_name, type_start));
+        let (name_start, name_end) = match next_token(code, type_end) {
+            Some(token) => token,
+            None => return Err(self.error(type_end, "Expected a field name after the type".to_string())),
+        };
+        let name = code.slice(name_start, name_end);
+        if !is_valid_name(name) {
+            return Err(self.error(name_start, format!("Bad field name {}", name)));
+        }
+        if let Some((extra, _)) = next_token(code, name_end) {
+            return Err(self.error(extra, "Unexpected text after the field name".to_string()));
+        }
+        let field = Field {name: name.to_string(), base_type: base_type, array: array, line: self.number};
+        Ok(Some(Item::Field(field, name_start)))
+    }
+
+    /// Parse "TYPE NAME=VALUE", given where the type and the = are.
+    fn parse_constant(&self, type_name: &str, type_start: usize, type_end: usize, equals: usize)
+            -> Result<Item, ParseError> {
+        let constant_type = match PrimitiveType::from_name(type_name) {
+            Some(PrimitiveType::Time) | Some(PrimitiveType::Duration) | None =>
+                return Err(self.error(type_start, format!("Constants can't be of type {}", type_name))),
+            Some(constant_type) => constant_type,
+        };
+
+        let name_text = self.text.slice(type_end, equals);
+        let name_start = type_end + name_text.len() - name_text.trim_left().len();
+        let name = name_text.trim();
+        if !is_valid_name(name) {
+            return Err(self.error(name_start, format!("Bad constant name {}", name)));
+        }
+
+        // Strings are everything after the =, including any #, so they
+        // can't have comments
+        let value_text = match (constant_type, self.text.find('#')) {
+            (PrimitiveType::String, _) | (_, None) => self.text.slice_from(equals + 1),
+            (_, Some(comment)) => self.text.slice(equals + 1, comment),
+        };
+        let value_start = equals + 1 + value_text.len() - value_text.trim_left().len();
+        let value = value_text.trim();
+        if let Err(err) = check_constant_value(constant_type, value) {
+            return Err(self.error(value_start, err));
+        }
+
+        let constant = Constant {
+            name: name.to_string(),
+            constant_type: constant_type,
+            value: value.to_string(),
+            line: self.number,
+        };
+        Ok(Item::Constant(constant, name_start))
+    }
+
+    /// Parse a field type, e.g. "int32", "Point[]" or "geometry_msgs/Point[3]".
+    fn parse_type(&self, package: &str, type_name: &str, offset: usize)
+            -> Result<(BaseType, Option<ArrayLength>), ParseError> {
+        let (base_name, array) = match type_name.find('[') {
+            None => (type_name, None),
+            Some(open) => {
+                if !type_name.ends_with("]") {
+                    return Err(self.error(offset + open, "Array type has no closing ]".to_string()));
+                }
+                let length = type_name.slice(open + 1, type_name.len() - 1);
+                let array = match length {
+                    "" => ArrayLength::Variable,
+                    _ => match length.parse() {
+                        Ok(length) => ArrayLength::Fixed(length),
+                        Err(_) => return Err(self.error(offset + open + 1,
+                            format!("Bad array length {}", length))),
+                    },
+                };
+                (type_name.slice_to(open), Some(array))
+            },
+        };
+
+        if let Some(primitive) = PrimitiveType::from_name(base_name) {
+            return Ok((BaseType::Primitive(primitive), array));
+        }
+        let name = match base_name.find('/') {
+            None if base_name == "Header" => MsgName {package: "std_msgs".to_string(), name: base_name.to_string()},
+            None => MsgName {package: package.to_string(), name: base_name.to_string()},
+            Some(slash) => MsgName {
+                package: base_name.slice_to(slash).to_string(),
+                name: base_name.slice_from(slash + 1).to_string(),
+            },
+        };
+        if !is_valid_name(name.package.as_slice()) || !is_valid_name(name.name.as_slice()) {
+            return Err(self.error(offset, format!("Bad type {}", base_name)));
+        }
+        Ok((BaseType::Message(name), array))
+    }
+}
+
+/// Find the next whitespace separated token at or after from, as the
+/// offsets of its start and end.
+fn next_token(text: &str, from: usize) -> Option<(usize, usize)> {
+    let rest = text.slice_from(from);
+    let start = match rest.find(|c: char| !c.is_whitespace()) {
+        Some(start) => from + start,
+        None => return None,
+    };
+    let end = match text.slice_from(start).find(|c: char| c.is_whitespace()) {
+        Some(length) => start + length,
+        None => text.len(),
+    };
+    Some((start, end))
+}
+
+/// Check that the value of a constant can be represented by its type.
+fn check_constant_value(constant_type: PrimitiveType, value: &str) -> Result<(), String> {
+    let (min, max): (i64, u64) = match constant_type {
+        PrimitiveType::String => return Ok(()),
+        PrimitiveType::Bool => return match value {
+            "true" | "false" | "True" | "False" | "0" | "1" => Ok(()),
+            _ => Err(format!("Bad bool constant {}", value)),
+        },
+        PrimitiveType::Float32 | PrimitiveType::Float64 => {
+            let parsed: Result<f64, _> = value.parse();
+            return match parsed {
+                Ok(_) => Ok(()),
+                Err(_) => Err(format!("Bad float constant {}", value)),
+            };
+        },
+        PrimitiveType::Int8 | PrimitiveType::Byte => (i8::MIN as i64, i8::MAX as u64),
+        PrimitiveType::UInt8 | PrimitiveType::Char => (0, u8::MAX as u64),
+        PrimitiveType::Int16 => (i16::MIN as i64, i16::MAX as u64),
+        PrimitiveType::UInt16 => (0, u16::MAX as u64),
+        PrimitiveType::Int32 => (i32::MIN as i64, i32::MAX as u64),
+        PrimitiveType::UInt32 => (0, u32::MAX as u64),
+        PrimitiveType::Int64 => (i64::MIN, i64::MAX as u64),
+        PrimitiveType::UInt64 => (0, u64::MAX),
+        PrimitiveType::Time | PrimitiveType::Duration =>
+            return Err(format!("Constants can't be of type {}", constant_type.name())),
+    };
+
+    let in_range = if value.starts_with("-") {
+        let parsed: Result<i64, _> = value.parse();
+        parsed.map(|x| x >= min)
+    } else {
+        let parsed: Result<u64, _> = value.parse();
+        parsed.map(|x| x <= max)
+    };
+    match in_range {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(format!("Constant {} is out of range for {}", value, constant_type.name())),
+        Err(_) => Err(format!("Bad integer constant {}", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_msg, parse_srv, ParseError, PrimitiveType, BaseType, ArrayLength, MsgName, Field,
+                Constant};
+
+    fn msg_name(package: &str, name: &str) -> MsgName {
+        MsgName {package: package.to_string(), name: name.to_string()}
+    }
+
+    fn error_at(text: &str) -> (usize, usize) {
+        let err: ParseError = parse_msg("test_msgs", "Test", text).unwrap_err();
+        (err.line, err.column)
+    }
+
+    #[test]
+    fn test_parse_msg() {
+        let text = "# A comment\n\
+                    Header header\n\
+                    int32 X = -5  # the x\n\
+                    string S=a # not a comment\n\
+                    \n  uint8[]  data\n\
+                    Point[3] points # comment\n\
+                    geometry_msgs/Pose pose\n";
+        let spec = parse_msg("test_msgs", "Test", text).unwrap();
+        assert_eq!(spec.name, msg_name("test_msgs", "Test"));
+        assert_eq!(spec.text.as_slice(), text);
+        assert_eq!(spec.constants, vec![
+            Constant {name: "X".to_string(), constant_type: PrimitiveType::Int32, value: "-5".to_string(), line: 3},
+            Constant {name: "S".to_string(), constant_type: PrimitiveType::String,
+                value: "a # not a comment".to_string(), line: 4},
+        ]);
+        assert_eq!(spec.fields, vec![
+            Field {name: "header".to_string(), base_type: BaseType::Message(msg_name("std_msgs", "Header")),
+                array: None, line: 2},
+            Field {name: "data".to_string(), base_type: BaseType::Primitive(PrimitiveType::UInt8),
+                array: Some(ArrayLength::Variable), line: 6},
+            Field {name: "points".to_string(), base_type: BaseType::Message(msg_name("test_msgs", "Point")),
+                array: Some(ArrayLength::Fixed(3)), line: 7},
+            Field {name: "pose".to_string(), base_type: BaseType::Message(msg_name("geometry_msgs", "Pose")),
+                array: None, line: 8},
+        ]);
+        assert_eq!(spec.dependencies(), vec![msg_name("std_msgs", "Header"), msg_name("test_msgs", "Point"),
+            msg_name("geometry_msgs", "Pose")]);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(error_at("int32 a\nint32\n"), (2, 6));
+        assert_eq!(error_at("int32 a b"), (1, 9));
+        assert_eq!(error_at("int32 1a"), (1, 7));
+        assert_eq!(error_at("int32[x] a"), (1, 7));
+        assert_eq!(error_at("int32[3 a"), (1, 6));
+        assert_eq!(error_at("  bad/type/name a"), (1, 3));
+        assert_eq!(error_at("int32 a\nfloat64 a"), (2, 9));
+        assert_eq!(error_at("uint8 X=256"), (1, 9));
+        assert_eq!(error_at("int8 X=-129"), (1, 8));
+        assert_eq!(error_at("float32 X= abc"), (1, 12));
+        assert_eq!(error_at("time T=1"), (1, 1));
+        assert_eq!(error_at("Point P=1"), (1, 1));
+        assert_eq!(error_at("=1"), (1, 1));
+        assert_eq!(format!("{}", parse_msg("test_msgs", "Test", "int32 a b").unwrap_err()),
+            "1:9: Unexpected text after the field name");
+    }
+
+    #[test]
+    fn test_parse_srv() {
+        let spec = parse_srv("test_msgs", "AddTwoInts", "int64 a\nint64 b\n---\nint64 sum\n").unwrap();
+        assert_eq!(spec.request.name, msg_name("test_msgs", "AddTwoIntsRequest"));
+        assert_eq!(spec.request.text.as_slice(), "int64 a\nint64 b");
+        assert_eq!(spec.request.fields.len(), 2);
+        assert_eq!(spec.response.name, msg_name("test_msgs", "AddTwoIntsResponse"));
+        assert_eq!(spec.response.text.as_slice(), "int64 sum\n");
+        assert_eq!(spec.response.fields[0].line, 4);
+
+        let spec = parse_srv("test_msgs", "Trigger", "---\n").unwrap();
+        assert!(spec.request.fields.is_empty() && spec.response.fields.is_empty());
+
+        let err = parse_srv("test_msgs", "Bad", "int64 a\n---\nint64 b\nint64 b\n").unwrap_err();
+        assert_eq!((err.line, err.column), (4, 7));
+        assert_eq!(parse_srv("test_msgs", "Bad", "int64 a\n").unwrap_err().line, 2);
+        assert_eq!(parse_srv("test_msgs", "Bad", "---\n---\n").unwrap_err().line, 2);
+    }
+}
@@ -0,0 +1,122 @@
+//! HTTP Basic authentication.
+
+const BASE64_CHARS: &'static [u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Value of the Authorization header for Basic authentication.
+pub fn basic_auth_value(username: &str, password: &str) -> String {
+    format!("Basic {}", base64_encode(format!("{}:{}", username, password).as_bytes()))
+}
+
+/// Check the value of a request's Authorization header against the expected
+/// credentials. Takes the same time wherever the first difference is, so
+/// that the password can't be guessed a character at a time.
+pub fn check_basic_auth(authorization: Option<&str>, username: &str, password: &str) -> bool {
+    let expected = basic_auth_value(username, password);
+    let actual = match authorization {
+        Some(a) => a,
+        None => return false,
+    };
+    if actual.len() != expected.len() {
+        return false;
+    }
+
+    let mut difference = 0u8;
+    for (a, b) in actual.bytes().zip(expected.bytes()) {
+        difference |= a ^ b;
+    }
+    difference == 0
+}
+
+/// Encode bytes as base64, as in RFC 4648.
+pub fn base64_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let b0 = chunk[0] as usize;
+        let b1 = if chunk.len() > 1 { chunk[1] as usize } else { 0 };
+        let b2 = if chunk.len() > 2 { chunk[2] as usize } else { 0 };
+
+        encoded.push(BASE64_CHARS[b0 >> 2] as char);
+        encoded.push(BASE64_CHARS[((b0 & 0x3) << 4) | (b1 >> 4)] as char);
+        encoded.push(match chunk.len() > 1 {
+            true => BASE64_CHARS[((b1 & 0xf) << 2) | (b2 >> 6)] as char,
+            false => '=',
+        });
+        encoded.push(match chunk.len() > 2 {
+            true => BASE64_CHARS[b2 & 0x3f] as char,
+            false => '=',
+        });
+    }
+    encoded
+}
+
+/// Decode base64 text, ignoring whitespace.
+pub fn base64_decode(text: &str) -> Result<Vec<u8>, String> {
+    let mut values = vec![];
+    let mut padding = 0;
+    for c in text.bytes() {
+        match c {
+            b' ' | b'\t' | b'\r' | b'\n' => continue,
+            b'=' => padding += 1,
+            _ if padding > 0 => return Err("Base64 padding in the middle of the text".to_string()),
+            _ => match BASE64_CHARS.iter().position(|x| *x == c) {
+                Some(value) => values.push(value),
+                None => return Err(format!("Bad base64 character {:?}", c as char)),
+            },
+        }
+    }
+    // Padding is optional, but must be right if it's there
+    if values.len() % 4 == 1 || padding > 2 || (padding > 0 && (values.len() + padding) % 4 != 0) {
+        return Err("Base64 text has a bad length".to_string());
+    }
+
+    let mut bytes = vec![];
+    for chunk in values.chunks(4) {
+        bytes.push(((chunk[0] << 2) | (chunk[1] >> 4)) as u8);
+        if chunk.len() > 2 {
+            bytes.push((((chunk[1] & 0xf) << 4) | (chunk[2] >> 2)) as u8);
+        }
+        if chunk.len() > 3 {
+            bytes.push((((chunk[2] & 0x3) << 6) | chunk[3]) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{base64_encode, base64_decode, basic_auth_value, check_basic_auth};
+
+    #[test]
+    fn test_base64_encode() {
+        // Test vectors from RFC 4648
+        assert_eq!(base64_encode(b"").as_slice(), "");
+        assert_eq!(base64_encode(b"f").as_slice(), "Zg==");
+        assert_eq!(base64_encode(b"fo").as_slice(), "Zm8=");
+        assert_eq!(base64_encode(b"foo").as_slice(), "Zm9v");
+        assert_eq!(base64_encode(b"foob").as_slice(), "Zm9vYg==");
+        assert_eq!(base64_encode(b"fooba").as_slice(), "Zm9vYmE=");
+        assert_eq!(base64_encode(b"foobar").as_slice(), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn test_base64_decode() {
+        assert_eq!(base64_decode(""), Ok(vec![]));
+        assert_eq!(base64_decode("Zg=="), Ok(b"f".to_vec()));
+        assert_eq!(base64_decode("Zm8="), Ok(b"fo".to_vec()));
+        assert_eq!(base64_decode("Zm9v\nYmFy"), Ok(b"foobar".to_vec()));
+        assert_eq!(base64_decode("Zm9vYg"), Ok(b"foob".to_vec()));
+        assert!(base64_decode("Zm9vY").is_err());
+        assert!(base64_decode("Zg==Zg==").is_err());
+        assert!(base64_decode("Zm9*").is_err());
+    }
+
+    #[test]
+    fn test_basic_auth() {
+        assert_eq!(basic_auth_value("Aladdin", "open sesame").as_slice(),
+            "Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ==");
+
+        assert!(check_basic_auth(Some("Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="), "Aladdin", "open sesame"));
+        assert!(!check_basic_auth(Some("Basic QWxhZGRpbjpvcGVuIHNlc2FtZR=="), "Aladdin", "open sesame"));
+        assert!(!check_basic_auth(None, "Aladdin", "open sesame"));
+    }
+}
@@ -0,0 +1,249 @@
+//! Event loop which reads HTTP requests from many connections at once.
+//!
+//! The accept thread hands each new connection to the event loop, which uses
+//! poll() to wait until any of its connections has data. Once a connection
+//! has sent a complete request (or an invalid one, or has taken too long),
+//! it is handed off to the worker threads which run the request handler and
+//! write the response. This way a client which is slow to send its request
+//! only costs a file descriptor, instead of tying up a worker thread.
+
+use libc;
+use libc::{c_int, c_short, c_void, size_t};
+use std::cmp;
+use std::old_io::TcpStream;
+use std::old_io::timer::sleep;
+use std::os;
+use std::os::unix::{AsRawFd, Fd};
+use std::sync::Arc;
+use std::sync::mpsc::{Receiver, Sender, TryRecvError};
+use std::time::Duration;
+use time;
+use http::RequestHeader;
+use http::server::{ServerConfig, RequestParser, RequestError, Job, InFlightCount, change_in_flight_count};
+
+#[repr(C)]
+struct PollFd {
+    fd: c_int,
+    events: c_short,
+    revents: c_short,
+}
+
+#[cfg(target_os = "linux")]
+type NumFds = libc::c_ulong;
+#[cfg(not(target_os = "linux"))]
+type NumFds = libc::c_uint;
+
+const POLLIN: c_short = 0x1;
+
+/// Longest time to wait before calling poll() again after it fails.
+const MAX_POLL_BACKOFF_MS: i64 = 1000;
+
+extern {
+    fn poll(fds: *mut PollFd, nfds: NumFds, timeout: c_int) -> c_int;
+}
+
+/// Wakes the event loop up from poll(), e.g. when the accept thread has a
+/// new connection for it. Implemented with a pipe which the event loop polls.
+pub struct Waker {
+    read_fd: Fd,
+    write_fd: Fd,
+}
+
+impl Waker {
+    pub fn new() -> Result<Waker, String> {
+        let mut fds = [0 as c_int; 2];
+        if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+            return Err(format!("Failed to create pipe for event loop: {}", os::last_os_error()));
+        }
+        let waker = Waker {read_fd: fds[0], write_fd: fds[1]};
+        try!(set_nonblocking(waker.read_fd, true));
+        try!(set_nonblocking(waker.write_fd, true));
+        Ok(waker)
+    }
+
+    pub fn wake(&self) {
+        // If the pipe is full the event loop is going to wake up anyway, so
+        // there's no need to check whether this worked.
+        let b = [1u8];
+        unsafe { libc::write(self.write_fd, b.as_ptr() as *const c_void, 1); }
+    }
+
+    /// Empty the pipe so that the next poll() doesn't return immediately.
+    fn clear(&self) {
+        let mut buf = [0u8; 64];
+        loop {
+            let n = unsafe {
+                libc::read(self.read_fd, buf.as_mut_ptr() as *mut c_void, buf.len() as size_t)
+            };
+            if n <= 0 {
+                break;
+            }
+        }
+    }
+}
+
+impl Drop for Waker {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.read_fd);
+            libc::close(self.write_fd);
+        }
+    }
+}
+
+/// Turn O_NONBLOCK on or off for a file descriptor.
+pub fn set_nonblocking(fd: Fd, nonblocking: bool) -> Result<(), String> {
+    unsafe {
+        let flags = libc::fcntl(fd, libc::F_GETFL);
+        if flags < 0 {
+            return Err(format!("Failed to get file descriptor flags: {}", os::last_os_error()));
+        }
+        let new_flags = match nonblocking {
+            true => flags | libc::O_NONBLOCK,
+            false => flags & !libc::O_NONBLOCK,
+        };
+        if libc::fcntl(fd, libc::F_SETFL, new_flags) < 0 {
+            return Err(format!("Failed to set file descriptor flags: {}", os::last_os_error()));
+        }
+    }
+    Ok(())
+}
+
+/// A connection which is still sending its request.
+struct Connection {
+    stream: TcpStream,
+    parser: RequestParser,
+    deadline_ns: u64,
+}
+
+impl Connection {
+    /// Read whatever the client has sent so far. Returns the request if it is
+    /// now complete.
+    fn read_available(&mut self) -> Result<Option<(RequestHeader, String)>, RequestError> {
+        let mut buf = [0u8; 4096];
+        loop {
+            let n = unsafe {
+                libc::read(self.stream.as_raw_fd(), buf.as_mut_ptr() as *mut c_void, buf.len() as size_t)
+            };
+            if n > 0 {
+                match try!(self.parser.feed(&buf[..n as usize])) {
+                    Some(request) => return Ok(Some(request)),
+                    None => {},
+                };
+            } else if n == 0 {
+                return Err(RequestError::new(400, "Connection closed before request was complete"));
+            } else {
+                let errno = os::errno() as c_int;
+                if errno == libc::EAGAIN || errno == libc::EWOULDBLOCK {
+                    return Ok(None);
+                } else if errno != libc::EINTR {
+                    return Err(RequestError::new(400, format!(
+                        "Failed to read request from stream: {}", os::last_os_error()).as_slice()));
+                }
+            }
+        }
+    }
+}
+
+/// Read requests from connections as they arrive on new_streams, and send
+/// them to the workers as jobs. Returns once new_streams has been closed and
+/// all of the connections have been handed off. Connections which can't be
+/// handed off are dropped, and no longer counted in in_flight.
+pub fn run_event_loop(
+    new_streams: Receiver<TcpStream>,
+    waker: Arc<Waker>,
+    config: ServerConfig,
+    jobs: Sender<Job>,
+    in_flight: InFlightCount)
+{
+    let mut connections: Vec<Connection> = vec![];
+    let mut accepting = true;
+    // Number of times in a row that poll() has failed
+    let mut poll_failures = 0usize;
+
+    while accepting || !connections.is_empty() {
+        let mut poll_fds = vec![PollFd {fd: waker.read_fd, events: POLLIN, revents: 0}];
+        for connection in connections.iter() {
+            poll_fds.push(PollFd {fd: connection.stream.as_raw_fd(), events: POLLIN, revents: 0});
+        }
+
+        let timeout_ms = poll_timeout_ms(connections.as_slice());
+        let num_ready = unsafe { poll(poll_fds.as_mut_ptr(), poll_fds.len() as NumFds, timeout_ms) };
+        if num_ready >= 0 {
+            poll_failures = 0;
+        } else if os::errno() as c_int != libc::EINTR {
+            // Errors like ENOMEM are likely to happen again straight away,
+            // so back off rather than spinning. No revents are set, but
+            // connections which have run out of time are still handed off.
+            warn!("poll() failed in HTTP event loop: {}", os::last_os_error());
+            poll_failures = cmp::min(poll_failures + 1, 10);
+            sleep(Duration::milliseconds(cmp::min(1i64 << poll_failures, MAX_POLL_BACKOFF_MS)));
+        }
+
+        // Read from the connections that have data, and hand off any which
+        // are finished to the workers.
+        let now_ns = time::precise_time_ns();
+        let mut still_reading = vec![];
+        for (mut connection, poll_fd) in connections.into_iter().zip(poll_fds[1..].iter()) {
+            let result = match poll_fd.revents {
+                0 => Ok(None),
+                _ => connection.read_available(),
+            };
+            let result = match result {
+                Ok(None) if now_ns >= connection.deadline_ns =>
+                    Err(RequestError::new(408, "Timed out reading request")),
+                other => other,
+            };
+
+            let job = match result {
+                Ok(None) => {
+                    still_reading.push(connection);
+                    continue;
+                },
+                Ok(Some((header, body))) => Job::Request(connection.stream, header, body),
+                Err(err) => Job::Error(connection.stream, err),
+            };
+            if jobs.send(job).is_err() {
+                warn!("HTTP workers have exited; dropping connection");
+                change_in_flight_count(&in_flight, -1);
+            }
+        }
+        connections = still_reading;
+
+        // Pick up any new connections from the accept thread
+        if poll_fds[0].revents != 0 {
+            waker.clear();
+        }
+        loop {
+            match new_streams.try_recv() {
+                Ok(stream) => {
+                    match set_nonblocking(stream.as_raw_fd(), true) {
+                        Ok(_) => (),
+                        Err(err) => {warn!("{}", err);},
+                    };
+                    connections.push(Connection {
+                        stream: stream,
+                        parser: RequestParser::new(&config),
+                        deadline_ns: now_ns + config.request_timeout_ms * 1000000,
+                    });
+                },
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    accepting = false;
+                    break;
+                },
+            };
+        }
+    }
+}
+
+/// How long poll() can wait before the next connection's deadline expires.
+fn poll_timeout_ms(connections: &[Connection]) -> c_int {
+    let now_ns = time::precise_time_ns();
+    match connections.iter().map(|c| c.deadline_ns).min() {
+        None => -1,
+        Some(deadline_ns) if deadline_ns <= now_ns => 0,
+        // Round up, so that we don't wake up just before the deadline
+        Some(deadline_ns) => ((deadline_ns - now_ns + 999999) / 1000000) as c_int,
+    }
+}
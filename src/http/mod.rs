@@ -1,20 +1,31 @@
-pub use self::server::{run_http_server, HandlesHttpRequests};
-pub use self::client::post;
+pub use self::server::{run_http_server, HandlesHttpRequests, ServerConfig, ServerHandle, ShutdownTrigger};
+pub use self::client::{post, post_with_config, ClientConfig};
+pub use self::router::Router;
+pub use self::url::Url;
+pub use self::tls::TlsServerConfig;
+pub use self::auth::{base64_encode, base64_decode};
 
 mod server;
 mod client;
+mod router;
+mod event_loop;
+mod url;
+mod auth;
+mod tls;
 
 #[derive(Debug, PartialEq, Copy)]
 pub struct ResponseHeader {
-    status: i32,
-    content_length: isize,
+    pub status: i32,
+    pub content_length: isize,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct RequestHeader {
-    method: String,
-    request_uri: String,
-    http_version: String,
-    content_length: i32,
+    pub method: String,
+    pub request_uri: String,
+    pub http_version: String,
+    pub content_length: i32,
+    /// Value of the Authorization field, if there was one.
+    pub authorization: Option<String>,
 }
 
@@ -0,0 +1,198 @@
+use std::sync::Arc;
+use http::{HandlesHttpRequests, RequestHeader};
+
+/// Object safe version of HandlesHttpRequests, so that handlers of different
+/// types can be stored in the same router.
+trait RouteHandler: Send + Sync {
+    fn handle_route(&self, header: &RequestHeader, body: &str) -> (i32, Vec<(String, String)>, String);
+}
+
+impl<H: HandlesHttpRequests> RouteHandler for H {
+    fn handle_route(&self, header: &RequestHeader, body: &str) -> (i32, Vec<(String, String)>, String) {
+        self.handle_request_with_headers(header, body)
+    }
+}
+
+#[derive(Clone)]
+struct Route {
+    method: Option<String>,
+    path_prefix: String,
+    handler: Arc<Box<RouteHandler + Send + Sync>>,
+}
+
+/// Dispatches HTTP requests to one of several handlers, based on the request
+/// method and path. This lets a single port serve e.g. XMLRPC on /RPC2 and
+/// diagnostics pages on other paths.
+///
+/// Each request goes to the route with the longest path prefix that matches
+/// the request path and accepts the request method. If some route matches
+/// the path but none accept the method, the response is a 405 with an Allow
+/// field listing the methods that are accepted; if no route matches the
+/// path at all the response is a 404.
+#[derive(Clone)]
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router {routes: vec![]}
+    }
+
+    /// Send requests with any method to handler if their path is inside of
+    /// path_prefix.
+    pub fn add_route<H: HandlesHttpRequests>(&mut self, path_prefix: &str, handler: H) {
+        self.routes.push(Route {
+            method: None,
+            path_prefix: path_prefix.to_string(),
+            handler: Arc::new(Box::new(handler) as Box<RouteHandler + Send + Sync>),
+        });
+    }
+
+    /// Send requests with the given method (e.g. "POST") to handler if their
+    /// path is inside of path_prefix.
+    pub fn add_method_route<H: HandlesHttpRequests>(
+        &mut self, method: &str, path_prefix: &str, handler: H) {
+        self.routes.push(Route {
+            method: Some(method.to_string()),
+            path_prefix: path_prefix.to_string(),
+            handler: Arc::new(Box::new(handler) as Box<RouteHandler + Send + Sync>),
+        });
+    }
+}
+
+impl HandlesHttpRequests for Router {
+    fn handle_request(&self, header: &RequestHeader, body: &str) -> (i32, String) {
+        let (status, _, body) = self.handle_request_with_headers(header, body);
+        (status, body)
+    }
+
+    fn handle_request_with_headers(&self, header: &RequestHeader, body: &str)
+            -> (i32, Vec<(String, String)>, String) {
+        let path = request_path(header.request_uri.as_slice());
+
+        let mut path_matched = false;
+        // Methods of the routes which match the path, for the Allow field
+        let mut allowed_methods: Vec<&str> = vec![];
+        let mut best_route: Option<&Route> = None;
+        for route in self.routes.iter() {
+            if !path_matches(route.path_prefix.as_slice(), path) {
+                continue;
+            }
+            path_matched = true;
+
+            match route.method {
+                Some(ref method) if *method != header.method => {
+                    if !allowed_methods.contains(&method.as_slice()) {
+                        allowed_methods.push(method.as_slice());
+                    }
+                    continue;
+                },
+                _ => {},
+            };
+
+            best_route = match best_route {
+                Some(best) if best.path_prefix.len() >= route.path_prefix.len() => Some(best),
+                _ => Some(route),
+            };
+        }
+
+        match best_route {
+            Some(route) => route.handler.handle_route(header, body),
+            None if path_matched => (405, vec![("Allow".to_string(), allowed_methods.connect(", "))],
+                format!("Method {} not allowed for {}", header.method, path)),
+            None => (404, vec![], format!("No handler for {}", path)),
+        }
+    }
+}
+
+/// Strip any query string from a request URI.
+fn request_path(request_uri: &str) -> &str {
+    match request_uri.find('?') {
+        Some(i) => &request_uri[..i],
+        None => request_uri,
+    }
+}
+
+/// Check whether path is inside of path_prefix. "/metrics/cpu" is inside of
+/// "/metrics", but "/metricsfoo" is not.
+fn path_matches(path_prefix: &str, path: &str) -> bool {
+    if !path.starts_with(path_prefix) {
+        return false;
+    }
+    path_prefix.ends_with("/")
+        || path.len() == path_prefix.len()
+        || path.as_bytes()[path_prefix.len()] == b'/'
+}
+
+#[cfg(test)]
+mod tests {
+    use http::{HandlesHttpRequests, RequestHeader};
+    use super::Router;
+
+    #[derive(Clone)]
+    struct NamedHandler {
+        name: String,
+    }
+
+    impl HandlesHttpRequests for NamedHandler {
+        fn handle_request(&self, _: &RequestHeader, _: &str) -> (i32, String) {
+            (200, self.name.clone())
+        }
+    }
+
+    fn make_router() -> Router {
+        let mut router = Router::new();
+        router.add_route("/", NamedHandler {name: "status".to_string()});
+        router.add_method_route("POST", "/RPC2", NamedHandler {name: "xmlrpc".to_string()});
+        router.add_method_route("GET", "/metrics", NamedHandler {name: "metrics".to_string()});
+        router
+    }
+
+    fn make_header(method: &str, request_uri: &str) -> RequestHeader {
+        RequestHeader {
+            method: method.to_string(),
+            request_uri: request_uri.to_string(),
+            http_version: "HTTP/1.0".to_string(),
+            content_length: 0,
+            authorization: None,
+        }
+    }
+
+    #[test]
+    fn test_route_longest_prefix() {
+        let router = make_router();
+        assert_eq!(router.handle_request(&make_header("POST", "/RPC2"), ""),
+            (200, "xmlrpc".to_string()));
+        assert_eq!(router.handle_request(&make_header("GET", "/metrics/cpu?verbose=1"), ""),
+            (200, "metrics".to_string()));
+        assert_eq!(router.handle_request(&make_header("GET", "/metricsfoo"), ""),
+            (200, "status".to_string()));
+        assert_eq!(router.handle_request(&make_header("GET", "/"), ""),
+            (200, "status".to_string()));
+    }
+
+    #[test]
+    fn test_route_method_fallback() {
+        // A GET to /RPC2 isn't accepted by the XMLRPC route, so falls back to "/"
+        let router = make_router();
+        assert_eq!(router.handle_request(&make_header("GET", "/RPC2"), ""),
+            (200, "status".to_string()));
+    }
+
+    #[test]
+    fn test_route_not_found() {
+        let mut router = Router::new();
+        router.add_method_route("POST", "/RPC2", NamedHandler {name: "xmlrpc".to_string()});
+        router.add_method_route("PUT", "/RPC2", NamedHandler {name: "xmlrpc".to_string()});
+
+        match router.handle_request_with_headers(&make_header("GET", "/RPC2"), "") {
+            (405, headers, _) => assert_eq!(headers, vec![("Allow".to_string(), "POST, PUT".to_string())]),
+            x => panic!("Expected 405, got {:?}", x),
+        };
+        match router.handle_request(&make_header("POST", "/foo"), "") {
+            (404, _) => {},
+            x => panic!("Expected 404, got {:?}", x),
+        };
+    }
+}
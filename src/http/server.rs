@@ -1,88 +1,627 @@
-use std::old_io::{TcpListener, TcpStream};
+use std::ascii::AsciiExt;
+use std::old_io::{TcpListener, TcpStream, TcpAcceptor, IoError, IoErrorKind};
 use std::old_io::{Acceptor, Listener};
+use std::old_io::net::ip::SocketAddr;
+use std::os::unix::AsRawFd;
+use std::str;
+use std::sync::{Arc, Mutex, Condvar};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use time;
 use http::RequestHeader;
-use std::sync::TaskPool;
+use http::auth;
+use http::event_loop::{self, Waker};
+use http::tls::{self, TlsServerConfig};
 
+/// Settings for an HTTP server.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    /// Number of threads running the request handler.
+    pub num_threads: usize,
+    /// Largest request header, in bytes, that will be accepted.
+    pub max_header_bytes: usize,
+    /// Largest request body, in bytes, that will be accepted.
+    pub max_body_bytes: usize,
+    /// Time that a client has to send its complete request after connecting,
+    /// in milliseconds. Also used as the timeout for writing the response.
+    pub request_timeout_ms: u64,
+    /// If set, connections use TLS with this certificate. TLS connections are
+    /// read by the worker threads rather than the event loop, so slow clients
+    /// can tie up a worker for up to request_timeout_ms.
+    pub tls: Option<TlsServerConfig>,
+    /// If set, requests must use HTTP Basic authentication with this
+    /// username and password, or get a 401 response.
+    pub basic_auth: Option<(String, String)>,
+    /// Whether requests are read by the event loop. If not, each worker
+    /// reads its connection's request itself, as TLS connections always
+    /// are, and slow clients can tie up all of the workers. This is mostly
+    /// useful for comparing the two designs.
+    pub use_event_loop: bool,
+}
+
+impl ServerConfig {
+    /// Default limits, with the given number of request handling threads.
+    pub fn new(num_threads: usize) -> ServerConfig {
+        ServerConfig {
+            num_threads: num_threads,
+            max_header_bytes: 16 * 1024,
+            max_body_bytes: 16 * 1024 * 1024,
+            request_timeout_ms: 10000,
+            tls: None,
+            basic_auth: None,
+            use_event_loop: true,
+        }
+    }
+}
+
+/// An error reading a request, along with the HTTP status code that should
+/// be sent back to the client.
+#[derive(Debug, PartialEq)]
+pub struct RequestError {
+    pub status: i32,
+    pub message: String,
+}
+
+impl RequestError {
+    pub fn new(status: i32, message: &str) -> RequestError {
+        RequestError {status: status, message: message.to_string()}
+    }
+}
+
+/// Work handed from the event loop to the worker threads.
+pub enum Job {
+    /// A complete request, to be passed to the request handler.
+    Request(TcpStream, RequestHeader, String),
+    /// A connection which didn't send a valid request in time, along with the
+    /// error to send back to it.
+    Error(TcpStream, RequestError),
+    /// A connection which the worker has to read the request from itself,
+    /// because it uses TLS or the event loop is turned off.
+    Connection(TcpStream),
+}
+
+/// Number of connections which have been accepted but not yet responded to,
+/// along with a condition variable which is notified when it changes.
+pub type InFlightCount = Arc<(Mutex<usize>, Condvar)>;
+
+/// Start an HTTP server on a listener, and return a handle to it.
+///
+/// Connections are accepted on a background thread and passed to an event
+/// loop, which reads requests from all of them at once. Complete requests
+/// are then handled by a fixed number of worker threads, so slow clients
+/// don't keep the workers from serving other requests. Requests which exceed
+/// the limits in config are rejected with a 400, 408 or 413 response.
+/// With config.use_event_loop turned off, each worker reads its own
+/// connection's request instead.
+///
+/// To have the system choose a free port, bind the listener to port 0 and
+/// then ask the returned handle for its socket_addr().
 pub fn run_http_server<H: HandlesHttpRequests>(
     listener: TcpListener,
-    num_threads: usize,
+    config: ServerConfig,
     request_handler: H,
-    ) -> Result<(), String> {
-    let pool = TaskPool::new(num_threads);
-
+    ) -> Result<ServerHandle, String> {
     let mut acceptor = match listener.listen() {
         Ok(x) => x,
         Err(_) => return Err("Failed to create connection acceptor".to_string()),
     };
+    let socket_addr = match acceptor.socket_name() {
+        Ok(x) => x,
+        Err(_) => return Err("Failed to get address of connection acceptor".to_string()),
+    };
+    let waker = Arc::new(try!(Waker::new()));
+
+    let shutting_down = Arc::new(AtomicBool::new(false));
+    let in_flight = Arc::new((Mutex::new(0us), Condvar::new()));
+    let (stream_tx, stream_rx) = channel();
+    let (job_tx, job_rx) = channel();
+    let job_rx = Arc::new(Mutex::new(job_rx));
+
+    let mut worker_threads = vec![];
+    for _ in 0..config.num_threads {
+        let job_rx = job_rx.clone();
+        let config = config.clone();
+        let handler = request_handler.clone();
+        let in_flight = in_flight.clone();
+        worker_threads.push(thread::spawn(move || {
+            supervise_worker(job_rx, config, handler, in_flight);
+        }));
+    }
+
+    // TLS connections skip the event loop and go straight to the workers
+    let connection_job_tx = match (&config.tls, config.use_event_loop) {
+        (&None, true) => None,
+        _ => Some(job_tx.clone()),
+    };
+
+    let event_loop_thread = {
+        let waker = waker.clone();
+        let config = config.clone();
+        let in_flight = in_flight.clone();
+        thread::spawn(move || {
+            // Dropping job_tx when the event loop exits lets the workers exit
+            // once they have handled all of the queued jobs.
+            event_loop::run_event_loop(stream_rx, waker, config, job_tx, in_flight);
+        })
+    };
+
+    let accept_thread = {
+        let mut acceptor = acceptor.clone();
+        let shutting_down = shutting_down.clone();
+        let in_flight = in_flight.clone();
+        thread::spawn(move || {
+            for stream in acceptor.incoming() {
+                if shutting_down.load(Ordering::SeqCst) {
+                    break;
+                }
+                match stream {
+                    Err(err) => {
+                        if err.kind == IoErrorKind::EndOfFile {
+                            break;
+                        }
+                        warn!("Failed to accept HTTP connection: {}", err);
+                    },
+                    Ok(stream) => {
+                        debug!("Got HTTP connection");
+                        change_in_flight_count(&in_flight, 1);
+                        let sent = match connection_job_tx {
+                            Some(ref job_tx) => job_tx.send(Job::Connection(stream)).is_ok(),
+                            None => stream_tx.send(stream).is_ok(),
+                        };
+                        if !sent {
+                            change_in_flight_count(&in_flight, -1);
+                            break;
+                        }
+                        waker.wake();
+                    },
+                };
+            };
+
+            // Let the event loop know that there won't be any more connections
+            drop(stream_tx);
+            drop(connection_job_tx);
+            waker.wake();
+        })
+    };
+
+    Ok(ServerHandle {
+        socket_addr: socket_addr,
+        trigger: ShutdownTrigger {acceptor: acceptor, shutting_down: shutting_down},
+        in_flight: in_flight,
+        accept_thread: Some(accept_thread),
+        event_loop_thread: Some(event_loop_thread),
+        worker_threads: worker_threads,
+    })
+}
+
+/// Handle to a running HTTP server.
+pub struct ServerHandle {
+    socket_addr: SocketAddr,
+    trigger: ShutdownTrigger,
+    in_flight: InFlightCount,
+    accept_thread: Option<JoinHandle>,
+    event_loop_thread: Option<JoinHandle>,
+    worker_threads: Vec<JoinHandle>,
+}
+
+impl ServerHandle {
+    /// Address that the server is listening on.
+    pub fn socket_addr(&self) -> SocketAddr {
+        self.socket_addr
+    }
+
+    /// Get a trigger which can be used to stop the server from another thread.
+    pub fn shutdown_trigger(&self) -> ShutdownTrigger {
+        self.trigger.clone()
+    }
+
+    /// Stop accepting new connections. Requests which have already been
+    /// accepted will still be handled.
+    pub fn stop_accepting(&mut self) {
+        self.trigger.trigger();
+    }
+
+    /// Stop accepting new connections, wait up to timeout for requests that
+    /// are in progress to finish, and join all of the server's threads.
+    ///
+    /// If requests are still in progress when the timeout expires, returns an
+    /// error and leaves the worker threads to finish in the background.
+    pub fn shutdown(mut self, timeout: Duration) -> Result<(), String> {
+        self.stop_accepting();
+        self.join_accept_thread();
+
+        let deadline_ns = time::precise_time_ns() + timeout.num_nanoseconds().unwrap_or(0) as u64;
+        let &(ref count_mutex, ref count_changed) = &*self.in_flight;
+        let mut count = count_mutex.lock().unwrap();
+        while *count > 0 {
+            let now_ns = time::precise_time_ns();
+            if now_ns >= deadline_ns {
+                return Err(format!("{} requests still in progress after shutdown timeout", *count));
+            }
+            let remaining = Duration::nanoseconds((deadline_ns - now_ns) as i64);
+            count = count_changed.wait_timeout(count, remaining).unwrap().0;
+        }
+        drop(count);
+
+        self.join_worker_threads()
+    }
+
+    /// Block until the server is stopped by a ShutdownTrigger, and then wait
+    /// for all in progress requests to finish.
+    pub fn wait(mut self) -> Result<(), String> {
+        self.join_accept_thread();
+        self.join_worker_threads()
+    }
+
+    fn join_accept_thread(&mut self) {
+        match self.accept_thread.take() {
+            Some(t) => {
+                if t.join().is_err() {
+                    warn!("HTTP accept thread panicked");
+                }
+            },
+            None => {},
+        };
+    }
+
+    fn join_worker_threads(&mut self) -> Result<(), String> {
+        match self.event_loop_thread.take() {
+            Some(t) => {
+                if t.join().is_err() {
+                    return Err("HTTP event loop thread panicked".to_string());
+                }
+            },
+            None => {},
+        };
+
+        let mut num_panicked = 0;
+        for t in self.worker_threads.drain() {
+            if t.join().is_err() {
+                num_panicked += 1;
+            }
+        }
+        match num_panicked {
+            0 => Ok(()),
+            n => Err(format!("{} HTTP worker threads panicked", n)),
+        }
+    }
+}
+
+/// Stops an HTTP server from accepting new connections. Can be cloned and
+/// sent to other threads, e.g. so that a request handler can shut down the
+/// server that it is running in.
+#[derive(Clone)]
+pub struct ShutdownTrigger {
+    acceptor: TcpAcceptor,
+    shutting_down: Arc<AtomicBool>,
+}
+
+impl ShutdownTrigger {
+    pub fn trigger(&mut self) {
+        self.shutting_down.store(true, Ordering::SeqCst);
+        match self.acceptor.close_accept() {
+            Ok(_) => (),
+            Err(err) => {warn!("Failed to close HTTP acceptor: {}", err);},
+        };
+    }
+}
+
+pub fn change_in_flight_count(in_flight: &InFlightCount, change: isize) {
+    let &(ref count_mutex, ref count_changed) = &**in_flight;
+    let mut count = count_mutex.lock().unwrap();
+    *count = (*count as isize + change) as usize;
+    count_changed.notify_all();
+}
 
-    for stream in acceptor.incoming() {
-        debug!("Got HTTP connection");
-        let handler_clone = request_handler.clone();
-        match stream {
-            Err(_) => {},
-            Ok(stream) => pool.execute(move || {
-                handle_incoming_request(stream, handler_clone);
-            }),
+/// Run a worker, and start it again whenever the request handler panics,
+/// so that panics don't use up the pool of workers.
+fn supervise_worker<H: HandlesHttpRequests>(
+    jobs: Arc<Mutex<Receiver<Job>>>,
+    config: ServerConfig,
+    request_handler: H,
+    in_flight: InFlightCount)
+{
+    loop {
+        let worker = {
+            let jobs = jobs.clone();
+            let config = config.clone();
+            let request_handler = request_handler.clone();
+            let in_flight = in_flight.clone();
+            thread::spawn(move || run_worker(jobs, config, request_handler, in_flight))
         };
+        match worker.join() {
+            Ok(_) => return,
+            Err(_) => warn!("HTTP request handler panicked; restarting worker"),
+        };
+    }
+}
+
+/// Handle jobs from the event loop until it shuts down and the queue is empty.
+fn run_worker<H: HandlesHttpRequests>(
+    jobs: Arc<Mutex<Receiver<Job>>>,
+    config: ServerConfig,
+    request_handler: H,
+    in_flight: InFlightCount)
+{
+    loop {
+        let job = match jobs.lock().unwrap().recv() {
+            Ok(job) => job,
+            Err(_) => break,
+        };
+        let _guard = JobGuard {stream: panic_stream(&job, &config), in_flight: &in_flight};
+        handle_job(job, &config, request_handler.clone());
+    }
+}
+
+/// Counts a job as finished when it is dropped, and if the worker is
+/// panicking, sends a 500 response instead of just dropping the connection.
+struct JobGuard<'a> {
+    /// Where to send the 500 response, if anywhere
+    stream: Option<TcpStream>,
+    in_flight: &'a InFlightCount,
+}
+
+impl<'a> Drop for JobGuard<'a> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            if let Some(ref mut stream) = self.stream {
+                write_http_response(stream, 500, &[], "Request handler failed");
+            }
+        }
+        change_in_flight_count(self.in_flight, -1);
+    }
+}
+
+/// A clone of the stream that a job's response is written to in plain text,
+/// if the request handler gets to run.
+fn panic_stream(job: &Job, config: &ServerConfig) -> Option<TcpStream> {
+    let stream = match *job {
+        Job::Request(ref stream, _, _) => stream,
+        Job::Connection(ref stream) if config.tls.is_none() => stream,
+        _ => return None,
     };
+    // Timeouts aren't shared between clones
+    let mut stream = stream.clone();
+    stream.set_write_timeout(Some(config.request_timeout_ms));
+    Some(stream)
+}
 
-    Ok(())
+fn handle_job<H: HandlesHttpRequests>(job: Job, config: &ServerConfig, request_handler: H) {
+    match job {
+        Job::Request(mut stream, header, body) => {
+            prepare_for_response(&mut stream, config);
+            let (status, headers, body) = handle_request(request_handler, header, body, config);
+            write_http_response(&mut stream, status, headers.as_slice(), body.as_slice());
+        },
+        Job::Error(mut stream, err) => {
+            warn!("Failed to read http request: {}", err.message);
+            prepare_for_response(&mut stream, config);
+            write_http_response(&mut stream, err.status, &[], err.message.as_slice());
+        },
+        Job::Connection(mut stream) => {
+            stream.set_read_timeout(Some(config.request_timeout_ms));
+            stream.set_write_timeout(Some(config.request_timeout_ms));
+            match config.tls {
+                Some(ref tls_config) => match tls::accept(stream, tls_config) {
+                    Ok(mut stream) => serve_connection(&mut stream, config, request_handler),
+                    Err(err) => {warn!("{}", err);},
+                },
+                None => serve_connection(&mut stream, config, request_handler),
+            };
+        },
+    };
 }
 
-fn handle_incoming_request<H: HandlesHttpRequests>(
-    mut stream: TcpStream,
+/// Read a request from a blocking stream and respond to it.
+fn serve_connection<S: Reader + Writer, H: HandlesHttpRequests>(
+    stream: &mut S,
+    config: &ServerConfig,
     request_handler: H)
 {
-    match read_http_request(&mut stream) {
-        Ok((header, body)) => {
-            let (response_status, response_body) = request_handler.handle_request(&header, body.as_slice());
-            let http_response = create_http_response(response_status, response_body.as_slice());
-            debug!("Sending response:\n{}", http_response.as_slice());
-            match stream.write(http_response.as_bytes()) {
-                Ok(_) => (),
-                Err(_) => {warn!("Failed to write response");},
-            };
+    let (status, headers, body) = match read_http_request(stream, config) {
+        Ok((header, body)) => handle_request(request_handler, header, body, config),
+        Err(err) => {
+            warn!("Failed to read http request: {}", err.message);
+            (err.status, vec![], err.message)
+        },
+    };
+    write_http_response(stream, status, headers.as_slice(), body.as_slice());
+}
+
+/// The event loop leaves sockets non-blocking; go back to blocking writes,
+/// but don't let a client that never reads hold on to this worker.
+fn prepare_for_response(stream: &mut TcpStream, config: &ServerConfig) {
+    match event_loop::set_nonblocking(stream.as_raw_fd(), false) {
+        Ok(_) => (),
+        Err(err) => {warn!("{}", err);},
+    };
+    stream.set_write_timeout(Some(config.request_timeout_ms));
+}
+
+/// Read a whole request from a blocking stream.
+fn read_http_request<R: Reader>(stream: &mut R, config: &ServerConfig)
+    -> Result<(RequestHeader, String), RequestError> {
+    let mut parser = RequestParser::new(config);
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = match stream.read(&mut buf) {
+            Ok(n) => n,
+            Err(IoError {kind: IoErrorKind::TimedOut, ..}) =>
+                return Err(RequestError::new(408, "Timed out reading request")),
+            Err(err) => return Err(RequestError::new(400, format!(
+                "Failed to read request from stream: {}", err).as_slice())),
+        };
+        match try!(parser.feed(&buf[..n])) {
+            Some(request) => return Ok(request),
+            None => {},
+        };
+    }
+}
+
+fn write_http_response<W: Writer>(stream: &mut W, status: i32, headers: &[(String, String)], body: &str) {
+    let http_response = create_http_response(status, headers, body);
+    debug!("Sending response:\n{}", http_response.as_slice());
+    match stream.write_all(http_response.as_bytes()) {
+        Ok(_) => (),
+        Err(_) => {warn!("Failed to write response");},
+    };
+}
+
+/// Check the request's credentials if the server requires them, and then
+/// pass it to the request handler.
+fn handle_request<H: HandlesHttpRequests>(
+    request_handler: H,
+    header: RequestHeader,
+    body: String,
+    config: &ServerConfig) -> (i32, Vec<(String, String)>, String)
+{
+    match config.basic_auth {
+        Some((ref username, ref password)) => {
+            let authorization = header.authorization.as_ref().map(|a| a.as_slice());
+            if !auth::check_basic_auth(authorization, username.as_slice(), password.as_slice()) {
+                return (401, vec![], "Authentication required".to_string());
+            }
         },
-        Err(err) => {warn!("Failed to read http request: {}", err);},
+        None => {},
     };
+
+    request_handler.handle_request_with_headers(&header, body.as_slice())
 }
 
-fn create_http_response(status: i32, body: &str) -> String {
+/// Build a response with the handler's header fields. The Content-Type is
+/// text/xml unless the handler gives one.
+fn create_http_response(status: i32, headers: &[(String, String)], body: &str) -> String {
+    let mut extra_headers = match status {
+        401 => "WWW-Authenticate: Basic realm=\"ros_rust\"\r\n".to_string(),
+        _ => "".to_string(),
+    };
+    let mut content_type = "text/xml";
+    for &(ref name, ref value) in headers.iter() {
+        if name.as_slice().eq_ignore_ascii_case("Content-Type") {
+            content_type = value.as_slice();
+        } else {
+            extra_headers.push_str(format!("{}: {}\r\n", name, value).as_slice());
+        }
+    }
+
     format!("\
-        HTTP/1.1 {status} OK\n\
-        Connection: close\n\
-        Content-Length: {content_length}\n\
-        Content-Type: text/xml\n\n{body}", status=status, content_length=body.len(), body=body)
+        HTTP/1.1 {status} {reason}\r\n\
+        Connection: close\r\n\
+        {extra_headers}\
+        Content-Length: {content_length}\r\n\
+        Content-Type: {content_type}\r\n\r\n{body}",
+        status=status, reason=reason_phrase(status), extra_headers=extra_headers,
+        content_length=body.len(), content_type=content_type, body=body)
 }
 
-fn read_http_request_header<R: Reader>(stream: &mut R) -> Result<RequestHeader, String> {
+/// Standard reason phrase to put in the status line for a status code.
+fn reason_phrase(status: i32) -> &'static str {
+    match status {
+        200 => "OK",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        413 => "Request Entity Too Large",
+        500 => "Internal Server Error",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        x if x >= 200 && x < 300 => "Success",
+        x if x >= 400 && x < 500 => "Client Error",
+        _ => "Server Error",
+    }
+}
+
+/// Parses an HTTP request incrementally, as its bytes arrive from the client.
+pub struct RequestParser {
+    max_header_bytes: usize,
+    max_body_bytes: usize,
+    buffer: Vec<u8>,
+    /// Parsed header, and the offset in the buffer where the body starts.
+    header: Option<(RequestHeader, usize)>,
+}
+
+impl RequestParser {
+    pub fn new(config: &ServerConfig) -> RequestParser {
+        RequestParser {
+            max_header_bytes: config.max_header_bytes,
+            max_body_bytes: config.max_body_bytes,
+            buffer: vec![],
+            header: None,
+        }
+    }
+
+    /// Add bytes received from the client. Returns the header and body of
+    /// the request once all of it has been received.
+    pub fn feed(&mut self, bytes: &[u8]) -> Result<Option<(RequestHeader, String)>, RequestError> {
+        self.buffer.push_all(bytes);
+
+        if self.header.is_none() {
+            let body_start = match find_header_end(self.buffer.as_slice()) {
+                Some(i) => i,
+                None => {
+                    if self.buffer.len() > self.max_header_bytes {
+                        return Err(RequestError::new(400, format!(
+                            "Request header larger than {} bytes", self.max_header_bytes).as_slice()));
+                    }
+                    return Ok(None);
+                },
+            };
+            if body_start > self.max_header_bytes {
+                return Err(RequestError::new(400, format!(
+                    "Request header larger than {} bytes", self.max_header_bytes).as_slice()));
+            }
+
+            let header = match str::from_utf8(&self.buffer[..body_start]) {
+                Ok(header_str) => try!(parse_http_request_header(header_str)),
+                Err(_) => return Err(RequestError::new(400, "Request header is not valid utf8")),
+            };
+            if header.content_length < 0 {
+                return Err(RequestError::new(400, format!(
+                    "Invalid content-length in header ({})", header.content_length).as_slice()));
+            }
+            if header.content_length as usize > self.max_body_bytes {
+                return Err(RequestError::new(413, format!(
+                    "Request body larger than {} bytes", self.max_body_bytes).as_slice()));
+            }
+            self.header = Some((header, body_start));
+        }
+
+        let (ref header, body_start) = *self.header.as_ref().unwrap();
+        let body_end = body_start + header.content_length as usize;
+        if self.buffer.len() < body_end {
+            return Ok(None);
+        }
+        match String::from_utf8(self.buffer[body_start..body_end].to_vec()) {
+            Ok(body) => Ok(Some((header.clone(), body))),
+            Err(_) => Err(RequestError::new(400, "Request body is not valid utf8")),
+        }
+    }
+}
+
+/// Find the end of the empty line which ends the header.
+fn find_header_end(buffer: &[u8]) -> Option<usize> {
+    buffer.windows(4).position(|w| w == &b"\r\n\r\n"[..]).map(|i| i + 4)
+}
+
+fn parse_http_request_header(header_str: &str) -> Result<RequestHeader, RequestError> {
     let mut header = RequestHeader {
         method: "".to_string(),
         request_uri: "".to_string(),
         http_version: "".to_string(),
-        content_length: 0};
-
-    // Read until finding empty line at end of header
-    let mut header_str = String::new();
-    let mut done = false;
-    while !done {
-        let b = match stream.read_byte() {
-            Ok(b) => b,
-            Err(_) => return Err("Failed to read resquest header from stream".to_string()),
-        };
-        header_str.push(b as char);
-        if header_str.len() >= 4 {
-            if header_str.as_slice()[header_str.len()-4..] == *"\r\n\r\n".as_slice() {
-                done = true;
-            }
-        }
-    }
+        content_length: 0,
+        authorization: None};
     debug!("Received header:\n{}", header_str);
 
     // Parse request line
     let request_line_re = regex!("^(.+) (.+) (.+)\r\n");
-    match request_line_re.captures(header_str.as_slice()) {
-        None => return Err("Unable to parse header request line".to_string()),
+    match request_line_re.captures(header_str) {
+        None => return Err(RequestError::new(400, "Unable to parse header request line")),
         Some(caps) => {
             header.method = caps.at(1).unwrap().to_string();
             header.request_uri = caps.at(2).unwrap().to_string();
@@ -90,17 +629,20 @@ fn read_http_request_header<R: Reader>(stream: &mut R) -> Result<RequestHeader,
         },
     };
 
+    let authorization_re = regex!("(?i)Authorization: ([^\r]*)\r\n");
+    header.authorization = authorization_re.captures(header_str).map(|caps| caps.at(1).unwrap().to_string());
+
     // Look for the Content-Length if this is a POST
     if header.method.as_slice() == "POST" {
         let content_length_re = regex!("(?i)Content-Length: ([0-9]+)\r\n");
-        match content_length_re.captures(header_str.as_slice()) {
-            None => return Err("Header missing Content-Length field".to_string()),
+        match content_length_re.captures(header_str) {
+            None => return Err(RequestError::new(400, "Header missing Content-Length field")),
             Some(caps) => {
                 header.content_length = match caps.at(1) {
                     None => panic!("Capture field unexpectedly missing".to_string()),
                     Some(s) => match s.parse() {
                         Ok(x) => x,
-                        Err(_) => return Err("Failed to parse content-length to string".to_string()),
+                        Err(_) => return Err(RequestError::new(400, "Failed to parse content-length to integer")),
                     },
                 }
             },
@@ -110,47 +652,68 @@ fn read_http_request_header<R: Reader>(stream: &mut R) -> Result<RequestHeader,
     Ok(header)
 }
 
-/// Read an HTTP request from a stream.
-fn read_http_request<R: Reader>(stream: &mut R) -> Result<(RequestHeader, String), String> {
-    let header = match read_http_request_header(stream) {
-        Ok(h) => h,
-        Err(e) => return Err(format!("Error reading header: {}", e)),
-    };
-
-    let mut body = String::new();
-    match header.content_length {
-        0 => {},
-        x if x < 0 => {
-            return Err(format!("Invalid content-length in header ({})", header.content_length));
-        },
-        x if x > 0 => {
-            // Read request content
-            let mut done = false;
-            while !done {
-                let b = match stream.read_byte() {
-                    Ok(b) => b,
-                    Err(_) => return Err("Failed to read requst body from stream".to_string()),
-                };
-                body.push(b as char);
-                if body.len() >= header.content_length as usize {
-                    done = true;
-                }
-            };
-        },
-        _ => return Err("Unexpected value for content_length".to_string()),
-    };
-
-    Ok((header, body))
-}
-
-pub trait HandlesHttpRequests: Sync + Send + Clone {
+pub trait HandlesHttpRequests: Sync + Send + Clone + 'static {
     fn handle_request(&self, header: &RequestHeader, body: &str) -> (i32, String);
+
+    /// Like handle_request, but also returns extra header fields to put in
+    /// the response, as (name, value) pairs. The server calls this, so
+    /// handlers which need to set header fields override it; by default
+    /// there are none.
+    fn handle_request_with_headers(&self, header: &RequestHeader, body: &str)
+            -> (i32, Vec<(String, String)>, String) {
+        let (status, body) = self.handle_request(header, body);
+        (status, vec![], body)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use http::RequestHeader;
-    use std::old_io::MemReader;
+    use http;
+    use http::{RequestHeader, HandlesHttpRequests, TlsServerConfig};
+    use std::old_io::{MemReader, TcpListener, TcpStream};
+    use std::time::Duration;
+    use super::{run_http_server, create_http_response, parse_http_request_header, read_http_request};
+    use super::{ServerConfig, RequestParser, RequestError};
+
+    #[derive(Clone)]
+    struct EchoHandler;
+
+    impl HandlesHttpRequests for EchoHandler {
+        fn handle_request(&self, _: &RequestHeader, body: &str) -> (i32, String) {
+            (200, body.to_string())
+        }
+    }
+
+    #[derive(Clone)]
+    struct PanickingHandler;
+
+    impl HandlesHttpRequests for PanickingHandler {
+        fn handle_request(&self, _: &RequestHeader, _: &str) -> (i32, String) {
+            panic!("Handler failed")
+        }
+    }
+
+    #[test]
+    fn test_create_http_response() {
+        assert_eq!(create_http_response(404, &[], "nope"), "\
+            HTTP/1.1 404 Not Found\r\n\
+            Connection: close\r\n\
+            Content-Length: 4\r\n\
+            Content-Type: text/xml\r\n\r\n\
+            nope".to_string());
+        assert_eq!(create_http_response(405, &[("Allow".to_string(), "GET, POST".to_string())], ""), "\
+            HTTP/1.1 405 Method Not Allowed\r\n\
+            Connection: close\r\n\
+            Allow: GET, POST\r\n\
+            Content-Length: 0\r\n\
+            Content-Type: text/xml\r\n\r\n".to_string());
+        assert_eq!(create_http_response(200, &[("content-type".to_string(), "text/html".to_string())], "hi"), "\
+            HTTP/1.1 200 OK\r\n\
+            Connection: close\r\n\
+            Content-Length: 2\r\n\
+            Content-Type: text/html\r\n\r\n\
+            hi".to_string());
+    }
 
     #[test]
     fn test_parse_request_header() {
@@ -166,13 +729,215 @@ mod tests {
             method: "POST".to_string(),
             request_uri: "/RPC2".to_string(),
             http_version: "HTTP/1.0".to_string(),
+            authorization: None,
         };
 
-        let mut request_reader = MemReader::new(request_header_str.as_bytes().to_vec());
-
-        match super::read_http_request_header(&mut request_reader) {
+        match parse_http_request_header(request_header_str) {
             Ok(x) => assert_eq!(x, correct_result),
+            Err(err) => assert!(false, err.message),
+        }
+    }
+
+    #[test]
+    fn test_request_parser_partial_reads() {
+        let request_str = "POST /RPC2 HTTP/1.0\r\nContent-Length: 5\r\n\r\nhello";
+        let mut parser = RequestParser::new(&ServerConfig::new(1));
+
+        // Feed the request a few bytes at a time
+        for chunk in request_str.as_bytes()[..request_str.len() - 1].chunks(3) {
+            match parser.feed(chunk) {
+                Ok(None) => (),
+                _ => return assert!(false, "Parser finished before request was complete"),
+            };
+        }
+        match parser.feed(&request_str.as_bytes()[request_str.len() - 1..]) {
+            Ok(Some((header, body))) => {
+                assert_eq!(header.request_uri.as_slice(), "/RPC2");
+                assert_eq!(body.as_slice(), "hello");
+            },
+            _ => assert!(false, "Parser failed to finish complete request"),
+        };
+    }
+
+    #[test]
+    fn test_request_limits() {
+        let mut config = ServerConfig::new(1);
+        config.max_header_bytes = 64;
+        config.max_body_bytes = 4;
+
+        let long_header = "\
+            POST /RPC2 HTTP/1.0\r\n\
+            User-Agent: Frontier/5.1.2 (WinNT) with a user agent string that is far too long\r\n";
+        match RequestParser::new(&config).feed(long_header.as_bytes()) {
+            Err(RequestError {status: 400, ..}) => (),
+            _ => assert!(false, "Oversized header not rejected"),
+        };
+
+        let long_body = "POST /RPC2 HTTP/1.0\r\nContent-Length: 5\r\n\r\nhello";
+        match RequestParser::new(&config).feed(long_body.as_bytes()) {
+            Err(RequestError {status: 413, ..}) => (),
+            _ => assert!(false, "Oversized body not rejected"),
+        };
+
+        // A truncated body leaves the parser waiting for the rest, until the
+        // connection is closed
+        let short_body = "POST /RPC2 HTTP/1.0\r\nContent-Length: 4\r\n\r\nhel";
+        match RequestParser::new(&config).feed(short_body.as_bytes()) {
+            Ok(None) => (),
+            _ => assert!(false, "Truncated body not left incomplete"),
+        };
+        let mut reader = MemReader::new(short_body.as_bytes().to_vec());
+        match read_http_request(&mut reader, &config) {
+            Err(RequestError {status: 400, ..}) => (),
+            _ => assert!(false, "Truncated body not rejected"),
+        };
+    }
+
+    #[test]
+    fn test_truncated_body() {
+        for &use_event_loop in [true, false].iter() {
+            let mut config = ServerConfig::new(1);
+            config.use_event_loop = use_event_loop;
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let server = run_http_server(listener, config, EchoHandler).unwrap();
+
+            let mut stream = TcpStream::connect(server.socket_addr()).unwrap();
+            stream.write_all("POST /RPC2 HTTP/1.0\r\nContent-Length: 4\r\n\r\nhel".as_bytes()).unwrap();
+            stream.close_write().unwrap();
+            let response = stream.read_to_string().unwrap();
+            assert!(response.as_slice().starts_with("HTTP/1.1 400"), "Bad response: {}", response);
+
+            server.shutdown(Duration::seconds(5)).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_serve_and_shutdown() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = match run_http_server(listener, ServerConfig::new(2), EchoHandler) {
+            Ok(server) => server,
+            Err(err) => return assert!(false, err),
+        };
+
+        let mut stream = TcpStream::connect(server.socket_addr()).unwrap();
+        stream.write_all("\
+            POST /RPC2 HTTP/1.0\r\n\
+            Content-Length: 5\r\n\r\n\
+            hello".as_bytes()).unwrap();
+        let response = stream.read_to_string().unwrap();
+        assert!(response.as_slice().ends_with("hello"), "Bad response: {}", response);
+
+        match server.shutdown(Duration::seconds(5)) {
+            Ok(_) => (),
             Err(err) => assert!(false, err),
+        };
+    }
+
+    #[test]
+    fn test_slow_client_times_out() {
+        let mut config = ServerConfig::new(1);
+        config.request_timeout_ms = 100;
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = run_http_server(listener, config, EchoHandler).unwrap();
+
+        // Send part of a header and then stall
+        let mut stream = TcpStream::connect(server.socket_addr()).unwrap();
+        stream.write_all("POST /RPC2 HTTP/1.0\r\n".as_bytes()).unwrap();
+        let response = stream.read_to_string().unwrap();
+        assert!(response.as_slice().starts_with("HTTP/1.1 408"), "Bad response: {}", response);
+
+        server.shutdown(Duration::seconds(5)).unwrap();
+    }
+
+    #[test]
+    fn test_slow_clients_dont_block_workers() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = run_http_server(listener, ServerConfig::new(1), EchoHandler).unwrap();
+
+        // More stalled clients than there are worker threads
+        let mut stalled_streams = vec![];
+        for _ in 0..4 {
+            let mut stream = TcpStream::connect(server.socket_addr()).unwrap();
+            stream.write_all("POST /RPC2 HTTP/1.0\r\n".as_bytes()).unwrap();
+            stalled_streams.push(stream);
         }
+
+        let mut stream = TcpStream::connect(server.socket_addr()).unwrap();
+        stream.set_read_timeout(Some(1000));
+        stream.write_all("POST /RPC2 HTTP/1.0\r\nContent-Length: 5\r\n\r\nhello".as_bytes()).unwrap();
+        let response = stream.read_to_string().unwrap();
+        assert!(response.as_slice().ends_with("hello"), "Bad response: {}", response);
+
+        drop(stalled_streams);
+        server.shutdown(Duration::seconds(5)).unwrap();
+    }
+
+    #[test]
+    fn test_handler_panic_returns_500() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = run_http_server(listener, ServerConfig::new(1), PanickingHandler).unwrap();
+
+        // Make two requests to check that the worker survives the panic
+        for _ in 0..2 {
+            let mut stream = TcpStream::connect(server.socket_addr()).unwrap();
+            stream.write_all("GET / HTTP/1.0\r\n\r\n".as_bytes()).unwrap();
+            let response = stream.read_to_string().unwrap();
+            assert!(response.as_slice().starts_with("HTTP/1.1 500 Internal Server Error\r\n"),
+                "Bad response: {}", response);
+        }
+
+        server.shutdown(Duration::seconds(5)).unwrap();
+    }
+
+    #[test]
+    fn test_basic_auth_required() {
+        let mut config = ServerConfig::new(1);
+        config.basic_auth = Some(("Aladdin".to_string(), "open sesame".to_string()));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = run_http_server(listener, config, EchoHandler).unwrap();
+
+        let uri = format!("http://{}/", server.socket_addr());
+        match http::post(uri.as_slice(), "hello") {
+            Ok((header, _)) => assert_eq!(header.status, 401),
+            Err(err) => assert!(false, err),
+        };
+
+        let uri = format!("http://Aladdin:open%20sesame@{}/", server.socket_addr());
+        match http::post(uri.as_slice(), "hello") {
+            Ok((header, body)) => assert_eq!((header.status, body.as_slice()), (200, "hello")),
+            Err(err) => assert!(false, err),
+        };
+
+        server.shutdown(Duration::seconds(5)).unwrap();
+    }
+
+    #[cfg(feature = "tls")]
+    #[test]
+    fn test_tls() {
+        let mut config = ServerConfig::new(1);
+        config.tls = Some(TlsServerConfig::new(
+            Path::new("test/certs/localhost.crt"),
+            Path::new("test/certs/localhost.key")).unwrap());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = run_http_server(listener, config, EchoHandler).unwrap();
+
+        let uri = format!("https://localhost:{}/", server.socket_addr().port);
+        let mut client_config = http::ClientConfig::new();
+        client_config.ca_file = Some(Path::new("test/certs/localhost.crt"));
+        match http::post_with_config(uri.as_slice(), "hello", &client_config) {
+            Ok((header, body)) => assert_eq!((header.status, body.as_slice()), (200, "hello")),
+            Err(err) => assert!(false, err),
+        };
+
+        // The certificate is for localhost, so connecting by IP address fails
+        // before the request is sent
+        let uri = format!("https://127.0.0.1:{}/", server.socket_addr().port);
+        assert!(http::post_with_config(uri.as_slice(), "hello", &client_config).is_err());
+
+        // Plain HTTP clients can't talk to a TLS server
+        let uri = format!("http://localhost:{}/", server.socket_addr().port);
+        assert!(http::post(uri.as_slice(), "hello").is_err());
+
+        server.shutdown(Duration::seconds(5)).unwrap();
     }
 }
@@ -0,0 +1,181 @@
+//! TLS support for the HTTP client and server, using OpenSSL.
+//!
+//! Only available when the crate is built with the "tls" feature. Without
+//! it, the same functions exist but always return an error, so that the rest
+//! of the http module doesn't need to care which is the case.
+
+use std::ascii::AsciiExt;
+use std::fmt;
+
+#[cfg(feature = "tls")]
+pub use self::openssl_impl::{TlsStream, ServerContext, connect, accept};
+#[cfg(not(feature = "tls"))]
+pub use self::disabled_impl::{TlsStream, ServerContext, connect, accept};
+
+/// Certificate and private key for an HTTP server. These are loaded once
+/// when the config is created, and shared by all of the connections.
+#[derive(Clone)]
+pub struct TlsServerConfig {
+    pub cert_file: Path,
+    pub key_file: Path,
+    context: ServerContext,
+}
+
+impl TlsServerConfig {
+    /// Load a certificate and private key from PEM files.
+    pub fn new(cert_file: Path, key_file: Path) -> Result<TlsServerConfig, String> {
+        let context = try!(ServerContext::new(&cert_file, &key_file));
+        Ok(TlsServerConfig {cert_file: cert_file, key_file: key_file, context: context})
+    }
+}
+
+impl fmt::Debug for TlsServerConfig {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "TlsServerConfig {{ cert_file: {}, key_file: {} }}",
+               self.cert_file.display(), self.key_file.display())
+    }
+}
+
+/// Whether a name from a certificate matches host. The name may start with
+/// a "*." wildcard, which matches exactly one label, e.g. "*.example.com"
+/// matches "a.example.com" but not "example.com" or "a.b.example.com".
+#[cfg_attr(not(feature = "tls"), allow(dead_code))]
+fn host_matches(name: &str, host: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    let host = host.to_ascii_lowercase();
+    if name.starts_with("*.") {
+        match host.find('.') {
+            Some(dot) => dot > 0 && host.slice_from(dot) == name.slice_from(1),
+            None => false,
+        }
+    } else {
+        name == host
+    }
+}
+
+#[cfg(feature = "tls")]
+mod openssl_impl {
+    use openssl::nid::Nid;
+    use openssl::ssl::{SslContext, SslMethod, SslStream, SslVerifyMode};
+    use openssl::x509::X509FileType;
+    use std::old_io::TcpStream;
+    use std::sync::Arc;
+    use super::{TlsServerConfig, host_matches};
+
+    pub type TlsStream = SslStream<TcpStream>;
+
+    /// A server's SSL context, with its certificate and key loaded.
+    #[derive(Clone)]
+    pub struct ServerContext {
+        ctx: Arc<SslContext>,
+    }
+
+    impl ServerContext {
+        pub fn new(cert_file: &Path, key_file: &Path) -> Result<ServerContext, String> {
+            let mut ctx = match SslContext::new(SslMethod::Sslv23) {
+                Ok(ctx) => ctx,
+                Err(err) => return Err(format!("Failed to create TLS context: {}", err)),
+            };
+            match ctx.set_certificate_file(cert_file, X509FileType::PEM) {
+                None => (),
+                Some(err) => return Err(format!("Failed to load TLS certificate: {}", err)),
+            };
+            match ctx.set_private_key_file(key_file, X509FileType::PEM) {
+                None => (),
+                Some(err) => return Err(format!("Failed to load TLS private key: {}", err)),
+            };
+            Ok(ServerContext {ctx: Arc::new(ctx)})
+        }
+    }
+
+    /// Start a TLS session as the client on a connected stream. The server's
+    /// certificate must be signed by a CA in ca_file, or by one of the
+    /// system's trusted CAs if ca_file is None, and its common name must
+    /// match host. Names in the subjectAltName extension aren't checked, so
+    /// certificates which only name the host there are rejected.
+    pub fn connect(stream: TcpStream, host: &str, ca_file: Option<&Path>) -> Result<TlsStream, String> {
+        let mut ctx = match SslContext::new(SslMethod::Sslv23) {
+            Ok(ctx) => ctx,
+            Err(err) => return Err(format!("Failed to create TLS context: {}", err)),
+        };
+        ctx.set_verify(SslVerifyMode::SslVerifyPeer, None);
+        let ca_err = match ca_file {
+            Some(path) => ctx.set_CA_file(path),
+            None => ctx.set_default_verify_paths(),
+        };
+        match ca_err {
+            None => (),
+            Some(err) => return Err(format!("Failed to load TLS CA certificates: {}", err)),
+        };
+
+        let stream = match SslStream::new(&ctx, stream) {
+            Ok(stream) => stream,
+            Err(err) => return Err(format!("TLS handshake with server failed: {}", err)),
+        };
+
+        // OpenSSL only checks that the certificate is signed by a trusted
+        // CA, so check that it was issued for this host before anything,
+        // like credentials, is sent to it
+        let cert = match stream.ssl().get_peer_certificate() {
+            Some(cert) => cert,
+            None => return Err("TLS server did not send a certificate".to_string()),
+        };
+        match cert.subject_name().text_by_nid(Nid::CN) {
+            Some(ref name) if host_matches(name.as_slice(), host) => Ok(stream),
+            Some(ref name) => Err(format!("TLS certificate is for {}, not {}", name.as_slice(), host)),
+            None => Err(format!("TLS certificate has no common name to check against {}", host)),
+        }
+    }
+
+    /// Start a TLS session as the server on a newly accepted stream.
+    pub fn accept(stream: TcpStream, config: &TlsServerConfig) -> Result<TlsStream, String> {
+        match SslStream::new_server(&*config.context.ctx, stream) {
+            Ok(stream) => Ok(stream),
+            Err(err) => Err(format!("TLS handshake with client failed: {}", err)),
+        }
+    }
+}
+
+#[cfg(not(feature = "tls"))]
+mod disabled_impl {
+    use std::old_io::TcpStream;
+    use super::TlsServerConfig;
+
+    pub type TlsStream = TcpStream;
+
+    #[derive(Clone)]
+    pub struct ServerContext;
+
+    impl ServerContext {
+        pub fn new(_: &Path, _: &Path) -> Result<ServerContext, String> {
+            Err("TLS support requires building ros_rust with the tls feature".to_string())
+        }
+    }
+
+    pub fn connect(_: TcpStream, _: &str, _: Option<&Path>) -> Result<TlsStream, String> {
+        Err("TLS support requires building ros_rust with the tls feature".to_string())
+    }
+
+    pub fn accept(_: TcpStream, _: &TlsServerConfig) -> Result<TlsStream, String> {
+        Err("TLS support requires building ros_rust with the tls feature".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::host_matches;
+
+    #[test]
+    fn test_host_matches() {
+        assert!(host_matches("localhost", "localhost"));
+        assert!(host_matches("Example.COM", "example.com"));
+        assert!(!host_matches("example.com", "example.org"));
+        assert!(!host_matches("example.com", "a.example.com"));
+
+        assert!(host_matches("*.example.com", "a.example.com"));
+        assert!(!host_matches("*.example.com", "example.com"));
+        assert!(!host_matches("*.example.com", ".example.com"));
+        assert!(!host_matches("*.example.com", "a.b.example.com"));
+        assert!(!host_matches("*.example.com", "localhost"));
+    }
+}
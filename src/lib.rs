@@ -1,6 +1,7 @@
 #![feature(slicing_syntax)]
 #![feature(io)]
 
+#![feature(libc)]
 #![feature(plugin)]
 #[plugin] #[no_link]
 extern crate regex_macros;
@@ -8,9 +9,19 @@ extern crate regex;
 
 #[macro_use]
 extern crate log;
+extern crate time;
+extern crate libc;
+#[cfg(feature = "tls")]
+extern crate openssl;
 
+#[macro_use]
 pub mod msg;
 pub mod http;
 pub mod xml;
+#[macro_use]
 pub mod xmlrpc;
+pub mod tcpros;
+pub mod udpros;
+pub mod genmsg;
+pub mod ros;
 
@@ -0,0 +1,226 @@
+use std::cmp;
+use std::old_io::IoResult;
+use std::default::Default;
+use msg::time::{Time, Duration};
+
+/// Size of the pieces that read_bytes reads in.
+const READ_CHUNK_BYTES: usize = 64 * 1024;
+
+/// A type which can be a field of a ROS message: one of the primitive
+/// types, a string, an array, or another message.
+pub trait RosField: Sized {
+    /// Serialize the value, as it is laid out inside a message.
+    fn encode<W: Writer>(&self, stream: &mut W) -> IoResult<()>;
+
+    /// Deserialize a value.
+    fn decode<R: Reader>(stream: &mut R) -> Result<Self, String>;
+}
+
+/// Read length bytes from a stream. The length usually comes from the
+/// sender, so the buffer grows as the bytes arrive rather than being
+/// allocated up front, and a bogus length fails at the end of the stream
+/// without using much memory.
+pub fn read_bytes<R: Reader>(stream: &mut R, length: usize) -> IoResult<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(cmp::min(length, READ_CHUNK_BYTES));
+    while bytes.len() < length {
+        let chunk = cmp::min(length - bytes.len(), READ_CHUNK_BYTES);
+        try!(stream.push_at_least(chunk, chunk, &mut bytes));
+    }
+    Ok(bytes)
+}
+
+/// Implement RosField for a primitive type, given the Reader and Writer
+/// methods which read and write it.
+macro_rules! primitive_field {
+    ($t:ty, $ros_type:expr, $write:ident, $read:ident) => (
+        impl RosField for $t {
+            fn encode<W: Writer>(&self, stream: &mut W) -> IoResult<()> {
+                stream.$write(*self)
+            }
+
+            fn decode<R: Reader>(stream: &mut R) -> Result<$t, String> {
+                stream.$read().map_err(|err| format!("Failed to read {}: {}", $ros_type, err))
+            }
+        }
+    )
+}
+
+primitive_field!(i8, "int8", write_i8, read_i8);
+primitive_field!(u8, "uint8", write_u8, read_u8);
+primitive_field!(i16, "int16", write_le_i16, read_le_i16);
+primitive_field!(u16, "uint16", write_le_u16, read_le_u16);
+primitive_field!(i32, "int32", write_le_i32, read_le_i32);
+primitive_field!(u32, "uint32", write_le_u32, read_le_u32);
+primitive_field!(i64, "int64", write_le_i64, read_le_i64);
+primitive_field!(u64, "uint64", write_le_u64, read_le_u64);
+primitive_field!(f32, "float32", write_le_f32, read_le_f32);
+primitive_field!(f64, "float64", write_le_f64, read_le_f64);
+
+/// bool is sent as a uint8.
+impl RosField for bool {
+    fn encode<W: Writer>(&self, stream: &mut W) -> IoResult<()> {
+        stream.write_u8(if *self { 1 } else { 0 })
+    }
+
+    fn decode<R: Reader>(stream: &mut R) -> Result<bool, String> {
+        let value: u8 = try!(RosField::decode(stream));
+        Ok(value != 0)
+    }
+}
+
+impl RosField for String {
+    fn encode<W: Writer>(&self, stream: &mut W) -> IoResult<()> {
+        try!(stream.write_le_u32(self.len() as u32));
+        stream.write_str(self.as_slice())
+    }
+
+    fn decode<R: Reader>(stream: &mut R) -> Result<String, String> {
+        let length: u32 = try!(RosField::decode(stream));
+        let bytes = match read_bytes(stream, length as usize) {
+            Ok(bytes) => bytes,
+            Err(err) => return Err(format!("Failed to read string: {}", err)),
+        };
+        String::from_utf8(bytes).map_err(|_| "String is not valid UTF-8".to_string())
+    }
+}
+
+impl RosField for Time {
+    fn encode<W: Writer>(&self, stream: &mut W) -> IoResult<()> {
+        try!(self.sec.encode(stream));
+        self.nsec.encode(stream)
+    }
+
+    fn decode<R: Reader>(stream: &mut R) -> Result<Time, String> {
+        Ok(Time {sec: try!(RosField::decode(stream)), nsec: try!(RosField::decode(stream))})
+    }
+}
+
+impl RosField for Duration {
+    fn encode<W: Writer>(&self, stream: &mut W) -> IoResult<()> {
+        try!(self.sec.encode(stream));
+        self.nsec.encode(stream)
+    }
+
+    fn decode<R: Reader>(stream: &mut R) -> Result<Duration, String> {
+        Ok(Duration {sec: try!(RosField::decode(stream)), nsec: try!(RosField::decode(stream))})
+    }
+}
+
+/// Variable length arrays, which are sent with their length.
+impl<T: RosField> RosField for Vec<T> {
+    fn encode<W: Writer>(&self, stream: &mut W) -> IoResult<()> {
+        try!(stream.write_le_u32(self.len() as u32));
+        for element in self.iter() {
+            try!(element.encode(stream));
+        }
+        Ok(())
+    }
+
+    fn decode<R: Reader>(stream: &mut R) -> Result<Vec<T>, String> {
+        let length: u32 = try!(RosField::decode(stream));
+        // The length comes from the sender, so don't trust it to size the
+        // Vec up front
+        let mut elements = vec![];
+        for _ in 0..length {
+            elements.push(try!(RosField::decode(stream)));
+        }
+        Ok(elements)
+    }
+}
+
+/// Implement RosField for fixed length arrays, which are sent without
+/// their length. The element type has to be Copy, so that the array can be
+/// filled in as it is read. Like the standard library's impls for arrays,
+/// this only goes up to 32 elements.
+macro_rules! fixed_array_fields {
+    ($($n:expr)*) => ($(
+        impl<T: RosField + Copy + Default> RosField for [T; $n] {
+            fn encode<W: Writer>(&self, stream: &mut W) -> IoResult<()> {
+                for element in self.iter() {
+                    try!(element.encode(stream));
+                }
+                Ok(())
+            }
+
+            fn decode<R: Reader>(stream: &mut R) -> Result<[T; $n], String> {
+                let mut elements = [Default::default(); $n];
+                for element in elements.iter_mut() {
+                    *element = try!(RosField::decode(stream));
+                }
+                Ok(elements)
+            }
+        }
+    )*)
+}
+
+fixed_array_fields!(0 1 2 3 4 5 6 7 8 9 10 11 12 13 14 15 16 17 18 19 20 21 22 23 24 25 26 27 28 29 30 31 32);
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Debug;
+    use std::old_io::MemReader;
+    use msg::time::{Time, Duration};
+    use super::{RosField, read_bytes};
+
+    fn round_trip<T: RosField + PartialEq + Debug>(value: T, bytes: &[u8]) {
+        let mut encoded = vec![];
+        value.encode(&mut encoded).unwrap();
+        assert_eq!(encoded.as_slice(), bytes);
+        let decoded: Result<T, String> = RosField::decode(&mut MemReader::new(encoded));
+        assert_eq!(decoded, Ok(value));
+    }
+
+    #[test]
+    fn test_primitives() {
+        round_trip(-2i8, b"\xfe");
+        round_trip(200u8, b"\xc8");
+        round_trip(-2i16, b"\xfe\xff");
+        round_trip(0x0102u16, b"\x02\x01");
+        round_trip(-2i32, b"\xfe\xff\xff\xff");
+        round_trip(0x01020304u32, b"\x04\x03\x02\x01");
+        round_trip(-2i64, b"\xfe\xff\xff\xff\xff\xff\xff\xff");
+        round_trip(1u64 << 32, b"\x00\x00\x00\x00\x01\x00\x00\x00");
+        round_trip(1.0f32, b"\x00\x00\x80\x3f");
+        round_trip(1.0f64, b"\x00\x00\x00\x00\x00\x00\xf0\x3f");
+        round_trip(true, b"\x01");
+        round_trip(false, b"\x00");
+        round_trip(Time {sec: 1, nsec: 2}, b"\x01\x00\x00\x00\x02\x00\x00\x00");
+        round_trip(Duration {sec: -1, nsec: 2}, b"\xff\xff\xff\xff\x02\x00\x00\x00");
+    }
+
+    #[test]
+    fn test_strings() {
+        round_trip("hello".to_string(), b"\x05\x00\x00\x00hello");
+        round_trip("".to_string(), b"\x00\x00\x00\x00");
+
+        let bad: Result<String, String> = RosField::decode(&mut MemReader::new(b"\x01\x00\x00\x00\xff".to_vec()));
+        assert!(bad.is_err());
+        let truncated: Result<String, String> = RosField::decode(&mut MemReader::new(b"\x05\x00\x00\x00hi".to_vec()));
+        assert!(truncated.is_err());
+    }
+
+    #[test]
+    fn test_read_bytes() {
+        let data: Vec<u8> = (0..200000).map(|i| (i % 256) as u8).collect();
+        assert_eq!(read_bytes(&mut MemReader::new(data.clone()), 200000), Ok(data.clone()));
+        assert_eq!(read_bytes(&mut MemReader::new(data.clone()), 5), Ok(data.slice_to(5).to_vec()));
+        assert_eq!(read_bytes(&mut MemReader::new(data.clone()), 0), Ok(vec![]));
+        assert!(read_bytes(&mut MemReader::new(data), 200001).is_err());
+
+        // A string claiming to be huge fails without allocating its length
+        let huge: Result<String, String> = RosField::decode(&mut MemReader::new(b"\xff\xff\xff\xffhi".to_vec()));
+        assert!(huge.is_err());
+    }
+
+    #[test]
+    fn test_arrays() {
+        round_trip(vec![1u16, 2], b"\x02\x00\x00\x00\x01\x00\x02\x00");
+        round_trip(vec!["a".to_string()], b"\x01\x00\x00\x00\x01\x00\x00\x00a");
+        round_trip(vec![vec![1u8], vec![]], b"\x02\x00\x00\x00\x01\x00\x00\x00\x01\x00\x00\x00\x00");
+        round_trip([1u8, 2, 3], b"\x01\x02\x03");
+        round_trip([true; 0], b"");
+
+        let truncated: Result<Vec<u32>, String> = RosField::decode(&mut MemReader::new(b"\x02\x00\x00\x00".to_vec()));
+        assert!(truncated.is_err());
+    }
+}
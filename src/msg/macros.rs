@@ -0,0 +1,66 @@
+/// Implement RosField and RosMessage for a struct, given its type name,
+/// md5sum, definition, and the names of its fields in the order they appear
+/// in the definition. Every field's type has to implement RosField.
+///
+/// Fixed length arrays can only be fields if they are up to 32 elements
+/// long, of a Copy type. A Vec can't stand in for a longer one, since Vecs
+/// are sent with their length, so messages with longer fixed length arrays
+/// need RosField implemented by hand, as the generated code does.
+///
+/// ```ignore
+/// pub struct Point {
+///     pub x: f64,
+///     pub y: f64,
+/// }
+///
+/// ros_message!(Point, "test_msgs/Point", "209f516d3eb691f0663e25cb750d67c1",
+///     "float64 x\nfloat64 y\n", x, y);
+/// ```
+#[macro_export]
+macro_rules! ros_message {
+    (impl RosMessage $name:ident, $msg_type:expr, $md5sum:expr, $definition:expr) => (
+        impl $crate::msg::RosMessage for $name {
+            fn msg_type() -> &'static str {
+                $msg_type
+            }
+
+            fn md5sum() -> &'static str {
+                $md5sum
+            }
+
+            fn msg_definition() -> &'static str {
+                $definition
+            }
+        }
+    );
+    ($name:ident, $msg_type:expr, $md5sum:expr, $definition:expr) => (
+        impl $crate::msg::RosField for $name {
+            fn encode<W: Writer>(&self, _: &mut W) -> ::std::old_io::IoResult<()> {
+                Ok(())
+            }
+
+            fn decode<R: Reader>(_: &mut R) -> Result<$name, String> {
+                Ok($name)
+            }
+        }
+
+        ros_message!(impl RosMessage $name, $msg_type, $md5sum, $definition);
+    );
+    ($name:ident, $msg_type:expr, $md5sum:expr, $definition:expr, $($field:ident),+) => (
+        impl $crate::msg::RosField for $name {
+            fn encode<W: Writer>(&self, stream: &mut W) -> ::std::old_io::IoResult<()> {
+                $(try!($crate::msg::RosField::encode(&self.$field, stream));)+
+                Ok(())
+            }
+
+            fn decode<R: Reader>(stream: &mut R) -> Result<$name, String> {
+                // Struct literal fields are evaluated in the order written
+                Ok($name {
+                    $($field: try!($crate::msg::RosField::decode(stream)),)+
+                })
+            }
+        }
+
+        ros_message!(impl RosMessage $name, $msg_type, $md5sum, $definition);
+    );
+}
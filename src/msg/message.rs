@@ -0,0 +1,16 @@
+use msg::field::RosField;
+
+/// A ROS message type, which can be sent over a topic. Messages are
+/// serialized as fields, so that they can be nested in other messages; the
+/// ros_message! macro implements both traits for a struct.
+pub trait RosMessage: RosField + Send + 'static {
+    /// Full name of the type, e.g. "std_msgs/String"
+    fn msg_type() -> &'static str;
+
+    /// MD5 sum of the message definition, which both ends of a connection
+    /// compare to check that they agree on the definition.
+    fn md5sum() -> &'static str;
+
+    /// Text of the message definition, as in the .msg file.
+    fn msg_definition() -> &'static str;
+}
@@ -1 +1,12 @@
+pub use msg::field::{RosField, read_bytes};
+pub use msg::message::RosMessage;
+pub use msg::service::RosService;
+pub use msg::time::{Time, Duration};
+
+#[macro_use]
+mod macros;
+mod field;
+mod message;
+mod service;
+mod time;
 pub mod std_msgs;
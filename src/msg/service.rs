@@ -0,0 +1,14 @@
+use msg::RosMessage;
+
+/// A ROS service type, made of a request message and a response message.
+pub trait RosService: 'static {
+    type Request: RosMessage;
+    type Response: RosMessage;
+
+    /// Full name of the type, e.g. "std_srvs/Empty"
+    fn srv_type() -> &'static str;
+
+    /// MD5 sum of the service definition, which clients and servers compare
+    /// to check that they agree on the definition.
+    fn md5sum() -> &'static str;
+}
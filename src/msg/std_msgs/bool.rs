@@ -0,0 +1,6 @@
+#[derive(Debug, PartialEq, Clone)]
+pub struct Bool {
+    pub data: bool,
+}
+
+ros_message!(Bool, "std_msgs/Bool", "8b94c1b53db61fb6aed406028ad6332a", "bool data\n", data);
@@ -0,0 +1,4 @@
+#[derive(Debug, PartialEq, Clone)]
+pub struct Empty;
+
+ros_message!(Empty, "std_msgs/Empty", "d41d8cd98f00b204e9800998ecf8427e", "");
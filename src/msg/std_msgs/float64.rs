@@ -0,0 +1,6 @@
+#[derive(Debug, PartialEq, Clone)]
+pub struct Float64 {
+    pub data: f64,
+}
+
+ros_message!(Float64, "std_msgs/Float64", "fdb28210bfa9d7c91146260178d9a584", "float64 data\n", data);
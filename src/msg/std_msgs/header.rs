@@ -0,0 +1,46 @@
+use msg::Time;
+
+/// Standard metadata for timestamped data in a particular coordinate frame.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Header {
+    /// Sequence number, which publishers usually increase with each message
+    pub seq: u32,
+    pub stamp: Time,
+    /// Frame the data is associated with
+    pub frame_id: String,
+}
+
+ros_message!(Header, "std_msgs/Header", "2176decaecbce78abc3b96ef049fabed",
+    "uint32 seq\ntime stamp\nstring frame_id\n", seq, stamp, frame_id);
+
+#[cfg(test)]
+mod tests {
+    use msg::{RosField, Time};
+    use msg::std_msgs::{Header, String};
+    use tcpros;
+
+    /// A message with nested messages and arrays of them.
+    #[derive(Debug, PartialEq, Clone)]
+    struct Labels {
+        header: Header,
+        labels: Vec<String>,
+        flags: [bool; 2],
+    }
+
+    ros_message!(Labels, "test_msgs/Labels", "00000000000000000000000000000000",
+        "Header header\nstd_msgs/String[] labels\nbool[2] flags\n", header, labels, flags);
+
+    #[test]
+    fn test_nested() {
+        let message = Labels {
+            header: Header {seq: 1, stamp: Time {sec: 2, nsec: 3}, frame_id: "f".to_string()},
+            labels: vec![String {data: "ab".to_string()}],
+            flags: [true, false],
+        };
+        let mut bytes = vec![];
+        message.encode(&mut bytes).unwrap();
+        assert_eq!(bytes.as_slice(), b"\x01\x00\x00\x00\x02\x00\x00\x00\x03\x00\x00\x00\x01\x00\x00\x00f\
+            \x01\x00\x00\x00\x02\x00\x00\x00ab\x01\x00");
+        assert_eq!(tcpros::decode_message(bytes), Ok(message));
+    }
+}
@@ -0,0 +1,6 @@
+#[derive(Debug, PartialEq, Clone)]
+pub struct Int32 {
+    pub data: i32,
+}
+
+ros_message!(Int32, "std_msgs/Int32", "da5909fbe378aeaf85e547e830cc1bb7", "int32 data\n", data);
@@ -1,3 +1,13 @@
-mod string;
-
+pub use msg::std_msgs::bool::Bool;
+pub use msg::std_msgs::empty::Empty;
+pub use msg::std_msgs::float64::Float64;
+pub use msg::std_msgs::header::Header;
+pub use msg::std_msgs::int32::Int32;
 pub use msg::std_msgs::string::String;
+
+mod bool;
+mod empty;
+mod float64;
+mod header;
+mod int32;
+mod string;
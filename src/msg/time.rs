@@ -0,0 +1,13 @@
+/// A ROS time: seconds and nanoseconds since the epoch.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Time {
+    pub sec: u32,
+    pub nsec: u32,
+}
+
+/// A ROS duration, which unlike a time can be negative.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Duration {
+    pub sec: i32,
+    pub nsec: i32,
+}
@@ -0,0 +1,111 @@
+use std::fmt;
+use xmlrpc;
+use xmlrpc::{Request, Response, Value};
+
+/// Status codes returned as the first element of every ROS API response.
+pub const STATUS_ERROR: i32 = -1;
+pub const STATUS_FAILURE: i32 = 0;
+pub const STATUS_SUCCESS: i32 = 1;
+
+/// Error from a call to one of the ROS XMLRPC APIs.
+#[derive(Debug, PartialEq, Clone)]
+pub enum ApiError {
+    /// The call couldn't be made, e.g. because the master isn't running.
+    Transport(String),
+    /// The response wasn't in the form specified by the API.
+    BadResponse(String),
+    /// The call was made but failed, e.g. because a service doesn't exist
+    /// (status code 0).
+    Failure(String),
+    /// The call had bad parameters (status code -1).
+    Error(String),
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ApiError::Transport(ref msg) => write!(f, "Unable to call ROS API: {}", msg),
+            ApiError::BadResponse(ref msg) => write!(f, "Bad response from ROS API: {}", msg),
+            ApiError::Failure(ref msg) => write!(f, "ROS API call failed: {}", msg),
+            ApiError::Error(ref msg) => write!(f, "ROS API call had bad parameters: {}", msg),
+        }
+    }
+}
+
+/// Call an API method, and unpack the value from the response.
+pub fn call(client: &xmlrpc::Client, method_name: &str, params: Vec<Value>) -> Result<Value, ApiError> {
+    let request = Request {method_name: method_name.to_string(), params: params};
+    match client.execute_request(&request) {
+        Ok(response) => unpack_response(response),
+        Err(err) => Err(ApiError::Transport(err)),
+    }
+}
+
+/// ROS API responses are a (code, statusMessage, value) triple. Check the
+/// code, and return the value if the call succeeded.
+pub fn unpack_response(response: Response) -> Result<Value, ApiError> {
+    let mut triple = match response {
+        Response::Fault {fault_code, fault_string} => return Err(ApiError::BadResponse(format!(
+            "Fault: {} ({})", fault_string, fault_code))),
+        Response::Success {param: Value::Array(triple)} => triple,
+        Response::Success {param} => return Err(ApiError::BadResponse(format!(
+            "Expected (code, statusMessage, value), got {:?}", param))),
+    };
+    if triple.len() != 3 {
+        return Err(ApiError::BadResponse(format!(
+            "Expected (code, statusMessage, value), got {:?}", triple)));
+    }
+
+    let value = triple.pop().unwrap();
+    let status_message = match triple.pop().unwrap() {
+        Value::String(s) => s,
+        x => return Err(ApiError::BadResponse(format!("Expected string statusMessage, got {:?}", x))),
+    };
+    match triple.pop().unwrap() {
+        Value::Int(STATUS_SUCCESS) => Ok(value),
+        Value::Int(STATUS_FAILURE) => Err(ApiError::Failure(status_message)),
+        Value::Int(STATUS_ERROR) => Err(ApiError::Error(status_message)),
+        x => Err(ApiError::BadResponse(format!("Bad status code {:?} ({})", x, status_message))),
+    }
+}
+
+/// Make the (code, statusMessage, value) triple for a response.
+pub fn make_response(code: i32, status_message: &str, value: Value) -> Response {
+    Response::Success {param: Value::Array(vec![
+        Value::Int(code),
+        Value::String(status_message.to_string()),
+        value])}
+}
+
+#[cfg(test)]
+mod tests {
+    use xmlrpc::{Response, Value};
+    use super::{unpack_response, make_response, ApiError};
+
+    #[test]
+    fn test_unpack_response() {
+        let response = make_response(1, "", Value::Int(42));
+        assert_eq!(unpack_response(response), Ok(Value::Int(42)));
+
+        let response = make_response(0, "no such service", Value::Int(0));
+        assert_eq!(unpack_response(response), Err(ApiError::Failure("no such service".to_string())));
+
+        let response = make_response(-1, "bad caller_id", Value::Int(0));
+        assert_eq!(unpack_response(response), Err(ApiError::Error("bad caller_id".to_string())));
+    }
+
+    #[test]
+    fn test_unpack_bad_response() {
+        let response = Response::Success {param: Value::Array(vec![Value::Int(1), Value::Int(2)])};
+        match unpack_response(response) {
+            Err(ApiError::BadResponse(_)) => (),
+            x => panic!("Expected BadResponse, got {:?}", x),
+        };
+
+        let response = make_response(7, "", Value::Int(0));
+        match unpack_response(response) {
+            Err(ApiError::BadResponse(_)) => (),
+            x => panic!("Expected BadResponse, got {:?}", x),
+        };
+    }
+}
@@ -1,39 +1,195 @@
-use std::fmt;
-use std::string;
-
 use xmlrpc;
+use xmlrpc::Value;
+use ros::common::{call, ApiError};
+
+/// Registrations known to the master, as returned by getSystemState. Each
+/// entry is a topic or service name along with the nodes registered for it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SystemState {
+    pub publishers: Vec<(String, Vec<String>)>,
+    pub subscribers: Vec<(String, Vec<String>)>,
+    pub services: Vec<(String, Vec<String>)>,
+}
 
+/// Client for the ROS Master API, making calls on behalf of one node.
+#[derive(Clone)]
 pub struct MasterProxy {
-    master_uri: String,
+    client: xmlrpc::Client,
     caller_id: String,
-    caller_api: String
+    caller_api: String,
 }
 
-
 impl MasterProxy {
-    fn get_published_topics(&self, root: String) -> Vec<string>
-    {
-        execute_xmlrpc_request(self.master_uri.as_slice(),
-            xmlrpc::Request {method_name: getPublishedTopics, params: vec!["/"]
-    }
-
-    fn register_subscriber(&self,  topic: &str, topic_type: &str) {
-        let request = format!(
-            "<?xml version=\"1.0\"?>\n\
-            <methodCall>\n\
-            <methodName>registerSubscriber</methodName>\n\
-            <params>\n\
-            <param>\n\
-            <value><string>{}</string></value>\n\
-            <value><string>{}</string></value>\n\
-            <value><string>{}</string></value>\n\
-            <value><string>{}</string></value>\n\
-            </param>\n\
-            </params>\n\
-            </methodCall>\n", self.caller_id, topic, topic_type, self.caller_api);
-
-        execute_xmlrpc_request(self.master_uri.as_slice(), request.as_slice());
+    /// Create a proxy for the master at master_uri (e.g. the value of
+    /// ROS_MASTER_URI). caller_id is the name of the calling node and
+    /// caller_api is the URI of its slave API server.
+    pub fn new(master_uri: &str, caller_id: &str, caller_api: &str) -> MasterProxy {
+        MasterProxy {
+            client: xmlrpc::Client::new(master_uri),
+            caller_id: caller_id.to_string(),
+            caller_api: caller_api.to_string(),
+        }
+    }
+
+    /// Name of the node that calls are made on behalf of.
+    pub fn caller_id(&self) -> &str {
+        self.caller_id.as_slice()
+    }
+
+    /// URI of the calling node's slave API server.
+    pub fn caller_api(&self) -> &str {
+        self.caller_api.as_slice()
+    }
+
+    /// Register the caller as a provider of a service, reachable at
+    /// service_api (e.g. "rosrpc://host:port").
+    pub fn register_service(&self, service: &str, service_api: &str) -> Result<(), ApiError> {
+        try!(self.call("registerService", vec![
+            string_value(service), string_value(service_api), string_value(self.caller_api.as_slice())]));
+        Ok(())
+    }
+
+    /// Unregister the caller as a provider of a service. Returns the number
+    /// of registrations removed, which is zero if the caller wasn't
+    /// registered for the service.
+    pub fn unregister_service(&self, service: &str, service_api: &str) -> Result<i32, ApiError> {
+        let value = try!(self.call("unregisterService", vec![
+            string_value(service), string_value(service_api)]));
+        validate(validate_xmlrpc_value!(value, i32))
+    }
+
+    /// Subscribe the caller to a topic. Returns the XMLRPC URIs of the nodes
+    /// currently publishing the topic.
+    pub fn register_subscriber(&self, topic: &str, topic_type: &str) -> Result<Vec<String>, ApiError> {
+        let value = try!(self.call("registerSubscriber", vec![
+            string_value(topic), string_value(topic_type), string_value(self.caller_api.as_slice())]));
+        validate(validate_xmlrpc_value!(value, [String]))
+    }
+
+    /// Unsubscribe the caller from a topic. Returns the number of
+    /// registrations removed.
+    pub fn unregister_subscriber(&self, topic: &str) -> Result<i32, ApiError> {
+        let value = try!(self.call("unregisterSubscriber", vec![
+            string_value(topic), string_value(self.caller_api.as_slice())]));
+        validate(validate_xmlrpc_value!(value, i32))
+    }
+
+    /// Register the caller as a publisher of a topic. Returns the XMLRPC URIs
+    /// of the nodes currently subscribed to the topic.
+    pub fn register_publisher(&self, topic: &str, topic_type: &str) -> Result<Vec<String>, ApiError> {
+        let value = try!(self.call("registerPublisher", vec![
+            string_value(topic), string_value(topic_type), string_value(self.caller_api.as_slice())]));
+        validate(validate_xmlrpc_value!(value, [String]))
+    }
+
+    /// Unregister the caller as a publisher of a topic. Returns the number of
+    /// registrations removed.
+    pub fn unregister_publisher(&self, topic: &str) -> Result<i32, ApiError> {
+        let value = try!(self.call("unregisterPublisher", vec![
+            string_value(topic), string_value(self.caller_api.as_slice())]));
+        validate(validate_xmlrpc_value!(value, i32))
+    }
+
+    /// Get the XMLRPC URI of a node.
+    pub fn lookup_node(&self, node_name: &str) -> Result<String, ApiError> {
+        let value = try!(self.call("lookupNode", vec![string_value(node_name)]));
+        validate(validate_xmlrpc_value!(value, String))
+    }
+
+    /// Get the (name, type) of published topics. Only topics whose names
+    /// start with subgraph are returned; use "" for all of them.
+    pub fn get_published_topics(&self, subgraph: &str) -> Result<Vec<(String, String)>, ApiError> {
+        let value = try!(self.call("getPublishedTopics", vec![string_value(subgraph)]));
+        validate(validate_xmlrpc_value!(value, [(String, String)]))
+    }
+
+    /// Get the (name, type) of all topics known to the master.
+    pub fn get_topic_types(&self) -> Result<Vec<(String, String)>, ApiError> {
+        let value = try!(self.call("getTopicTypes", vec![]));
+        validate(validate_xmlrpc_value!(value, [(String, String)]))
+    }
+
+    /// Get all of the registrations known to the master.
+    pub fn get_system_state(&self) -> Result<SystemState, ApiError> {
+        let value = try!(self.call("getSystemState", vec![]));
+        let (publishers, subscribers, services) = try!(validate(validate_xmlrpc_value!(
+            value, ([(String, [String])], [(String, [String])], [(String, [String])]))));
+        Ok(SystemState {publishers: publishers, subscribers: subscribers, services: services})
+    }
+
+    /// Get the URI of the master.
+    pub fn get_uri(&self) -> Result<String, ApiError> {
+        let value = try!(self.call("getUri", vec![]));
+        validate(validate_xmlrpc_value!(value, String))
+    }
+
+    /// Get the URI of the node providing a service (e.g.
+    /// "rosrpc://host:port").
+    pub fn lookup_service(&self, service: &str) -> Result<String, ApiError> {
+        let value = try!(self.call("lookupService", vec![string_value(service)]));
+        validate(validate_xmlrpc_value!(value, String))
+    }
+
+    /// Call a method, with the caller_id added as the first parameter.
+    fn call(&self, method_name: &str, params: Vec<Value>) -> Result<Value, ApiError> {
+        let mut all_params = vec![string_value(self.caller_id.as_slice())];
+        all_params.extend(params.into_iter());
+        call(&self.client, method_name, all_params)
     }
 }
 
+fn string_value(s: &str) -> Value {
+    Value::String(s.to_string())
+}
 
+/// Turn a validation error into an ApiError.
+fn validate<T>(result: Result<T, String>) -> Result<T, ApiError> {
+    result.map_err(|err| ApiError::BadResponse(err))
+}
+
+#[cfg(test)]
+mod tests {
+    use xmlrpc::{Request, Response, Value};
+    use ros::common::make_response;
+    use ros::test_util::FakeMaster;
+    use super::SystemState;
+
+    fn string_list(strings: &[&str]) -> Value {
+        Value::Array(strings.iter().map(|s| Value::String(s.to_string())).collect())
+    }
+
+    /// Answers a few master API calls with canned responses.
+    fn respond(request: &Request) -> Response {
+        match request.method_name.as_slice() {
+            "getUri" => make_response(1, "", Value::String("http://master:11311/".to_string())),
+            "registerSubscriber" => make_response(1, "", string_list(&["http://pub1:1234/"])),
+            "lookupService" => make_response(0, "no provider", Value::String("".to_string())),
+            "getSystemState" => make_response(1, "", Value::Array(vec![
+                Value::Array(vec![
+                    Value::Array(vec![Value::String("/foo".to_string()), string_list(&["/a", "/b"])])]),
+                Value::Array(vec![]),
+                Value::Array(vec![])])),
+            _ => make_response(-1, "unknown method", Value::Int(0)),
+        }
+    }
+
+    #[test]
+    fn test_master_proxy() {
+        let master = FakeMaster::start(respond);
+        let proxy = master.proxy("/test_node");
+
+        assert_eq!(proxy.get_uri(), Ok("http://master:11311/".to_string()));
+        assert_eq!(proxy.register_subscriber("/foo", "std_msgs/String"),
+            Ok(vec!["http://pub1:1234/".to_string()]));
+        assert!(proxy.lookup_service("/bar").is_err());
+        assert_eq!(proxy.get_system_state(), Ok(SystemState {
+            publishers: vec![("/foo".to_string(), vec!["/a".to_string(), "/b".to_string()])],
+            subscribers: vec![],
+            services: vec![],
+        }));
+
+        // Every call is made as the proxy's node
+        assert!(master.calls(0).iter().all(|&(_, ref caller_id)| caller_id.as_slice() == "/test_node"));
+        master.shutdown();
+    }
+}
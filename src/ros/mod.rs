@@ -0,0 +1,27 @@
+pub use ros::master_proxy::{MasterProxy, SystemState};
+pub use ros::param_proxy::ParamProxy;
+pub use ros::param_cache::ParamCache;
+pub use ros::registry::{Registry, ConnectionInfo, Direction};
+pub use ros::slave_api::SlaveApi;
+pub use ros::negotiation::{Protocol, ProtocolOffer, register_subscriber, request_topic};
+pub use ros::publisher::{Publisher, PublisherConfig};
+pub use ros::subscriber::{Subscriber, SubscriberConfig};
+pub use ros::queue::{MessageQueue, DropPolicy};
+pub use ros::service_server::{ServiceServer, ServiceServerConfig};
+pub use ros::service_client::{ServiceClient, ServiceClientConfig, wait_for_service};
+pub use ros::common::ApiError;
+
+mod common;
+mod master_proxy;
+mod param_proxy;
+mod param_cache;
+mod registry;
+mod slave_api;
+mod negotiation;
+mod queue;
+mod publisher;
+mod subscriber;
+mod service_server;
+mod service_client;
+#[cfg(test)]
+mod test_util;
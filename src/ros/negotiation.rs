@@ -0,0 +1,470 @@
+//! Negotiation of topic connections with requestTopic.
+//!
+//! A subscriber calls requestTopic on each publisher, offering the
+//! protocols it supports in order of preference. The publisher picks the
+//! first one that it also supports, and answers with the parameters needed
+//! to connect with it.
+
+use std::collections::BTreeSet;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use tcpros::ConnectionHeader;
+use xmlrpc;
+use xmlrpc::{Value, FromValue, ToValue};
+use ros::common::{call, ApiError};
+use ros::master_proxy::MasterProxy;
+use ros::registry::Registry;
+
+/// How long to wait for a publisher to answer requestTopic, so that one
+/// which hangs doesn't hold up negotiating with the others.
+const REQUEST_TOPIC_TIMEOUT_MS: u64 = 10000;
+
+/// A protocol offered by a subscriber in requestTopic.
+#[derive(Debug, PartialEq, Clone)]
+pub enum ProtocolOffer {
+    Tcpros,
+    /// The subscriber's connection header, and where it receives datagrams
+    Udpros {header: ConnectionHeader, host: String, port: u16, max_datagram_size: i32},
+}
+
+/// A protocol chosen by a publisher in requestTopic, along with where to
+/// connect to it.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Protocol {
+    Tcpros {host: String, port: u16},
+    /// Where the publisher sends datagrams from, the id they will carry,
+    /// and the publisher's connection header
+    Udpros {host: String, port: u16, connection_id: i32, max_datagram_size: i32, header: ConnectionHeader},
+}
+
+impl ProtocolOffer {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ProtocolOffer::Tcpros => "TCPROS",
+            ProtocolOffer::Udpros {..} => "UDPROS",
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        match *self {
+            ProtocolOffer::Tcpros => Value::Array(vec!["TCPROS".to_value()]),
+            ProtocolOffer::Udpros {ref header, ref host, port, max_datagram_size} => Value::Array(vec![
+                "UDPROS".to_value(),
+                Value::Base64(header.encode()),
+                host.to_value(),
+                Value::Int(port as i32),
+                Value::Int(max_datagram_size),
+            ]),
+        }
+    }
+
+    pub fn from_value(value: &Value) -> Result<ProtocolOffer, String> {
+        let params = match *value {
+            Value::Array(ref params) => params,
+            ref x => return Err(format!("Expected protocol offer, found {:?}", x)),
+        };
+        match params.first() {
+            Some(&Value::String(ref name)) if name.as_slice() == "TCPROS" => Ok(ProtocolOffer::Tcpros),
+            Some(&Value::String(ref name)) if name.as_slice() == "UDPROS" => {
+                if params.len() != 5 {
+                    return Err(format!("Expected 5 UDPROS parameters, found {}", params.len()));
+                }
+                Ok(ProtocolOffer::Udpros {
+                    header: try!(header_param(&params[1])),
+                    host: try!(FromValue::from_value(&params[2])),
+                    port: try!(port_param(&params[3])),
+                    max_datagram_size: try!(FromValue::from_value(&params[4])),
+                })
+            },
+            Some(&Value::String(ref name)) => Err(format!("Unsupported protocol {}", name)),
+            x => Err(format!("Expected protocol name, found {:?}", x)),
+        }
+    }
+}
+
+impl Protocol {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Protocol::Tcpros {..} => "TCPROS",
+            Protocol::Udpros {..} => "UDPROS",
+        }
+    }
+
+    pub fn to_value(&self) -> Value {
+        match *self {
+            Protocol::Tcpros {ref host, port} =>
+                Value::Array(vec!["TCPROS".to_value(), host.to_value(), Value::Int(port as i32)]),
+            Protocol::Udpros {ref host, port, connection_id, max_datagram_size, ref header} => Value::Array(vec![
+                "UDPROS".to_value(),
+                host.to_value(),
+                Value::Int(port as i32),
+                Value::Int(connection_id),
+                Value::Int(max_datagram_size),
+                Value::Base64(header.encode()),
+            ]),
+        }
+    }
+
+    pub fn from_value(value: &Value) -> Result<Protocol, String> {
+        let name = match *value {
+            Value::Array(ref params) => match params.first() {
+                Some(&Value::String(ref name)) => name.clone(),
+                _ => return Err(format!("Expected protocol name, found {:?}", params.first())),
+            },
+            ref x => return Err(format!("Expected protocol parameters, found {:?}", x)),
+        };
+        match name.as_slice() {
+            "TCPROS" => {
+                let params = value.clone();
+                let (_, host, port) = try!(validate_xmlrpc_value!(params, (String, String, i32)));
+                if port <= 0 || port > 65535 {
+                    return Err(format!("Bad TCPROS port {}", port));
+                }
+                Ok(Protocol::Tcpros {host: host, port: port as u16})
+            },
+            "UDPROS" => {
+                let params = match *value {
+                    Value::Array(ref params) if params.len() == 6 => params,
+                    _ => return Err("Expected 6 UDPROS parameters".to_string()),
+                };
+                Ok(Protocol::Udpros {
+                    host: try!(FromValue::from_value(&params[1])),
+                    port: try!(port_param(&params[2])),
+                    connection_id: try!(FromValue::from_value(&params[3])),
+                    max_datagram_size: try!(FromValue::from_value(&params[4])),
+                    header: try!(header_param(&params[5])),
+                })
+            },
+            x => Err(format!("Unsupported protocol {}", x)),
+        }
+    }
+}
+
+/// Decode a connection header sent as a base64 parameter.
+fn header_param(value: &Value) -> Result<ConnectionHeader, String> {
+    match *value {
+        Value::Base64(ref bytes) => ConnectionHeader::decode(bytes.as_slice()),
+        ref x => Err(format!("Expected connection header, found {:?}", x)),
+    }
+}
+
+fn port_param(value: &Value) -> Result<u16, String> {
+    let port: i32 = try!(FromValue::from_value(value));
+    match port {
+        port if port > 0 && port <= 65535 => Ok(port as u16),
+        port => Err(format!("Bad port {}", port)),
+    }
+}
+
+/// Choose the first of the offered protocols (a requestTopic parameter)
+/// that accept returns a protocol for. accept returns None for offers that
+/// aren't available, and an error to stop negotiating. Offers of unknown
+/// protocols are skipped.
+pub fn choose_protocol<F>(offers: &[Value], mut accept: F) -> Result<Option<Protocol>, String>
+        where F: FnMut(&ProtocolOffer) -> Result<Option<Protocol>, String> {
+    for offer in offers.iter() {
+        let offer = match ProtocolOffer::from_value(offer) {
+            Ok(offer) => offer,
+            Err(err) => {
+                debug!("Skipping protocol offer: {}", err);
+                continue;
+            },
+        };
+        if let Some(protocol) = try!(accept(&offer)) {
+            return Ok(Some(protocol));
+        }
+    }
+    Ok(None)
+}
+
+/// Call requestTopic on a publisher, and return the protocol it chose.
+pub fn request_topic(publisher_uri: &str, caller_id: &str, topic: &str, offers: &[ProtocolOffer])
+        -> Result<Protocol, ApiError> {
+    let mut client = xmlrpc::Client::new(publisher_uri);
+    client.http_config.timeout_ms = Some(REQUEST_TOPIC_TIMEOUT_MS);
+    let offers = Value::Array(offers.iter().map(|offer| offer.to_value()).collect());
+    let value = try!(call(&client, "requestTopic", vec![caller_id.to_value(), topic.to_value(), offers]));
+    Protocol::from_value(&value).map_err(|err| ApiError::BadResponse(err))
+}
+
+/// Subscribe to a topic, with the master and in the registry, and negotiate
+/// a connection with each of its publishers: those returned by the master,
+/// and new ones as the master sends publisherUpdate calls. on_publisher is
+/// called with each publisher's URI and chosen protocol.
+///
+/// Negotiation happens on a separate thread, so that slow publishers don't
+/// hold up the publisherUpdate call, and on_publisher may not have been
+/// called for the initial publishers when this returns.
+pub fn register_subscriber<F>(
+    master: &MasterProxy,
+    registry: &Registry,
+    topic: &str,
+    topic_type: &str,
+    offers: Vec<ProtocolOffer>,
+    on_publisher: F,
+    ) -> Result<(), ApiError>
+    where F: Fn(&str, Protocol) + Send + Sync + 'static
+{
+    let negotiator = Arc::new(Negotiator {
+        caller_id: master.caller_id().to_string(),
+        topic: topic.to_string(),
+        offers: offers,
+        state: Mutex::new(NegotiationState::new()),
+        on_publisher: Box::new(on_publisher) as Box<Fn(&str, Protocol) + Send + Sync>,
+    });
+
+    // Add the subscription before registering it, so that no updates are
+    // missed
+    let update_negotiator = negotiator.clone();
+    registry.add_subscription(topic, topic_type, move |uris: &[String]| {
+        update_publishers(&update_negotiator, uris);
+    });
+    match master.register_subscriber(topic, topic_type) {
+        Ok(uris) => {
+            update_publishers(&negotiator, uris.as_slice());
+            Ok(())
+        },
+        Err(err) => {
+            registry.remove_subscription(topic);
+            Err(err)
+        },
+    }
+}
+
+struct Negotiator {
+    caller_id: String,
+    topic: String,
+    offers: Vec<ProtocolOffer>,
+    state: Mutex<NegotiationState>,
+    on_publisher: Box<Fn(&str, Protocol) + Send + Sync>,
+}
+
+struct NegotiationState {
+    /// Publishers in the master's latest update
+    publishers: BTreeSet<String>,
+    /// Publishers we have negotiated with successfully
+    negotiated: BTreeSet<String>,
+    /// Publishers waiting to be negotiated with. The first is the one being
+    /// negotiated with, if any. A thread is negotiating whenever this isn't
+    /// empty.
+    queue: Vec<String>,
+}
+
+impl NegotiationState {
+    fn new() -> NegotiationState {
+        NegotiationState {publishers: BTreeSet::new(), negotiated: BTreeSet::new(), queue: vec![]}
+    }
+
+    /// Replace the set of publishers with uris, and queue the ones that
+    /// haven't been negotiated with, including those that failed before.
+    /// Publishers that go away are forgotten, so that we negotiate with them
+    /// again if they come back.
+    fn update(&mut self, uris: &[String]) {
+        self.publishers = uris.iter().map(|uri| uri.clone()).collect();
+        let publishers = &self.publishers;
+        self.negotiated = self.negotiated.iter().filter(|uri| publishers.contains(*uri))
+            .map(|uri| uri.clone()).collect();
+        for uri in uris.iter() {
+            if !self.negotiated.contains(uri) && !self.queue.contains(uri) {
+                self.queue.push(uri.clone());
+            }
+        }
+    }
+}
+
+/// Update the publishers of the negotiator's topic, and start a thread to
+/// negotiate with any new ones if there isn't one running already.
+fn update_publishers(negotiator: &Arc<Negotiator>, uris: &[String]) {
+    let start_thread = {
+        let mut state = negotiator.state.lock().unwrap();
+        let was_running = !state.queue.is_empty();
+        state.update(uris);
+        !was_running && !state.queue.is_empty()
+    };
+    if start_thread {
+        let negotiator = negotiator.clone();
+        thread::spawn(move || negotiator.negotiate());
+    }
+}
+
+impl Negotiator {
+    /// Negotiate with the queued publishers until there are none left.
+    fn negotiate(&self) {
+        loop {
+            let uri = match self.state.lock().unwrap().queue.first() {
+                Some(uri) => uri.clone(),
+                None => return,
+            };
+            let result = request_topic(uri.as_slice(), self.caller_id.as_slice(), self.topic.as_slice(),
+                self.offers.as_slice());
+
+            // Publishers are only recorded once negotiation succeeds, so
+            // failed ones are tried again on the next update
+            let protocol = {
+                let mut state = self.state.lock().unwrap();
+                state.queue.remove(0);
+                match result {
+                    Ok(protocol) => {
+                        if !state.publishers.contains(&uri) {
+                            continue;
+                        }
+                        state.negotiated.insert(uri.clone());
+                        protocol
+                    },
+                    Err(err) => {
+                        warn!("requestTopic for {} to {} failed: {}", self.topic, uri, err);
+                        continue;
+                    },
+                }
+            };
+            (*self.on_publisher)(uri.as_slice(), protocol);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::old_io::TcpListener;
+    use std::old_io::timer::sleep;
+    use std::sync::{Arc, Mutex};
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+    use tcpros::ConnectionHeader;
+    use xmlrpc::Value;
+    use ros::registry::Registry;
+    use ros::test_util::FakeSlave;
+    use super::{choose_protocol, request_topic, update_publishers, Negotiator, NegotiationState};
+    use super::{Protocol, ProtocolOffer};
+
+    fn offer(params: Vec<&str>) -> Value {
+        Value::Array(params.iter().map(|p| Value::String(p.to_string())).collect())
+    }
+
+    fn udpros_offer() -> ProtocolOffer {
+        let mut header = ConnectionHeader::new();
+        header.callerid = Some("/listener".to_string());
+        ProtocolOffer::Udpros {header: header, host: "localhost".to_string(), port: 4321, max_datagram_size: 1500}
+    }
+
+    #[test]
+    fn test_choose_protocol() {
+        fn accept_tcpros(offer: &ProtocolOffer) -> Result<Option<Protocol>, String> {
+            Ok(match *offer {
+                ProtocolOffer::Tcpros => Some(Protocol::Tcpros {host: "localhost".to_string(), port: 1234}),
+                _ => None,
+            })
+        }
+        let tcpros = Protocol::Tcpros {host: "localhost".to_string(), port: 1234};
+
+        assert_eq!(choose_protocol(&[offer(vec!["TCPROS"])], accept_tcpros), Ok(Some(tcpros.clone())));
+        assert_eq!(choose_protocol(&[offer(vec!["UDPROS", "x"]), offer(vec!["TCPROS"])], accept_tcpros),
+            Ok(Some(tcpros.clone())));
+        assert_eq!(choose_protocol(&[udpros_offer().to_value(), offer(vec!["TCPROS"])], accept_tcpros),
+            Ok(Some(tcpros.clone())));
+        assert_eq!(choose_protocol(&[offer(vec!["UDPROS", "x"])], accept_tcpros), Ok(None));
+        assert_eq!(choose_protocol(&[Value::Int(1)], accept_tcpros), Ok(None));
+
+        assert!(choose_protocol(&[offer(vec!["TCPROS"])], |_: &ProtocolOffer| Err("rejected".to_string())).is_err());
+    }
+
+    #[test]
+    fn test_protocol_values() {
+        let tcpros = Protocol::Tcpros {host: "localhost".to_string(), port: 1234};
+        assert_eq!(Protocol::from_value(&tcpros.to_value()), Ok(tcpros));
+        assert!(Protocol::from_value(&offer(vec!["TCPROS"])).is_err());
+        assert_eq!(ProtocolOffer::Tcpros.to_value(), offer(vec!["TCPROS"]));
+
+        let udpros = Protocol::Udpros {
+            host: "localhost".to_string(),
+            port: 1234,
+            connection_id: 3,
+            max_datagram_size: 1500,
+            header: ConnectionHeader::new(),
+        };
+        assert_eq!(Protocol::from_value(&udpros.to_value()), Ok(udpros));
+        assert_eq!(ProtocolOffer::from_value(&udpros_offer().to_value()), Ok(udpros_offer()));
+        assert_eq!(ProtocolOffer::from_value(&ProtocolOffer::Tcpros.to_value()), Ok(ProtocolOffer::Tcpros));
+        assert!(ProtocolOffer::from_value(&offer(vec!["UDPROS", "x"])).is_err());
+    }
+
+    #[test]
+    fn test_negotiation_state() {
+        let mut state = NegotiationState::new();
+        let a = "http://a:1/".to_string();
+        let b = "http://b:1/".to_string();
+
+        state.update(&[a.clone()]);
+        assert_eq!(state.queue, vec![a.clone()]);
+        // Publishers aren't queued twice
+        state.update(&[a.clone(), b.clone()]);
+        assert_eq!(state.queue, vec![a.clone(), b.clone()]);
+
+        // a succeeded and b failed, so only b is tried again
+        state.queue.clear();
+        state.negotiated.insert(a.clone());
+        state.update(&[a.clone(), b.clone()]);
+        assert_eq!(state.queue, vec![b.clone()]);
+
+        // a went away, so it is negotiated with again when it comes back
+        state.queue.clear();
+        state.update(&[b.clone()]);
+        state.queue.clear();
+        state.update(&[a.clone(), b.clone()]);
+        assert_eq!(state.queue, vec![a.clone(), b.clone()]);
+    }
+
+    #[test]
+    fn test_negotiator() {
+        let registry = Registry::new();
+        registry.add_publication("/chatter", "std_msgs/String", 4321);
+        let slave = FakeSlave::start("talker-host", registry);
+        let good_uri = slave.uri();
+        // Nothing listens on this port, since we hold the listener
+        let mut bad_listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let bad_uri = format!("http://{}/", bad_listener.socket_name().unwrap());
+
+        let (tx, rx) = channel();
+        let tx = Mutex::new(tx);
+        let negotiator = Arc::new(Negotiator {
+            caller_id: "/listener".to_string(),
+            topic: "/chatter".to_string(),
+            offers: vec![ProtocolOffer::Tcpros],
+            state: Mutex::new(NegotiationState::new()),
+            on_publisher: Box::new(move |uri: &str, protocol: Protocol| {
+                tx.lock().unwrap().send((uri.to_string(), protocol)).unwrap();
+            }) as Box<Fn(&str, Protocol) + Send + Sync>,
+        });
+
+        update_publishers(&negotiator, &[bad_uri.clone(), good_uri.clone()]);
+        assert_eq!(rx.recv().unwrap(),
+            (good_uri.clone(), Protocol::Tcpros {host: "talker-host".to_string(), port: 4321}));
+        while !negotiator.state.lock().unwrap().queue.is_empty() {
+            sleep(Duration::milliseconds(10));
+        }
+
+        // The failed publisher is queued again by the next update
+        let mut state = negotiator.state.lock().unwrap();
+        assert!(state.negotiated.contains(&good_uri));
+        assert!(!state.negotiated.contains(&bad_uri));
+        state.update(&[bad_uri.clone(), good_uri.clone()]);
+        assert_eq!(state.queue, vec![bad_uri.clone()]);
+        state.queue.clear();
+        drop(state);
+
+        slave.shutdown();
+    }
+
+    #[test]
+    fn test_request_topic() {
+        let registry = Registry::new();
+        registry.add_publication("/chatter", "std_msgs/String", 4321);
+        let slave = FakeSlave::start("talker-host", registry);
+        let publisher_uri = slave.uri();
+
+        assert_eq!(request_topic(publisher_uri.as_slice(), "/listener", "/chatter", &[ProtocolOffer::Tcpros]),
+            Ok(Protocol::Tcpros {host: "talker-host".to_string(), port: 4321}));
+        assert!(request_topic(publisher_uri.as_slice(), "/listener", "/other", &[ProtocolOffer::Tcpros]).is_err());
+
+        slave.shutdown();
+    }
+}
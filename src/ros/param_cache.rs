@@ -0,0 +1,347 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use xmlrpc;
+use xmlrpc::{Request, Response, Value, FromValue};
+use ros::common::{make_response, ApiError, STATUS_ERROR, STATUS_SUCCESS};
+use ros::param_proxy::ParamProxy;
+
+type ParamCallback = Arc<Box<Fn(&str, &Value) + Send + Sync>>;
+
+struct CacheState {
+    /// Latest value of each subscribed parameter. The master represents an
+    /// unset parameter as an empty struct, and so do we.
+    values: BTreeMap<String, Value>,
+    callbacks: BTreeMap<String, Vec<ParamCallback>>,
+    /// Updates received while subscribing to each of these parameters. The
+    /// master may send them before the subscription call returns, and
+    /// they're newer than the value it returns.
+    pending: BTreeMap<String, Vec<(String, Value)>>,
+}
+
+/// Cache of parameter values, kept up to date by the master.
+///
+/// Reading a parameter subscribes to it, and later reads are served from the
+/// cache. The master calls paramUpdate on the node's slave API when a
+/// subscribed parameter changes, so the ParamCache has to be given those
+/// calls: it implements HandlesXmlrpcRequests for paramUpdate, so it can be
+/// served with run_xmlrpc_server at the caller_api of its ParamProxy.
+///
+/// Parameter names must be global (e.g. "/robot/rate").
+#[derive(Clone)]
+pub struct ParamCache {
+    proxy: Arc<ParamProxy>,
+    state: Arc<Mutex<CacheState>>,
+    /// Held while subscribing, so that only one thread subscribes to a
+    /// parameter.
+    subscribe_lock: Arc<Mutex<()>>,
+}
+
+impl ParamCache {
+    pub fn new(proxy: ParamProxy) -> ParamCache {
+        ParamCache {
+            proxy: Arc::new(proxy),
+            state: Arc::new(Mutex::new(CacheState {
+                values: BTreeMap::new(),
+                callbacks: BTreeMap::new(),
+                pending: BTreeMap::new(),
+            })),
+            subscribe_lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Get the value of a parameter, subscribing to it if this is the first
+    /// time it has been read.
+    pub fn get_param<T: FromValue>(&self, key: &str) -> Result<T, ApiError> {
+        let value = try!(self.get_value(key));
+        if value == empty_struct() {
+            return Err(ApiError::Error(format!("Parameter [{}] is not set", key)));
+        }
+        FromValue::from_value(&value).map_err(|err| ApiError::BadResponse(err))
+    }
+
+    /// Call callback with the parameter's name and new value whenever the
+    /// parameter changes, subscribing to it if necessary. An unset
+    /// parameter's value is an empty struct.
+    pub fn add_callback<F>(&self, key: &str, callback: F) -> Result<(), ApiError>
+            where F: Fn(&str, &Value) + Send + Sync + 'static {
+        try!(self.get_value(key));
+        let key = normalize_key(key);
+        let mut state = self.state.lock().unwrap();
+        if !state.callbacks.contains_key(&key) {
+            state.callbacks.insert(key.clone(), vec![]);
+        }
+        let callback = Box::new(callback) as Box<Fn(&str, &Value) + Send + Sync>;
+        state.callbacks.get_mut(&key).unwrap().push(Arc::new(callback));
+        Ok(())
+    }
+
+    /// Stop caching a parameter, unsubscribing from it and removing its
+    /// callbacks.
+    pub fn unsubscribe(&self, key: &str) -> Result<(), ApiError> {
+        let key = normalize_key(key);
+        {
+            let mut state = self.state.lock().unwrap();
+            state.values.remove(&key);
+            state.callbacks.remove(&key);
+        }
+        try!(self.proxy.unsubscribe_param(key.as_slice()));
+        Ok(())
+    }
+
+    /// Update the cache for a paramUpdate call, and notify callbacks of any
+    /// subscribed parameters whose values changed.
+    pub fn update(&self, key: &str, value: &Value) {
+        let mut notifications = vec![];
+        {
+            let mut state = self.state.lock().unwrap();
+            let key = normalize_key(key);
+            for updates in state.pending.values_mut() {
+                updates.push((key.clone(), value.clone()));
+            }
+            let changed = apply_update(&mut state.values, key.as_slice(), value);
+            for (changed_key, changed_value) in changed.into_iter() {
+                if let Some(callbacks) = state.callbacks.get(&changed_key) {
+                    for callback in callbacks.iter() {
+                        notifications.push((callback.clone(), changed_key.clone(), changed_value.clone()));
+                    }
+                }
+            }
+        }
+
+        // Callbacks are called without holding the lock, so that they can
+        // read other parameters.
+        for (callback, changed_key, changed_value) in notifications.into_iter() {
+            (**callback)(changed_key.as_slice(), &changed_value);
+        }
+    }
+
+    fn get_value(&self, key: &str) -> Result<Value, ApiError> {
+        let key = normalize_key(key);
+        if let Some(value) = self.state.lock().unwrap().values.get(&key) {
+            return Ok(value.clone());
+        }
+
+        let _subscribing = self.subscribe_lock.lock().unwrap();
+        {
+            // Another thread may have subscribed while this one waited
+            let mut state = self.state.lock().unwrap();
+            if let Some(value) = state.values.get(&key) {
+                return Ok(value.clone());
+            }
+            state.pending.insert(key.clone(), vec![]);
+        }
+
+        // The state lock isn't held during the call, so that paramUpdate
+        // calls made by the master in the meantime can be recorded
+        let result = self.proxy.subscribe_param(key.as_slice());
+        let mut state = self.state.lock().unwrap();
+        let updates = state.pending.remove(&key).unwrap();
+        let mut new_values = BTreeMap::new();
+        new_values.insert(key.clone(), try!(result));
+        for (update_key, update_value) in updates.into_iter() {
+            apply_update(&mut new_values, update_key.as_slice(), &update_value);
+        }
+        let value = new_values.remove(&key).unwrap();
+        state.values.insert(key, value.clone());
+        Ok(value)
+    }
+}
+
+impl xmlrpc::HandlesXmlrpcRequests for ParamCache {
+    fn handle_request(&self, request: &Request) -> Response {
+        if request.method_name != "paramUpdate" {
+            return make_response(STATUS_ERROR, format!(
+                "Unknown method {}", request.method_name).as_slice(), Value::Int(0));
+        }
+        match (request.params.len(), request.params.get(1)) {
+            (3, Some(&Value::String(ref key))) => {
+                self.update(key.as_slice(), &request.params[2]);
+                make_response(STATUS_SUCCESS, "", Value::Int(0))
+            },
+            _ => make_response(STATUS_ERROR,
+                "Expected (caller_id, parameter_key, parameter_value)", Value::Int(0)),
+        }
+    }
+}
+
+fn empty_struct() -> Value {
+    Value::Struct(BTreeMap::new())
+}
+
+/// Remove any trailing slash, which the master adds to keys in paramUpdate.
+fn normalize_key(key: &str) -> String {
+    match key.len() > 1 && key.ends_with("/") {
+        true => key.slice_to(key.len() - 1).to_string(),
+        false => key.to_string(),
+    }
+}
+
+/// Split a parameter name into its namespace components.
+fn split_key(key: &str) -> Vec<&str> {
+    key.split('/').filter(|s| !s.is_empty()).collect()
+}
+
+/// Set key to value in the cached values, including inside cached
+/// namespaces that contain key, and cached parameters inside key. Returns
+/// the cached keys whose values changed, along with their new values.
+fn apply_update(values: &mut BTreeMap<String, Value>, key: &str, value: &Value) -> Vec<(String, Value)> {
+    let key_parts = split_key(key);
+    let mut changed = vec![];
+    for (cached_key, cached_value) in values.iter_mut() {
+        let cached_parts = split_key(cached_key.as_slice());
+        let new_value = if cached_parts.len() <= key_parts.len() {
+            if cached_parts.as_slice() != key_parts.slice_to(cached_parts.len()) {
+                continue;
+            }
+            // The update is for this parameter or something inside it
+            let mut new_value = cached_value.clone();
+            set_member(&mut new_value, key_parts.slice_from(cached_parts.len()), value);
+            new_value
+        } else {
+            if key_parts.as_slice() != cached_parts.slice_to(key_parts.len()) {
+                continue;
+            }
+            // The update is for a namespace containing this parameter
+            match get_member(value, cached_parts.slice_from(key_parts.len())) {
+                Some(v) => v.clone(),
+                None => empty_struct(),
+            }
+        };
+        if new_value != *cached_value {
+            *cached_value = new_value.clone();
+            changed.push((cached_key.clone(), new_value));
+        }
+    }
+    changed
+}
+
+fn get_member<'a>(value: &'a Value, path: &[&str]) -> Option<&'a Value> {
+    if path.is_empty() {
+        return Some(value);
+    }
+    match *value {
+        Value::Struct(ref members) => match members.get(&path[0].to_string()) {
+            Some(member) => get_member(member, path.slice_from(1)),
+            None => None,
+        },
+        _ => None,
+    }
+}
+
+fn set_member(value: &mut Value, path: &[&str], new_value: &Value) {
+    if path.is_empty() {
+        *value = new_value.clone();
+        return;
+    }
+    // Setting a parameter inside something that isn't a namespace turns it
+    // into one
+    let is_struct = match *value {
+        Value::Struct(_) => true,
+        _ => false,
+    };
+    if !is_struct {
+        *value = empty_struct();
+    }
+    if let Value::Struct(ref mut members) = *value {
+        let name = path[0].to_string();
+        if !members.contains_key(&name) {
+            members.insert(name.clone(), empty_struct());
+        }
+        set_member(members.get_mut(&name).unwrap(), path.slice_from(1), new_value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+    use xmlrpc::{HandlesXmlrpcRequests, Request, Value};
+    use ros::common::make_response;
+    use ros::param_proxy::ParamProxy;
+    use ros::test_util::{start_master, FakeMaster};
+    use super::{apply_update, empty_struct, ParamCache};
+
+    #[test]
+    fn test_apply_update() {
+        let mut ns = BTreeMap::new();
+        ns.insert("a".to_string(), Value::Int(1));
+        let mut values = BTreeMap::new();
+        values.insert("/ns".to_string(), Value::Struct(ns.clone()));
+        values.insert("/ns/a".to_string(), Value::Int(1));
+        values.insert("/other".to_string(), Value::Int(5));
+
+        // Updating a parameter updates the namespace containing it
+        let changed = apply_update(&mut values, "/ns/a", &Value::Int(2));
+        ns.insert("a".to_string(), Value::Int(2));
+        assert_eq!(changed, vec![
+            ("/ns".to_string(), Value::Struct(ns.clone())),
+            ("/ns/a".to_string(), Value::Int(2))]);
+
+        // Deleting a namespace unsets the parameters inside it
+        let changed = apply_update(&mut values, "/ns", &empty_struct());
+        assert_eq!(changed, vec![
+            ("/ns".to_string(), empty_struct()),
+            ("/ns/a".to_string(), empty_struct())]);
+
+        assert_eq!(apply_update(&mut values, "/other", &Value::Int(5)), vec![]);
+    }
+
+    #[test]
+    fn test_param_cache() {
+        let master = start_master();
+        let proxy = ParamProxy::new(master.uri().as_slice(), "/test_node", "http://localhost:1234/");
+        proxy.set_param("/rate", 1).unwrap();
+        let cache = ParamCache::new(proxy);
+
+        // Only the first read subscribes
+        assert_eq!(cache.get_param("/rate"), Ok(1i32));
+        assert_eq!(cache.get_param("/rate"), Ok(1i32));
+        assert_eq!(master.calls(2), vec![
+            ("setParam".to_string(), "".to_string()),
+            ("subscribeParam".to_string(), "/rate".to_string())]);
+
+        let updates = Arc::new(Mutex::new(vec![]));
+        let callback_updates = updates.clone();
+        cache.add_callback("/rate", move |key: &str, value: &Value| {
+            callback_updates.lock().unwrap().push((key.to_string(), value.clone()));
+        }).unwrap();
+
+        let request = Request {method_name: "paramUpdate".to_string(), params: vec![
+            Value::String("/master".to_string()),
+            Value::String("/rate/".to_string()),
+            Value::Int(20)]};
+        assert_eq!(cache.handle_request(&request), make_response(1, "", Value::Int(0)));
+        assert_eq!(cache.get_param("/rate"), Ok(20i32));
+        assert_eq!(*updates.lock().unwrap(), vec![("/rate".to_string(), Value::Int(20))]);
+
+        master.shutdown();
+    }
+
+    #[test]
+    fn test_update_while_subscribing() {
+        // Before returning from subscribeParam, the master sends the cache a
+        // paramUpdate, as if another node set the parameter in the meantime
+        let master_cache: Arc<Mutex<Option<ParamCache>>> = Arc::new(Mutex::new(None));
+        let subscribe_cache = master_cache.clone();
+        let master = FakeMaster::start(move |request: &Request| match (request.method_name.as_slice(),
+                request.params.get(2)) {
+            ("subscribeParam", Some(&Value::String(ref key))) => {
+                if let Some(ref cache) = *subscribe_cache.lock().unwrap() {
+                    cache.update(format!("{}/", key).as_slice(), &Value::Int(2));
+                }
+                make_response(1, "", Value::Int(1))
+            },
+            _ => make_response(-1, "unknown method", Value::Int(0)),
+        });
+        let proxy = ParamProxy::new(master.uri().as_slice(), "/test_node", "http://localhost:1234/");
+        let cache = ParamCache::new(proxy);
+        *master_cache.lock().unwrap() = Some(cache.clone());
+
+        // The update is newer than the value returned by subscribeParam
+        assert_eq!(cache.get_param("/rate"), Ok(2i32));
+        assert_eq!(cache.get_param("/rate"), Ok(2i32));
+
+        *master_cache.lock().unwrap() = None;
+        master.shutdown();
+    }
+}
@@ -0,0 +1,128 @@
+use xmlrpc;
+use xmlrpc::{Value, FromValue, ToValue};
+use ros::common::{call, ApiError};
+
+/// Client for the ROS Parameter Server API, making calls on behalf of one
+/// node. The parameter server is part of the master.
+pub struct ParamProxy {
+    client: xmlrpc::Client,
+    caller_id: String,
+    caller_api: String,
+}
+
+impl ParamProxy {
+    /// Create a proxy for the parameter server of the master at
+    /// master_uri. caller_id is the name of the calling node and caller_api
+    /// is the URI of its slave API server, which receives paramUpdate calls
+    /// for subscribed parameters.
+    pub fn new(master_uri: &str, caller_id: &str, caller_api: &str) -> ParamProxy {
+        ParamProxy {
+            client: xmlrpc::Client::new(master_uri),
+            caller_id: caller_id.to_string(),
+            caller_api: caller_api.to_string(),
+        }
+    }
+
+    /// Get the value of a parameter, decoded as T. Getting a namespace
+    /// returns a struct of all the parameters in it, which can be decoded as
+    /// a BTreeMap. Use xmlrpc::Value as T to get the value undecoded.
+    pub fn get_param<T: FromValue>(&self, key: &str) -> Result<T, ApiError> {
+        let value = try!(self.call("getParam", vec![key.to_value()]));
+        decode(&value)
+    }
+
+    /// Set the value of a parameter. Setting a struct value sets a parameter
+    /// for each of its members in the namespace key.
+    pub fn set_param<T: ToValue>(&self, key: &str, value: T) -> Result<(), ApiError> {
+        try!(self.call("setParam", vec![key.to_value(), value.to_value()]));
+        Ok(())
+    }
+
+    /// Delete a parameter.
+    pub fn delete_param(&self, key: &str) -> Result<(), ApiError> {
+        try!(self.call("deleteParam", vec![key.to_value()]));
+        Ok(())
+    }
+
+    /// Check whether a parameter is set.
+    pub fn has_param(&self, key: &str) -> Result<bool, ApiError> {
+        let value = try!(self.call("hasParam", vec![key.to_value()]));
+        decode(&value)
+    }
+
+    /// Search for a parameter, starting in the caller's namespace and
+    /// working up to the root. Returns the full name of the closest
+    /// parameter found, or None if there isn't one.
+    pub fn search_param(&self, key: &str) -> Result<Option<String>, ApiError> {
+        match self.call("searchParam", vec![key.to_value()]) {
+            Ok(value) => Ok(Some(try!(decode(&value)))),
+            // The master reports an unsuccessful search as an error
+            Err(ApiError::Error(_)) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Get the names of all parameters.
+    pub fn get_param_names(&self) -> Result<Vec<String>, ApiError> {
+        let value = try!(self.call("getParamNames", vec![]));
+        decode(&value)
+    }
+
+    /// Subscribe to a parameter, so that the master calls paramUpdate on the
+    /// caller's slave API when it changes. Returns the current value, which
+    /// is an empty struct if the parameter isn't set.
+    pub fn subscribe_param(&self, key: &str) -> Result<Value, ApiError> {
+        self.call("subscribeParam", vec![self.caller_api.to_value(), key.to_value()])
+    }
+
+    /// Unsubscribe from a parameter. Returns the number of subscriptions
+    /// removed, which is zero if the caller wasn't subscribed.
+    pub fn unsubscribe_param(&self, key: &str) -> Result<i32, ApiError> {
+        let value = try!(self.call("unsubscribeParam", vec![self.caller_api.to_value(), key.to_value()]));
+        decode(&value)
+    }
+
+    /// Call a method, with the caller_id added as the first parameter.
+    fn call(&self, method_name: &str, params: Vec<Value>) -> Result<Value, ApiError> {
+        let mut all_params = vec![self.caller_id.to_value()];
+        all_params.extend(params.into_iter());
+        call(&self.client, method_name, all_params)
+    }
+}
+
+fn decode<T: FromValue>(value: &Value) -> Result<T, ApiError> {
+    FromValue::from_value(value).map_err(|err| ApiError::BadResponse(err))
+}
+
+#[cfg(test)]
+mod tests {
+    use ros::test_util::start_master;
+    use super::ParamProxy;
+
+    #[test]
+    fn test_param_proxy() {
+        let master = start_master();
+        let proxy = ParamProxy::new(master.uri().as_slice(), "/test_node", "http://localhost:1234/");
+
+        assert_eq!(proxy.has_param("/rate"), Ok(false));
+        assert!(proxy.get_param::<i32>("/rate").is_err());
+        assert_eq!(proxy.search_param("/rate"), Ok(None));
+
+        proxy.set_param("/rate", 10).unwrap();
+        proxy.set_param("/names", vec!["a", "b"]).unwrap();
+        assert_eq!(proxy.has_param("/rate"), Ok(true));
+        assert_eq!(proxy.get_param("/rate"), Ok(10i32));
+        assert_eq!(proxy.get_param("/rate"), Ok(10.0f64));
+        assert_eq!(proxy.get_param("/names"), Ok(vec!["a".to_string(), "b".to_string()]));
+        assert!(proxy.get_param::<String>("/rate").is_err());
+        assert_eq!(proxy.search_param("/rate"), Ok(Some("/rate".to_string())));
+
+        // Values with XML markup in them, like robot descriptions, must be
+        // escaped on the way to the server and back
+        let description = "<robot name=\"a&b\"><link name='base'/></robot>";
+        proxy.set_param("/robot_description", description).unwrap();
+        assert_eq!(proxy.get_param("/robot_description"), Ok(description.to_string()));
+
+        master.shutdown();
+    }
+}
@@ -0,0 +1,749 @@
+use std::marker::PhantomData;
+use std::old_io::{Acceptor, Listener, TcpAcceptor, TcpListener, TcpStream, IoErrorKind};
+use std::old_io::net::ip::{SocketAddr, ToSocketAddr};
+use std::old_io::net::udp::UdpSocket;
+use std::old_io::timer::sleep;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use msg::RosMessage;
+use tcpros;
+use tcpros::{ConnectionHeader, SocketOptions};
+use udpros;
+use ros::master_proxy::MasterProxy;
+use ros::negotiation::{Protocol, ProtocolOffer};
+use ros::queue::{MessageQueue, DropPolicy};
+use ros::registry::{Registry, Direction};
+
+/// How long a subscriber has to send its connection header.
+const HANDSHAKE_TIMEOUT_MS: u64 = 10000;
+/// How often an advertised publisher asks the master whether its UDPROS
+/// subscribers are still registered.
+const UDPROS_CHECK_MS: i64 = 1000;
+/// Number of messages in a row which can fail to send to a UDPROS
+/// subscriber before it is dropped.
+const MAX_UDPROS_SEND_FAILURES: usize = 10;
+
+/// Options for a Publisher.
+#[derive(Debug, Clone)]
+pub struct PublisherConfig {
+    /// Keep the last message published, and send it to each new subscriber
+    /// when it connects.
+    pub latching: bool,
+    /// Number of messages that can wait to be sent to each subscriber.
+    /// When a subscriber's queue is full, messages are dropped according to
+    /// drop_policy, so that a slow subscriber can't hold up publishing.
+    /// 0 means the queues are unbounded.
+    pub queue_size: usize,
+    pub drop_policy: DropPolicy,
+    /// Options for the sockets of subscribers' connections. TCP_NODELAY is
+    /// turned on for the subscribers which ask for it in their headers.
+    pub socket: SocketOptions,
+    /// Accept UDPROS connections as well as TCPROS ones. Latched messages
+    /// are only sent over TCPROS. Off by default, since it takes a socket
+    /// and a thread checking the master for each topic.
+    pub udpros: bool,
+}
+
+impl PublisherConfig {
+    pub fn new() -> PublisherConfig {
+        PublisherConfig {
+            latching: false,
+            queue_size: 100,
+            drop_policy: DropPolicy::DropOldest,
+            socket: SocketOptions::new(),
+            udpros: false,
+        }
+    }
+}
+
+/// Queue of serialized messages for a connected subscriber.
+struct SubscriberQueue {
+    queue: Arc<MessageQueue<Arc<Vec<u8>>>>,
+    connection_id: i32,
+}
+
+/// A subscriber which receives messages over UDPROS.
+struct UdpTarget {
+    /// The subscriber's caller id
+    caller_id: String,
+    addr: SocketAddr,
+    /// Both the registry's id for the connection, and the UDPROS id
+    connection_id: i32,
+    max_datagram_size: usize,
+    next_message_id: u8,
+    /// Number of messages in a row which failed to send
+    failures: usize,
+}
+
+/// Queues of serialized messages for each connected subscriber.
+struct Outgoing {
+    queues: Vec<SubscriberQueue>,
+    /// Socket which UDPROS datagrams are sent from
+    udp_socket: Option<UdpSocket>,
+    udp_targets: Vec<UdpTarget>,
+    /// The last message published, if the topic is latched
+    latched: Option<Arc<Vec<u8>>>,
+    /// Number of messages dropped from the queues of all subscribers
+    dropped: u64,
+    /// Set when the publisher is dropped
+    closed: bool,
+}
+
+type SharedOutgoing = Arc<Mutex<Outgoing>>;
+
+/// Publishes messages on a topic to every subscriber that connects to its
+/// TCPROS server, or asks for UDPROS datagrams. The topic is added to the
+/// node's registry, so that the slave API answers requestTopic for it.
+pub struct Publisher<M> {
+    core: PublisherCore,
+    marker: PhantomData<M>,
+}
+
+/// The parts of a Publisher which don't depend on the message type.
+struct PublisherCore {
+    topic: String,
+    port: u16,
+    registry: Registry,
+    /// The master that the publisher is registered with, if it is
+    master: Option<MasterProxy>,
+    latching: bool,
+    acceptor: TcpAcceptor,
+    outgoing: SharedOutgoing,
+}
+
+impl<M: RosMessage> Publisher<M> {
+    /// Start a TCPROS server for a topic, on any free port, and add the
+    /// topic to the registry. caller_id is the name of the publishing node.
+    pub fn new(caller_id: &str, topic: &str, registry: &Registry) -> Result<Publisher<M>, String> {
+        Publisher::with_config(caller_id, topic, registry, PublisherConfig::new())
+    }
+
+    pub fn with_config(caller_id: &str, topic: &str, registry: &Registry, config: PublisherConfig)
+            -> Result<Publisher<M>, String> {
+        let listener = match TcpListener::bind("0.0.0.0:0") {
+            Ok(listener) => listener,
+            Err(err) => return Err(format!("Failed to bind TCPROS server: {}", err)),
+        };
+        let acceptor = match listener.listen() {
+            Ok(acceptor) => acceptor,
+            Err(err) => return Err(format!("Failed to listen for TCPROS connections: {}", err)),
+        };
+        let port = match acceptor.socket_name() {
+            Ok(addr) => addr.port,
+            Err(err) => return Err(format!("Failed to get address of TCPROS server: {}", err)),
+        };
+
+        let udp_socket = match config.udpros {
+            true => match UdpSocket::bind("0.0.0.0:0") {
+                Ok(socket) => Some(socket),
+                Err(err) => return Err(format!("Failed to bind UDPROS socket: {}", err)),
+            },
+            false => None,
+        };
+
+        let outgoing = Arc::new(Mutex::new(Outgoing {
+            queues: vec![],
+            udp_socket: udp_socket,
+            udp_targets: vec![],
+            latched: None,
+            dropped: 0,
+            closed: false,
+        }));
+        {
+            let mut acceptor = acceptor.clone();
+            let caller_id = caller_id.to_string();
+            let topic = topic.to_string();
+            let registry = registry.clone();
+            let config = config.clone();
+            let outgoing = outgoing.clone();
+            thread::spawn(move || {
+                for stream in acceptor.incoming() {
+                    match stream {
+                        Err(err) => {
+                            if err.kind == IoErrorKind::EndOfFile {
+                                break;
+                            }
+                            warn!("Failed to accept TCPROS connection for {}: {}", topic, err);
+                        },
+                        Ok(stream) => {
+                            let caller_id = caller_id.clone();
+                            let topic = topic.clone();
+                            let registry = registry.clone();
+                            let config = config.clone();
+                            let outgoing = outgoing.clone();
+                            thread::spawn(move || {
+                                serve_subscriber::<M>(stream, caller_id, topic, config, registry, outgoing);
+                            });
+                        },
+                    };
+                }
+            });
+        }
+
+        registry.add_publication(topic, M::msg_type(), port);
+        if config.udpros {
+            let udp_port = try!(udp_port(&outgoing));
+            let caller_id = caller_id.to_string();
+            let handler_topic = topic.to_string();
+            let handler_registry = registry.clone();
+            let outgoing = outgoing.clone();
+            registry.set_udpros_handler(topic, move |host: &str, offer: &ProtocolOffer| {
+                add_udp_subscriber::<M>(host, udp_port, offer, caller_id.as_slice(), handler_topic.as_slice(),
+                    &handler_registry, &outgoing)
+            });
+        }
+        Ok(Publisher {
+            core: PublisherCore {
+                topic: topic.to_string(),
+                port: port,
+                registry: registry.clone(),
+                master: None,
+                latching: config.latching,
+                acceptor: acceptor,
+                outgoing: outgoing,
+            },
+            marker: PhantomData,
+        })
+    }
+
+    /// Create a publisher, and register it with the master. It is
+    /// unregistered when it is dropped.
+    pub fn advertise(master: &MasterProxy, registry: &Registry, topic: &str) -> Result<Publisher<M>, String> {
+        Publisher::advertise_with_config(master, registry, topic, PublisherConfig::new())
+    }
+
+    pub fn advertise_with_config(master: &MasterProxy, registry: &Registry, topic: &str, config: PublisherConfig)
+            -> Result<Publisher<M>, String> {
+        let mut publisher = try!(Publisher::with_config(master.caller_id(), topic, registry, config));
+        match master.register_publisher(topic, M::msg_type()) {
+            Ok(_) => {
+                publisher.core.master = Some(master.clone());
+                if publisher.core.outgoing.lock().unwrap().udp_socket.is_some() {
+                    let master = master.clone();
+                    let topic = topic.to_string();
+                    let registry = registry.clone();
+                    let outgoing = publisher.core.outgoing.clone();
+                    thread::spawn(move || check_udp_subscribers(master, topic, registry, outgoing));
+                }
+                Ok(publisher)
+            },
+            Err(err) => Err(format!("Failed to register publisher of {}: {}", topic, err)),
+        }
+    }
+
+    /// Send a message to every connected subscriber.
+    pub fn publish(&self, message: &M) {
+        let bytes = Arc::new(tcpros::encode_message(message));
+        let mut outgoing = self.core.outgoing.lock().unwrap();
+        if self.core.latching {
+            outgoing.latched = Some(bytes.clone());
+        }
+        // Queues are closed when subscribers disconnect
+        outgoing.queues.retain(|q| !q.queue.is_closed());
+
+        let mut dropped = 0;
+        for q in outgoing.queues.iter() {
+            if q.queue.push(bytes.clone()).is_some() {
+                self.core.registry.record_dropped(q.connection_id);
+                dropped += 1;
+            }
+        }
+        outgoing.dropped += dropped;
+
+        let Outgoing {ref mut udp_socket, ref mut udp_targets, ..} = *outgoing;
+        if let Some(ref mut socket) = *udp_socket {
+            for target in udp_targets.iter_mut() {
+                send_datagrams(socket, target, bytes.as_slice(), &self.core.registry);
+            }
+        }
+        // Subscribers which can't be sent to have most likely gone away
+        let registry = &self.core.registry;
+        udp_targets.retain(|target| {
+            if target.failures < MAX_UDPROS_SEND_FAILURES {
+                return true;
+            }
+            warn!("Dropping UDPROS subscriber {} at {}, which can't be sent to", target.caller_id, target.addr);
+            registry.remove_connection(target.connection_id);
+            false
+        });
+    }
+
+    pub fn topic(&self) -> &str {
+        self.core.topic.as_slice()
+    }
+
+    /// Port of the TCPROS server.
+    pub fn port(&self) -> u16 {
+        self.core.port
+    }
+
+    pub fn num_subscribers(&self) -> usize {
+        let outgoing = self.core.outgoing.lock().unwrap();
+        outgoing.queues.iter().filter(|q| !q.queue.is_closed()).count() + outgoing.udp_targets.len()
+    }
+
+    /// Number of messages dropped because subscribers' queues were full.
+    pub fn dropped_messages(&self) -> u64 {
+        self.core.outgoing.lock().unwrap().dropped
+    }
+}
+
+impl Drop for PublisherCore {
+    fn drop(&mut self) {
+        self.registry.remove_publication(self.topic.as_slice());
+        match self.acceptor.close_accept() {
+            Ok(_) => (),
+            Err(err) => warn!("Failed to close TCPROS server for {}: {}", self.topic, err),
+        };
+        // Closing the queues disconnects the subscribers
+        let mut outgoing = self.outgoing.lock().unwrap();
+        outgoing.closed = true;
+        for q in outgoing.queues.drain() {
+            q.queue.close();
+        }
+        // UDPROS subscribers are told with an ERR datagram
+        let Outgoing {ref mut udp_socket, ref mut udp_targets, ..} = *outgoing;
+        for target in udp_targets.drain() {
+            if let Some(ref mut socket) = *udp_socket {
+                let header = udpros::DatagramHeader {
+                    connection_id: target.connection_id as u32,
+                    opcode: udpros::Opcode::Err,
+                    message_id: target.next_message_id,
+                    block: 0,
+                };
+                let _ = socket.send_to(header.encode().as_slice(), target.addr);
+            }
+            self.registry.remove_connection(target.connection_id);
+        }
+        if let Some(ref master) = self.master {
+            match master.unregister_publisher(self.topic.as_slice()) {
+                Ok(_) => (),
+                Err(err) => warn!("Failed to unregister publisher of {}: {}", self.topic, err),
+            };
+        }
+    }
+}
+
+fn udp_port(outgoing: &SharedOutgoing) -> Result<u16, String> {
+    let mut outgoing = outgoing.lock().unwrap();
+    match outgoing.udp_socket {
+        Some(ref mut socket) => match socket.socket_name() {
+            Ok(addr) => Ok(addr.port),
+            Err(err) => Err(format!("Failed to get address of UDPROS socket: {}", err)),
+        },
+        None => Err("No UDPROS socket".to_string()),
+    }
+}
+
+/// Accept a UDPROS offer from a subscriber, and start sending it messages.
+/// Returns the protocol parameters to answer requestTopic with.
+fn add_udp_subscriber<M: RosMessage>(host: &str, udp_port: u16, offer: &ProtocolOffer, caller_id: &str,
+        topic: &str, registry: &Registry, outgoing: &SharedOutgoing) -> Result<Protocol, String> {
+    let (header, subscriber_host, subscriber_port, max_datagram_size) = match *offer {
+        ProtocolOffer::Udpros {ref header, ref host, port, max_datagram_size} =>
+            (header, host, port, max_datagram_size),
+        _ => return Err("Expected a UDPROS offer".to_string()),
+    };
+    let reply = match check_subscriber_header::<M>(caller_id, topic, header) {
+        Ok(reply) => reply,
+        Err(reply) => return Err(reply.error.unwrap()),
+    };
+    if max_datagram_size <= udpros::DATAGRAM_HEADER_BYTES as i32 {
+        return Err(format!("UDPROS datagram size {} is too small", max_datagram_size));
+    }
+    let addr = match (subscriber_host.as_slice(), subscriber_port).to_socket_addr() {
+        Ok(addr) => addr,
+        Err(err) => return Err(format!("Failed to resolve {}: {}", subscriber_host, err)),
+    };
+
+    let subscriber_id = header.callerid.clone().unwrap_or("".to_string());
+    let connection_id = registry.add_connection(topic, Direction::Outbound, subscriber_id.as_slice(), "UDPROS");
+    let mut outgoing = outgoing.lock().unwrap();
+    // A subscriber which asks again, e.g. after reconnecting, replaces its
+    // old connection rather than getting every message twice
+    outgoing.udp_targets.retain(|target| {
+        if target.caller_id == subscriber_id && target.addr == addr {
+            registry.remove_connection(target.connection_id);
+            return false;
+        }
+        true
+    });
+    outgoing.udp_targets.push(UdpTarget {
+        caller_id: subscriber_id,
+        addr: addr,
+        connection_id: connection_id,
+        max_datagram_size: max_datagram_size as usize,
+        next_message_id: 0,
+        failures: 0,
+    });
+    Ok(Protocol::Udpros {
+        host: host.to_string(),
+        port: udp_port,
+        connection_id: connection_id,
+        max_datagram_size: max_datagram_size,
+        header: reply,
+    })
+}
+
+/// Send a serialized message to a UDPROS subscriber. Datagrams which can't
+/// be sent are lost, like those lost on the network.
+fn send_datagrams(socket: &mut UdpSocket, target: &mut UdpTarget, bytes: &[u8], registry: &Registry) {
+    let message_id = target.next_message_id;
+    // Message ids wrap around
+    target.next_message_id = ((message_id as u16 + 1) % 256) as u8;
+    let datagrams = match udpros::fragment(target.connection_id as u32, message_id, bytes,
+            target.max_datagram_size) {
+        Ok(datagrams) => datagrams,
+        Err(err) => {
+            warn!("Failed to send message over UDPROS: {}", err);
+            registry.record_dropped(target.connection_id);
+            return;
+        },
+    };
+    for datagram in datagrams.iter() {
+        if let Err(err) = socket.send_to(datagram.as_slice(), target.addr) {
+            debug!("Failed to send UDPROS datagram to {}: {}", target.addr, err);
+            registry.record_dropped(target.connection_id);
+            target.failures += 1;
+            return;
+        }
+    }
+    target.failures = 0;
+    registry.record_message(target.connection_id, bytes.len());
+}
+
+/// Periodically drop UDPROS subscribers which are no longer registered
+/// with the master, since nothing else tells the publisher that they have
+/// gone. Runs until the publisher is dropped.
+fn check_udp_subscribers(master: MasterProxy, topic: String, registry: Registry, outgoing: SharedOutgoing) {
+    loop {
+        sleep(Duration::milliseconds(UDPROS_CHECK_MS));
+        // Only subscribers which were added before asking the master are
+        // checked, since later ones may have registered since
+        let checked: Vec<i32> = {
+            let outgoing = outgoing.lock().unwrap();
+            if outgoing.closed {
+                return;
+            }
+            outgoing.udp_targets.iter().map(|target| target.connection_id).collect()
+        };
+        if checked.is_empty() {
+            continue;
+        }
+        let state = match master.get_system_state() {
+            Ok(state) => state,
+            Err(err) => {
+                debug!("Failed to check UDPROS subscribers of {}: {}", topic, err);
+                continue;
+            },
+        };
+        let subscribers = match state.subscribers.iter().find(|&&(ref t, _)| *t == topic) {
+            Some(&(_, ref subscribers)) => subscribers.clone(),
+            None => vec![],
+        };
+        let mut outgoing = outgoing.lock().unwrap();
+        outgoing.udp_targets.retain(|target| {
+            if !checked.contains(&target.connection_id) || subscribers.contains(&target.caller_id) {
+                return true;
+            }
+            debug!("UDPROS subscriber {} of {} is no longer registered", target.caller_id, topic);
+            registry.remove_connection(target.connection_id);
+            false
+        });
+    }
+}
+
+/// Check a subscriber's connection header. Returns the header to reply
+/// with, which has the error field set if the subscriber can't be served.
+fn check_subscriber_header<M: RosMessage>(caller_id: &str, topic: &str, header: &ConnectionHeader)
+        -> Result<ConnectionHeader, ConnectionHeader> {
+    let error = match (&header.topic, &header.md5sum, &header.topic_type) {
+        (&None, _, _) => Some("Connection header has no topic".to_string()),
+        (&Some(ref t), _, _) if t.as_slice() != topic => Some(format!("Not a publisher of [{}]", t)),
+        (_, &None, _) => Some("Connection header has no md5sum".to_string()),
+        (_, &Some(ref md5sum), _) if md5sum.as_slice() != "*" && md5sum.as_slice() != M::md5sum() =>
+            Some(format!("md5sums don't match: {} vs. {}", md5sum, M::md5sum())),
+        (_, _, &Some(ref t)) if t.as_slice() != "*" && t.as_slice() != M::msg_type() =>
+            Some(format!("Message types don't match: {} vs. {}", t, M::msg_type())),
+        _ => None,
+    };
+
+    let mut reply = ConnectionHeader::new();
+    reply.callerid = Some(caller_id.to_string());
+    match error {
+        Some(error) => {
+            reply.error = Some(error);
+            Err(reply)
+        },
+        None => {
+            reply.topic = Some(topic.to_string());
+            reply.topic_type = Some(M::msg_type().to_string());
+            reply.md5sum = Some(M::md5sum().to_string());
+            reply.message_definition = Some(M::msg_definition().to_string());
+            Ok(reply)
+        },
+    }
+}
+
+/// Set the socket options on a subscriber's connection, and TCP_NODELAY if
+/// its header asks for it.
+fn configure_stream(stream: &mut TcpStream, header: &ConnectionHeader, socket: &SocketOptions)
+        -> Result<(), String> {
+    try!(socket.apply(stream));
+    if header.tcp_nodelay == Some(true) {
+        if let Err(err) = stream.set_nodelay(true) {
+            return Err(format!("Failed to set TCP_NODELAY: {}", err));
+        }
+    }
+    Ok(())
+}
+
+/// Exchange headers with a subscriber, and then send it messages until it
+/// disconnects or the publisher is dropped.
+fn serve_subscriber<M: RosMessage>(mut stream: TcpStream, caller_id: String, topic: String,
+        config: PublisherConfig, registry: Registry, outgoing: SharedOutgoing) {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT_MS));
+    let header = match tcpros::read_header(&mut stream, tcpros::MAX_HEADER_BYTES) {
+        Ok(header) => header,
+        Err(err) => {
+            warn!("Bad connection header from subscriber to {}: {}", topic, err);
+            return;
+        },
+    };
+    stream.set_read_timeout(None);
+
+    let mut reply = match check_subscriber_header::<M>(caller_id.as_slice(), topic.as_slice(), &header) {
+        Ok(reply) => reply,
+        Err(reply) => {
+            warn!("Rejected subscriber to {}: {}", topic, reply.error.as_ref().unwrap());
+            let _ = tcpros::write_header(&mut stream, &reply);
+            return;
+        },
+    };
+
+    reply.latching = Some(config.latching);
+
+    if let Err(err) = configure_stream(&mut stream, &header, &config.socket) {
+        warn!("Failed to set socket options for subscriber to {}: {}", topic, err);
+    }
+
+    let subscriber_id = header.callerid.unwrap_or("".to_string());
+    let connection_id = registry.add_connection(topic.as_slice(), Direction::Outbound,
+        subscriber_id.as_slice(), "TCPROS");
+
+    // Add the subscriber before replying, so that it gets every message
+    // published after it has the reply. A latched message goes first.
+    let queue = Arc::new(MessageQueue::new(config.queue_size, config.drop_policy));
+    {
+        let mut outgoing = outgoing.lock().unwrap();
+        // The publisher was dropped during the handshake, and has already
+        // closed the queues it knows about
+        if outgoing.closed {
+            registry.remove_connection(connection_id);
+            return;
+        }
+        if let Some(ref latched) = outgoing.latched {
+            queue.push(latched.clone());
+        }
+        outgoing.queues.push(SubscriberQueue {queue: queue.clone(), connection_id: connection_id});
+    }
+
+    if tcpros::write_header(&mut stream, &reply).is_ok() {
+        while let Some(bytes) = queue.pop() {
+            if stream.write_all(bytes.as_slice()).is_err() {
+                break;
+            }
+            registry.record_message(connection_id, bytes.len());
+        }
+    }
+    // Closing the queue makes the next publish remove the subscriber
+    queue.close();
+    registry.remove_connection(connection_id);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::old_io::{Acceptor, Listener, TcpListener, TcpStream};
+    use std::old_io::net::udp::UdpSocket;
+    use std::old_io::timer::sleep;
+    use std::os::unix::AsRawFd;
+    use std::time::Duration;
+    use msg::std_msgs;
+    use tcpros;
+    use tcpros::{ConnectionHeader, SocketOptions};
+    use ros::negotiation::ProtocolOffer;
+    use ros::registry::Registry;
+    use ros::test_util::start_master;
+    use super::{check_subscriber_header, configure_stream, Publisher, PublisherConfig};
+
+    fn subscriber_header(md5sum: &str) -> ConnectionHeader {
+        let mut header = ConnectionHeader::new();
+        header.callerid = Some("/listener".to_string());
+        header.topic = Some("/chatter".to_string());
+        header.topic_type = Some("std_msgs/String".to_string());
+        header.md5sum = Some(md5sum.to_string());
+        header
+    }
+
+    #[test]
+    fn test_check_subscriber_header() {
+        let header = subscriber_header("992ce8a1687cec8c8bd883ec73ca41d1");
+        let reply = check_subscriber_header::<std_msgs::String>("/talker", "/chatter", &header).unwrap();
+        assert_eq!(reply.md5sum, header.md5sum);
+        assert_eq!(reply.message_definition, Some("string data\n".to_string()));
+
+        assert!(check_subscriber_header::<std_msgs::String>("/talker", "/chatter", &subscriber_header("*")).is_ok());
+        assert!(check_subscriber_header::<std_msgs::String>("/talker", "/other", &header).is_err());
+
+        let reply = check_subscriber_header::<std_msgs::String>("/talker", "/chatter", &subscriber_header("1234"));
+        assert!(reply.unwrap_err().error.is_some());
+    }
+
+    #[test]
+    fn test_publish() {
+        let registry = Registry::new();
+        let publisher = Publisher::<std_msgs::String>::new("/talker", "/chatter", &registry).unwrap();
+        assert_eq!(registry.tcpros_port("/chatter"), Some(publisher.port()));
+
+        let mut stream = TcpStream::connect(("127.0.0.1", publisher.port())).unwrap();
+        tcpros::write_header(&mut stream, &subscriber_header("992ce8a1687cec8c8bd883ec73ca41d1")).unwrap();
+        let reply = tcpros::read_header(&mut stream, tcpros::MAX_HEADER_BYTES).unwrap();
+        assert_eq!(reply.callerid, Some("/talker".to_string()));
+        assert_eq!(reply.error, None);
+        assert_eq!(publisher.num_subscribers(), 1);
+
+        let message = std_msgs::String {data: "hello".to_string()};
+        publisher.publish(&message);
+        assert_eq!(tcpros::read_message(&mut stream, 100), Ok(message));
+
+        // A subscriber with the wrong md5sum gets an error
+        let mut stream = TcpStream::connect(("127.0.0.1", publisher.port())).unwrap();
+        tcpros::write_header(&mut stream, &subscriber_header("1234")).unwrap();
+        let reply = tcpros::read_header(&mut stream, tcpros::MAX_HEADER_BYTES).unwrap();
+        assert!(reply.error.is_some());
+
+        drop(publisher);
+        assert_eq!(registry.tcpros_port("/chatter"), None);
+    }
+
+    #[test]
+    fn test_drop_during_handshake() {
+        let registry = Registry::new();
+        let publisher = Publisher::<std_msgs::String>::new("/talker", "/chatter", &registry).unwrap();
+        let mut stream = TcpStream::connect(("127.0.0.1", publisher.port())).unwrap();
+        // Give the publisher time to start waiting for the header
+        sleep(Duration::milliseconds(50));
+        drop(publisher);
+
+        // The connection is closed rather than left waiting for messages
+        tcpros::write_header(&mut stream, &subscriber_header("*")).unwrap();
+        stream.set_read_timeout(Some(5000));
+        assert!(tcpros::read_header(&mut stream, tcpros::MAX_HEADER_BYTES).is_err());
+        assert_eq!(registry.connections().len(), 0);
+    }
+
+    #[test]
+    fn test_latching() {
+        let registry = Registry::new();
+        let mut config = PublisherConfig::new();
+        config.latching = true;
+        let publisher = Publisher::<std_msgs::String>::with_config("/talker", "/map", &registry, config).unwrap();
+        let message = std_msgs::String {data: "latched".to_string()};
+        publisher.publish(&message);
+
+        let mut header = subscriber_header("*");
+        header.topic = Some("/map".to_string());
+        let mut stream = TcpStream::connect(("127.0.0.1", publisher.port())).unwrap();
+        tcpros::write_header(&mut stream, &header).unwrap();
+        let reply = tcpros::read_header(&mut stream, tcpros::MAX_HEADER_BYTES).unwrap();
+        assert_eq!(reply.latching, Some(true));
+        assert_eq!(tcpros::read_message(&mut stream, 100), Ok(message));
+    }
+
+    #[test]
+    fn test_tcp_nodelay() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut acceptor = listener.listen().unwrap();
+        let addr = acceptor.socket_name().unwrap();
+
+        let mut header = subscriber_header("*");
+        header.tcp_nodelay = Some(true);
+        let bytes = header.encode();
+        assert!(bytes.windows(13).any(|field| field == b"tcp_nodelay=1"));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        tcpros::write_header(&mut stream, &header).unwrap();
+        let mut accepted = acceptor.accept().unwrap();
+        let received = tcpros::read_header(&mut accepted, tcpros::MAX_HEADER_BYTES).unwrap();
+        assert!(!tcpros::nodelay(accepted.as_raw_fd()));
+        configure_stream(&mut accepted, &received, &SocketOptions::new()).unwrap();
+        assert!(tcpros::nodelay(accepted.as_raw_fd()));
+
+        // Without the field, Nagle's algorithm stays on
+        let mut stream = TcpStream::connect(addr).unwrap();
+        tcpros::write_header(&mut stream, &subscriber_header("*")).unwrap();
+        let mut accepted = acceptor.accept().unwrap();
+        let received = tcpros::read_header(&mut accepted, tcpros::MAX_HEADER_BYTES).unwrap();
+        configure_stream(&mut accepted, &received, &SocketOptions::new()).unwrap();
+        assert!(!tcpros::nodelay(accepted.as_raw_fd()));
+    }
+
+    #[test]
+    fn test_advertise() {
+        let master = start_master();
+        let registry = Registry::new();
+
+        let publisher = Publisher::<std_msgs::String>::advertise(&master.proxy("/talker"), &registry, "/chatter")
+            .unwrap();
+        assert_eq!(master.calls(1), vec![("registerPublisher".to_string(), "/chatter".to_string())]);
+
+        drop(publisher);
+        assert_eq!(master.calls(1), vec![
+            ("registerPublisher".to_string(), "/chatter".to_string()),
+            ("unregisterPublisher".to_string(), "/chatter".to_string())]);
+        assert_eq!(registry.tcpros_port("/chatter"), None);
+
+        master.shutdown();
+    }
+
+    #[test]
+    fn test_udp_subscribers() {
+        let master = start_master();
+        let listener = master.proxy("/listener");
+        listener.register_subscriber("/chatter", "std_msgs/String").unwrap();
+        let registry = Registry::new();
+        let mut config = PublisherConfig::new();
+        config.udpros = true;
+        let publisher = Publisher::<std_msgs::String>::advertise_with_config(&master.proxy("/talker"), &registry,
+            "/chatter", config).unwrap();
+
+        let mut socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let offer = ProtocolOffer::Udpros {
+            header: subscriber_header("*"),
+            host: "127.0.0.1".to_string(),
+            port: socket.socket_name().unwrap().port,
+            max_datagram_size: 1500,
+        };
+        let handler = registry.udpros_handler("/chatter").unwrap();
+        (**handler)("127.0.0.1", &offer).unwrap();
+        // Asking again replaces the first connection
+        (**handler)("127.0.0.1", &offer).unwrap();
+        assert_eq!(publisher.num_subscribers(), 1);
+        assert_eq!(registry.connections().len(), 1);
+
+        // The subscriber is dropped once the master no longer lists it
+        listener.unregister_subscriber("/chatter").unwrap();
+        for _ in 0..100 {
+            if publisher.num_subscribers() == 0 {
+                break;
+            }
+            sleep(Duration::milliseconds(50));
+        }
+        assert_eq!(publisher.num_subscribers(), 0);
+        assert_eq!(registry.connections().len(), 0);
+
+        drop(publisher);
+        master.shutdown();
+    }
+}
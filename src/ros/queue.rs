@@ -0,0 +1,153 @@
+use std::collections::VecDeque;
+use std::sync::{Mutex, Condvar};
+
+/// Which message to drop when a full queue is pushed to.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DropPolicy {
+    /// Drop the oldest message in the queue, to make room for the new one
+    DropOldest,
+    /// Drop the new message
+    DropNewest,
+}
+
+struct QueueState<T> {
+    items: VecDeque<T>,
+    closed: bool,
+    dropped: u64,
+}
+
+/// A queue of messages between threads. A bounded queue drops messages
+/// rather than blocking when it is full.
+pub struct MessageQueue<T> {
+    state: Mutex<QueueState<T>>,
+    changed: Condvar,
+    capacity: usize,
+    policy: DropPolicy,
+}
+
+impl<T: Send> MessageQueue<T> {
+    /// Create a queue holding up to capacity messages. A capacity of 0
+    /// means the queue is unbounded, as in roscpp.
+    pub fn new(capacity: usize, policy: DropPolicy) -> MessageQueue<T> {
+        MessageQueue {
+            state: Mutex::new(QueueState {items: VecDeque::new(), closed: false, dropped: 0}),
+            changed: Condvar::new(),
+            capacity: capacity,
+            policy: policy,
+        }
+    }
+
+    /// Add a message to the queue. If the queue was full, returns the
+    /// message that was dropped. Messages pushed after the queue is closed
+    /// are discarded.
+    pub fn push(&self, item: T) -> Option<T> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return None;
+        }
+
+        let has_room = self.capacity == 0 || state.items.len() < self.capacity;
+        let dropped = match (has_room, self.policy) {
+            (true, _) => None,
+            (false, DropPolicy::DropOldest) => state.items.pop_front(),
+            (false, DropPolicy::DropNewest) => {
+                state.dropped += 1;
+                return Some(item);
+            },
+        };
+        if dropped.is_some() {
+            state.dropped += 1;
+        }
+        state.items.push_back(item);
+        self.changed.notify_one();
+        dropped
+    }
+
+    /// Take the oldest message from the queue, waiting for one if it is
+    /// empty. Returns None once the queue is closed.
+    pub fn pop(&self) -> Option<T> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.closed {
+                return None;
+            }
+            match state.items.pop_front() {
+                Some(item) => return Some(item),
+                None => state = self.changed.wait(state).unwrap(),
+            };
+        }
+    }
+
+    /// Close the queue, discarding any messages in it and waking up
+    /// threads waiting in pop().
+    pub fn close(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.closed = true;
+        state.items.clear();
+        self.changed.notify_all();
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().closed
+    }
+
+    /// Number of messages dropped because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        self.state.lock().unwrap().dropped
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::thread;
+    use super::{MessageQueue, DropPolicy};
+
+    #[test]
+    fn test_drop_oldest() {
+        let queue = MessageQueue::new(2, DropPolicy::DropOldest);
+        assert_eq!(queue.push(1), None);
+        assert_eq!(queue.push(2), None);
+        assert_eq!(queue.push(3), Some(1));
+        assert_eq!(queue.dropped(), 1);
+        assert_eq!(queue.pop(), Some(2));
+        assert_eq!(queue.pop(), Some(3));
+    }
+
+    #[test]
+    fn test_drop_newest() {
+        let queue = MessageQueue::new(2, DropPolicy::DropNewest);
+        assert_eq!(queue.push(1), None);
+        assert_eq!(queue.push(2), None);
+        assert_eq!(queue.push(3), Some(3));
+        assert_eq!(queue.dropped(), 1);
+        assert_eq!(queue.pop(), Some(1));
+        assert_eq!(queue.pop(), Some(2));
+    }
+
+    #[test]
+    fn test_unbounded() {
+        let queue = MessageQueue::new(0, DropPolicy::DropNewest);
+        for i in 0..1000 {
+            assert_eq!(queue.push(i), None);
+        }
+        assert_eq!(queue.dropped(), 0);
+        assert_eq!(queue.pop(), Some(0));
+    }
+
+    #[test]
+    fn test_close() {
+        let queue = Arc::new(MessageQueue::<i32>::new(1, DropPolicy::DropOldest));
+        let popper = {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                assert_eq!(queue.pop(), None);
+            })
+        };
+        queue.close();
+        assert!(popper.join().is_ok());
+        assert!(queue.is_closed());
+        assert_eq!(queue.push(1), None);
+        assert_eq!(queue.pop(), None);
+    }
+}
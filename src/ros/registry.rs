@@ -0,0 +1,247 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use ros::negotiation::{Protocol, ProtocolOffer};
+
+type PublisherUpdateCallback = Arc<Box<Fn(&[String]) + Send + Sync>>;
+type UdprosHandler = Arc<Box<Fn(&str, &ProtocolOffer) -> Result<Protocol, String> + Send + Sync>>;
+
+/// Direction of a topic connection, from this node's point of view.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Direction {
+    /// We subscribe, and the peer publishes
+    Inbound,
+    /// We publish, and the peer subscribes
+    Outbound,
+}
+
+/// A topic connection to another node, along with its traffic statistics.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ConnectionInfo {
+    pub id: i32,
+    pub topic: String,
+    pub direction: Direction,
+    /// caller_id of the node at the other end
+    pub destination_id: String,
+    /// e.g. "TCPROS"
+    pub transport: String,
+    pub bytes: u64,
+    pub messages: u64,
+    pub dropped: u64,
+}
+
+struct Publication {
+    topic_type: String,
+    tcpros_port: u16,
+    udpros_handler: Option<UdprosHandler>,
+}
+
+struct Subscription {
+    topic_type: String,
+    publisher_uris: Vec<String>,
+    on_publisher_update: PublisherUpdateCallback,
+}
+
+struct RegistryState {
+    publications: BTreeMap<String, Publication>,
+    subscriptions: BTreeMap<String, Subscription>,
+    connections: BTreeMap<i32, ConnectionInfo>,
+    next_connection_id: i32,
+}
+
+/// The topics a node publishes and subscribes to, and its connections to
+/// other nodes for them. Publishers and subscribers keep it up to date, and
+/// the slave API reports from it.
+#[derive(Clone)]
+pub struct Registry {
+    state: Arc<Mutex<RegistryState>>,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry {state: Arc::new(Mutex::new(RegistryState {
+            publications: BTreeMap::new(),
+            subscriptions: BTreeMap::new(),
+            connections: BTreeMap::new(),
+            next_connection_id: 0,
+        }))}
+    }
+
+    /// Record that a topic is published, by a TCPROS server listening on
+    /// tcpros_port.
+    pub fn add_publication(&self, topic: &str, topic_type: &str, tcpros_port: u16) {
+        let publication = Publication {
+            topic_type: topic_type.to_string(),
+            tcpros_port: tcpros_port,
+            udpros_handler: None,
+        };
+        self.state.lock().unwrap().publications.insert(topic.to_string(), publication);
+    }
+
+    pub fn remove_publication(&self, topic: &str) {
+        self.state.lock().unwrap().publications.remove(&topic.to_string());
+    }
+
+    /// Port of the TCPROS server for a published topic, or None if the topic
+    /// isn't published.
+    pub fn tcpros_port(&self, topic: &str) -> Option<u16> {
+        self.state.lock().unwrap().publications.get(&topic.to_string()).map(|p| p.tcpros_port)
+    }
+
+    /// Accept UDPROS connections for a published topic. handler is called
+    /// with this node's host and a subscriber's UDPROS offer, and returns
+    /// the protocol parameters to answer requestTopic with.
+    pub fn set_udpros_handler<F>(&self, topic: &str, handler: F)
+            where F: Fn(&str, &ProtocolOffer) -> Result<Protocol, String> + Send + Sync + 'static {
+        let handler = Box::new(handler) as Box<Fn(&str, &ProtocolOffer) -> Result<Protocol, String> + Send + Sync>;
+        if let Some(publication) = self.state.lock().unwrap().publications.get_mut(&topic.to_string()) {
+            publication.udpros_handler = Some(Arc::new(handler));
+        }
+    }
+
+    /// Handler for UDPROS connections to a published topic, or None if the
+    /// topic isn't published over UDPROS.
+    pub fn udpros_handler(&self, topic: &str) -> Option<UdprosHandler> {
+        match self.state.lock().unwrap().publications.get(&topic.to_string()) {
+            Some(publication) => publication.udpros_handler.clone(),
+            None => None,
+        }
+    }
+
+    /// Record that a topic is subscribed to. on_publisher_update is called
+    /// with the XMLRPC URIs of the topic's publishers whenever the master
+    /// sends a publisherUpdate for it.
+    pub fn add_subscription<F>(&self, topic: &str, topic_type: &str, on_publisher_update: F)
+            where F: Fn(&[String]) + Send + Sync + 'static {
+        let callback = Box::new(on_publisher_update) as Box<Fn(&[String]) + Send + Sync>;
+        let subscription = Subscription {
+            topic_type: topic_type.to_string(),
+            publisher_uris: vec![],
+            on_publisher_update: Arc::new(callback),
+        };
+        self.state.lock().unwrap().subscriptions.insert(topic.to_string(), subscription);
+    }
+
+    pub fn remove_subscription(&self, topic: &str) {
+        self.state.lock().unwrap().subscriptions.remove(&topic.to_string());
+    }
+
+    /// Set the publishers of a subscribed topic, and notify the subscriber.
+    /// Returns false if the topic isn't subscribed to.
+    pub fn update_publishers(&self, topic: &str, publisher_uris: &[String]) -> bool {
+        let callback = {
+            let mut state = self.state.lock().unwrap();
+            match state.subscriptions.get_mut(&topic.to_string()) {
+                Some(subscription) => {
+                    subscription.publisher_uris = publisher_uris.to_vec();
+                    subscription.on_publisher_update.clone()
+                },
+                None => return false,
+            }
+        };
+        // Called without the lock held, so that the subscriber can use the
+        // registry
+        (**callback)(publisher_uris);
+        true
+    }
+
+    /// Latest known publishers of a subscribed topic.
+    pub fn publisher_uris(&self, topic: &str) -> Vec<String> {
+        match self.state.lock().unwrap().subscriptions.get(&topic.to_string()) {
+            Some(subscription) => subscription.publisher_uris.clone(),
+            None => vec![],
+        }
+    }
+
+    /// (topic, type) of each published topic.
+    pub fn publications(&self) -> Vec<(String, String)> {
+        self.state.lock().unwrap().publications.iter()
+            .map(|(topic, p)| (topic.clone(), p.topic_type.clone())).collect()
+    }
+
+    /// (topic, type) of each subscribed topic.
+    pub fn subscriptions(&self) -> Vec<(String, String)> {
+        self.state.lock().unwrap().subscriptions.iter()
+            .map(|(topic, s)| (topic.clone(), s.topic_type.clone())).collect()
+    }
+
+    /// Record a new connection, and return its id.
+    pub fn add_connection(&self, topic: &str, direction: Direction, destination_id: &str, transport: &str) -> i32 {
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_connection_id;
+        state.next_connection_id += 1;
+        state.connections.insert(id, ConnectionInfo {
+            id: id,
+            topic: topic.to_string(),
+            direction: direction,
+            destination_id: destination_id.to_string(),
+            transport: transport.to_string(),
+            bytes: 0,
+            messages: 0,
+            dropped: 0,
+        });
+        id
+    }
+
+    pub fn remove_connection(&self, id: i32) {
+        self.state.lock().unwrap().connections.remove(&id);
+    }
+
+    /// Record a message of the given size sent or received on a connection.
+    pub fn record_message(&self, id: i32, bytes: usize) {
+        if let Some(connection) = self.state.lock().unwrap().connections.get_mut(&id) {
+            connection.bytes += bytes as u64;
+            connection.messages += 1;
+        }
+    }
+
+    /// Record a message dropped on a connection, e.g. because a queue was
+    /// full.
+    pub fn record_dropped(&self, id: i32) {
+        if let Some(connection) = self.state.lock().unwrap().connections.get_mut(&id) {
+            connection.dropped += 1;
+        }
+    }
+
+    pub fn connections(&self) -> Vec<ConnectionInfo> {
+        self.state.lock().unwrap().connections.values().map(|c| c.clone()).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use super::{Registry, Direction};
+
+    #[test]
+    fn test_update_publishers() {
+        let registry = Registry::new();
+        let updates = Arc::new(Mutex::new(vec![]));
+        let callback_updates = updates.clone();
+        registry.add_subscription("/foo", "std_msgs/String", move |uris: &[String]| {
+            callback_updates.lock().unwrap().push(uris.to_vec());
+        });
+
+        let uris = vec!["http://a:1/".to_string()];
+        assert!(registry.update_publishers("/foo", uris.as_slice()));
+        assert!(!registry.update_publishers("/bar", uris.as_slice()));
+        assert_eq!(registry.publisher_uris("/foo"), uris);
+        assert_eq!(*updates.lock().unwrap(), vec![uris.clone()]);
+        assert_eq!(registry.subscriptions(), vec![("/foo".to_string(), "std_msgs/String".to_string())]);
+    }
+
+    #[test]
+    fn test_connections() {
+        let registry = Registry::new();
+        let id = registry.add_connection("/foo", Direction::Outbound, "/listener", "TCPROS");
+        registry.record_message(id, 10);
+        registry.record_message(id, 5);
+        registry.record_dropped(id);
+
+        let connections = registry.connections();
+        assert_eq!(connections.len(), 1);
+        assert_eq!((connections[0].bytes, connections[0].messages, connections[0].dropped), (15, 2, 1));
+
+        registry.remove_connection(id);
+        assert_eq!(registry.connections(), vec![]);
+    }
+}
@@ -0,0 +1,230 @@
+use std::marker::PhantomData;
+use std::old_io::TcpStream;
+use std::old_io::timer::sleep;
+use std::sync::Mutex;
+use std::time::Duration;
+use time;
+use http::Url;
+use msg::{RosMessage, RosService};
+use tcpros;
+use tcpros::ConnectionHeader;
+use ros::master_proxy::MasterProxy;
+
+/// How often wait_for_service asks the master for the service.
+const WAIT_POLL_MS: i64 = 100;
+
+/// Options for a ServiceClient.
+#[derive(Debug, Clone)]
+pub struct ServiceClientConfig {
+    /// Keep the connection open between calls. If the connection fails,
+    /// the call fails, and the next call reconnects.
+    pub persistent: bool,
+    /// Largest response that will be read, in bytes. Calls with longer
+    /// responses fail.
+    pub max_message_bytes: usize,
+    /// How long a call waits for its response, including the connection
+    /// header if it has to connect, before failing. None waits forever.
+    pub timeout_ms: Option<u64>,
+}
+
+impl ServiceClientConfig {
+    pub fn new() -> ServiceClientConfig {
+        ServiceClientConfig {persistent: false, max_message_bytes: tcpros::MAX_MESSAGE_BYTES, timeout_ms: None}
+    }
+}
+
+/// Calls a service. The provider of the service is looked up through the
+/// master for each call, unless the client is persistent, in which case one
+/// connection is kept open and reused for as long as it works.
+pub struct ServiceClient<S> {
+    service: String,
+    master: MasterProxy,
+    config: ServiceClientConfig,
+    /// The open connection of a persistent client
+    connection: Mutex<Option<TcpStream>>,
+    marker: PhantomData<S>,
+}
+
+impl<S: RosService> ServiceClient<S> {
+    /// Create a client which makes a new connection for each call.
+    pub fn new(master: &MasterProxy, service: &str) -> ServiceClient<S> {
+        ServiceClient::with_config(master, service, ServiceClientConfig::new())
+    }
+
+    /// Create a client which keeps its connection open between calls. If
+    /// the connection fails, the call fails, and the next call reconnects.
+    pub fn persistent(master: &MasterProxy, service: &str) -> ServiceClient<S> {
+        let mut config = ServiceClientConfig::new();
+        config.persistent = true;
+        ServiceClient::with_config(master, service, config)
+    }
+
+    pub fn with_config(master: &MasterProxy, service: &str, config: ServiceClientConfig) -> ServiceClient<S> {
+        ServiceClient {
+            service: service.to_string(),
+            master: master.clone(),
+            config: config,
+            connection: Mutex::new(None),
+            marker: PhantomData,
+        }
+    }
+
+    pub fn service(&self) -> &str {
+        self.service.as_slice()
+    }
+
+    /// Call the service. Fails if the service can't be reached, or if the
+    /// server sends back an error.
+    pub fn call(&self, request: &S::Request) -> Result<S::Response, String> {
+        // Holding the lock means calls on a persistent connection take turns
+        let mut connection = self.connection.lock().unwrap();
+        // Read timeouts count from when they are set, so each call sets its own
+        let mut stream = match connection.take() {
+            Some(mut stream) => {
+                stream.set_read_timeout(self.config.timeout_ms);
+                stream
+            },
+            None => try!(self.connect()),
+        };
+
+        if let Err(err) = stream.write_all(tcpros::encode_message(request).as_slice()) {
+            return Err(format!("Failed to send request to service {}: {}", self.service, err));
+        }
+        let response = try!(tcpros::read_service_response(&mut stream, self.config.max_message_bytes));
+
+        // The connection is only kept if it worked
+        if self.config.persistent {
+            *connection = Some(stream);
+        }
+        response.map_err(|err| format!("Service {} failed: {}", self.service, err))
+    }
+
+    /// Look up the service, connect to it, and exchange headers.
+    fn connect(&self) -> Result<TcpStream, String> {
+        let uri = match self.master.lookup_service(self.service.as_slice()) {
+            Ok(uri) => uri,
+            Err(err) => return Err(format!("Failed to look up service {}: {}", self.service, err)),
+        };
+        let url = try!(Url::parse(uri.as_slice()));
+        let mut stream = match TcpStream::connect((url.host.as_slice(), url.port)) {
+            Ok(stream) => stream,
+            Err(err) => return Err(format!("Failed to connect to service {} at {}: {}", self.service, uri, err)),
+        };
+        stream.set_read_timeout(self.config.timeout_ms);
+
+        let mut header = ConnectionHeader::new();
+        header.callerid = Some(self.master.caller_id().to_string());
+        header.service = Some(self.service.clone());
+        header.md5sum = Some(S::md5sum().to_string());
+        header.topic_type = Some(S::srv_type().to_string());
+        header.message_definition = Some(<S::Request as RosMessage>::msg_definition().to_string());
+        if self.config.persistent {
+            header.persistent = Some(true);
+        }
+        if let Err(err) = tcpros::write_header(&mut stream, &header) {
+            return Err(format!("Failed to send connection header: {}", err));
+        }
+        let reply = try!(tcpros::read_header(&mut stream, tcpros::MAX_HEADER_BYTES));
+        match reply.error {
+            Some(error) => Err(format!("Service {} rejected connection: {}", self.service, error)),
+            None => Ok(stream),
+        }
+    }
+}
+
+/// Wait until a service is registered with the master, or timeout has
+/// passed. Returns the URI of the service's provider.
+pub fn wait_for_service(master: &MasterProxy, service: &str, timeout: Duration) -> Result<String, String> {
+    let deadline_ns = time::precise_time_ns() + timeout.num_nanoseconds().unwrap_or(0) as u64;
+    loop {
+        // Errors are expected until the service is registered, and if the
+        // master isn't running yet
+        let err = match master.lookup_service(service) {
+            Ok(uri) => return Ok(uri),
+            Err(err) => err,
+        };
+        if time::precise_time_ns() >= deadline_ns {
+            return Err(format!("Timed out waiting for service {}: {}", service, err));
+        }
+        sleep(Duration::milliseconds(WAIT_POLL_MS));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::old_io::timer::sleep;
+    use std::time::Duration;
+    use msg::std_msgs;
+    use ros::service_server::ServiceServer;
+    use ros::test_util::{start_master, Echo, FakeMaster};
+    use super::{ServiceClient, ServiceClientConfig, wait_for_service};
+
+    fn lookups(master: &FakeMaster) -> usize {
+        master.calls(1).iter().filter(|&&(ref method, _)| method.as_slice() == "lookupService").count()
+    }
+
+    #[test]
+    fn test_service_client() {
+        let master = start_master();
+        let master_proxy = master.proxy("/node");
+
+        assert!(wait_for_service(&master_proxy, "/echo", Duration::milliseconds(50)).is_err());
+        let client = ServiceClient::<Echo>::new(&master_proxy, "/echo");
+        assert!(client.call(&std_msgs::String {data: "hi".to_string()}).is_err());
+
+        let server = ServiceServer::<Echo>::new(&master_proxy, "/echo", |request: std_msgs::String| {
+            match request.data.as_slice() {
+                "" => Err("empty request".to_string()),
+                data => Ok(std_msgs::String {data: format!("echo: {}", data)}),
+            }
+        }).unwrap();
+        assert_eq!(wait_for_service(&master_proxy, "/echo", Duration::seconds(5)),
+            Ok(server.uri().to_string()));
+
+        assert_eq!(client.call(&std_msgs::String {data: "hi".to_string()}),
+            Ok(std_msgs::String {data: "echo: hi".to_string()}));
+        assert!(client.call(&std_msgs::String {data: "".to_string()}).unwrap_err().contains("empty request"));
+
+        // A persistent client only looks the service up once
+        let before = lookups(&master);
+        let client = ServiceClient::<Echo>::persistent(&master_proxy, "/echo");
+        for i in 0..3 {
+            let data = format!("{}", i);
+            assert_eq!(client.call(&std_msgs::String {data: data.clone()}),
+                Ok(std_msgs::String {data: format!("echo: {}", data)}));
+        }
+        assert_eq!(lookups(&master), before + 1);
+
+        drop(server);
+        master.shutdown();
+    }
+
+    #[test]
+    fn test_timeout() {
+        let master = start_master();
+        let master_proxy = master.proxy("/node");
+        let server = ServiceServer::<Echo>::new(&master_proxy, "/echo", |request: std_msgs::String| {
+            if request.data.as_slice() == "slow" {
+                sleep(Duration::milliseconds(1000));
+            }
+            Ok(request)
+        }).unwrap();
+
+        let mut config = ServiceClientConfig::new();
+        config.persistent = true;
+        config.timeout_ms = Some(200);
+        let client = ServiceClient::<Echo>::with_config(&master_proxy, "/echo", config);
+        let fast = std_msgs::String {data: "fast".to_string()};
+        assert_eq!(client.call(&fast), Ok(fast.clone()));
+        assert!(client.call(&std_msgs::String {data: "slow".to_string()}).is_err());
+        // The timed out connection is replaced, and each call gets the
+        // full timeout
+        sleep(Duration::milliseconds(300));
+        assert_eq!(client.call(&fast), Ok(fast.clone()));
+        sleep(Duration::milliseconds(300));
+        assert_eq!(client.call(&fast), Ok(fast.clone()));
+
+        drop(server);
+        master.shutdown();
+    }
+}
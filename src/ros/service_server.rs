@@ -0,0 +1,361 @@
+use std::marker::PhantomData;
+use std::old_io::{Acceptor, Listener, TcpAcceptor, TcpListener, TcpStream, IoErrorKind};
+use std::sync::Arc;
+use std::thread;
+use http::Url;
+use msg::{RosMessage, RosService, read_bytes};
+use tcpros;
+use tcpros::ConnectionHeader;
+use ros::master_proxy::MasterProxy;
+
+/// How long a client has to send its connection header.
+const HANDSHAKE_TIMEOUT_MS: u64 = 10000;
+
+/// Options for a ServiceServer.
+#[derive(Debug, Clone)]
+pub struct ServiceServerConfig {
+    /// Largest request that will be read, in bytes. Clients which send a
+    /// longer one are disconnected.
+    pub max_message_bytes: usize,
+}
+
+impl ServiceServerConfig {
+    pub fn new() -> ServiceServerConfig {
+        ServiceServerConfig {max_message_bytes: tcpros::MAX_MESSAGE_BYTES}
+    }
+}
+
+/// Provides a service, calling a handler with each request. The service is
+/// registered with the master, and unregistered when the server is dropped.
+pub struct ServiceServer<S> {
+    core: ServiceServerCore,
+    marker: PhantomData<S>,
+}
+
+/// The parts of a ServiceServer which don't depend on the service type.
+struct ServiceServerCore {
+    service: String,
+    /// rosrpc URI that clients connect to
+    uri: String,
+    master: MasterProxy,
+    acceptor: TcpAcceptor,
+}
+
+impl<S: RosService> ServiceServer<S> {
+    /// Start a TCPROS server for a service, on any free port of the host in
+    /// the node's caller_api, and register it with the master. handler
+    /// returns the response to a request, or an error string to send back
+    /// to the client.
+    pub fn new<F>(master: &MasterProxy, service: &str, handler: F) -> Result<ServiceServer<S>, String>
+            where F: Fn(S::Request) -> Result<S::Response, String> + Send + Sync + 'static {
+        ServiceServer::with_config(master, service, ServiceServerConfig::new(), handler)
+    }
+
+    pub fn with_config<F>(master: &MasterProxy, service: &str, config: ServiceServerConfig, handler: F)
+            -> Result<ServiceServer<S>, String>
+            where F: Fn(S::Request) -> Result<S::Response, String> + Send + Sync + 'static {
+        let host = try!(Url::parse(master.caller_api())).host;
+        let listener = match TcpListener::bind("0.0.0.0:0") {
+            Ok(listener) => listener,
+            Err(err) => return Err(format!("Failed to bind service server: {}", err)),
+        };
+        let mut acceptor = match listener.listen() {
+            Ok(acceptor) => acceptor,
+            Err(err) => return Err(format!("Failed to listen for service connections: {}", err)),
+        };
+        let port = match acceptor.socket_name() {
+            Ok(addr) => addr.port,
+            Err(err) => return Err(format!("Failed to get address of service server: {}", err)),
+        };
+        let uri = format!("rosrpc://{}:{}", host, port);
+
+        let handler = Arc::new(
+            Box::new(handler) as Box<Fn(S::Request) -> Result<S::Response, String> + Send + Sync>);
+        {
+            let mut acceptor = acceptor.clone();
+            let caller_id = master.caller_id().to_string();
+            let service = service.to_string();
+            thread::spawn(move || {
+                for stream in acceptor.incoming() {
+                    match stream {
+                        Err(err) => {
+                            if err.kind == IoErrorKind::EndOfFile {
+                                break;
+                            }
+                            warn!("Failed to accept connection for service {}: {}", service, err);
+                        },
+                        Ok(stream) => {
+                            let caller_id = caller_id.clone();
+                            let service = service.clone();
+                            let handler = handler.clone();
+                            let config = config.clone();
+                            thread::spawn(move || {
+                                serve_client::<S>(stream, caller_id, service, config, handler);
+                            });
+                        },
+                    };
+                }
+            });
+        }
+
+        if let Err(err) = master.register_service(service, uri.as_slice()) {
+            let _ = acceptor.close_accept();
+            return Err(format!("Failed to register service {}: {}", service, err));
+        }
+        Ok(ServiceServer {
+            core: ServiceServerCore {
+                service: service.to_string(),
+                uri: uri,
+                master: master.clone(),
+                acceptor: acceptor,
+            },
+            marker: PhantomData,
+        })
+    }
+
+    pub fn service(&self) -> &str {
+        self.core.service.as_slice()
+    }
+
+    /// rosrpc URI of the server, as registered with the master.
+    pub fn uri(&self) -> &str {
+        self.core.uri.as_slice()
+    }
+}
+
+impl Drop for ServiceServerCore {
+    fn drop(&mut self) {
+        match self.acceptor.close_accept() {
+            Ok(_) => (),
+            Err(err) => warn!("Failed to close server for service {}: {}", self.service, err),
+        };
+        match self.master.unregister_service(self.service.as_slice(), self.uri.as_slice()) {
+            Ok(_) => (),
+            Err(err) => warn!("Failed to unregister service {}: {}", self.service, err),
+        };
+    }
+}
+
+/// Check a client's connection header. Returns the header to reply with,
+/// which has the error field set if the client can't be served.
+fn check_client_header<S: RosService>(caller_id: &str, service: &str, header: &ConnectionHeader)
+        -> Result<ConnectionHeader, ConnectionHeader> {
+    let error = match (&header.service, &header.md5sum) {
+        (&None, _) => Some("Connection header has no service".to_string()),
+        (&Some(ref s), _) if s.as_slice() != service => Some(format!("Not a provider of [{}]", s)),
+        (_, &None) => Some("Connection header has no md5sum".to_string()),
+        (_, &Some(ref md5sum)) if md5sum.as_slice() != "*" && md5sum.as_slice() != S::md5sum() =>
+            Some(format!("md5sums don't match: {} vs. {}", md5sum, S::md5sum())),
+        _ => None,
+    };
+
+    let mut reply = ConnectionHeader::new();
+    reply.callerid = Some(caller_id.to_string());
+    match error {
+        Some(error) => {
+            reply.error = Some(error);
+            Err(reply)
+        },
+        None => {
+            reply.topic_type = Some(S::srv_type().to_string());
+            reply.md5sum = Some(S::md5sum().to_string());
+            reply.other.insert("request_type".to_string(),
+                <S::Request as RosMessage>::msg_type().to_string());
+            reply.other.insert("response_type".to_string(),
+                <S::Response as RosMessage>::msg_type().to_string());
+            Ok(reply)
+        },
+    }
+}
+
+/// Exchange headers with a client, and then answer its requests: just one,
+/// unless it asked for a persistent connection.
+fn serve_client<S: RosService>(mut stream: TcpStream, caller_id: String, service: String,
+        config: ServiceServerConfig,
+        handler: Arc<Box<Fn(S::Request) -> Result<S::Response, String> + Send + Sync>>) {
+    stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT_MS));
+    let header = match tcpros::read_header(&mut stream, tcpros::MAX_HEADER_BYTES) {
+        Ok(header) => header,
+        Err(err) => {
+            warn!("Bad connection header from client of service {}: {}", service, err);
+            return;
+        },
+    };
+    stream.set_read_timeout(None);
+
+    let reply = match check_client_header::<S>(caller_id.as_slice(), service.as_slice(), &header) {
+        Ok(reply) => reply,
+        Err(reply) => {
+            warn!("Rejected client of service {}: {}", service, reply.error.as_ref().unwrap());
+            let _ = tcpros::write_header(&mut stream, &reply);
+            return;
+        },
+    };
+    if tcpros::write_header(&mut stream, &reply).is_err() {
+        return;
+    }
+    // Probes, e.g. from rosservice, only want the header
+    if header.other.get(&"probe".to_string()).map(|p| p.as_slice()) == Some("1") {
+        return;
+    }
+
+    let persistent = header.persistent.unwrap_or(false);
+    loop {
+        let request_length = match stream.read_le_u32() {
+            Ok(length) => length as usize,
+            // The client closed the connection between requests
+            Err(ref err) if err.kind == IoErrorKind::EndOfFile => return,
+            Err(err) => {
+                warn!("Failed to read request for service {}: {}", service, err);
+                return;
+            },
+        };
+        if request_length > config.max_message_bytes {
+            warn!("Request for service {} is too long ({} bytes, limit is {})",
+                service, request_length, config.max_message_bytes);
+            return;
+        }
+        let bytes = match read_bytes(&mut stream, request_length) {
+            Ok(bytes) => bytes,
+            Err(err) => {
+                warn!("Failed to read request for service {}: {}", service, err);
+                return;
+            },
+        };
+        let request: S::Request = match tcpros::decode_message(bytes) {
+            Ok(request) => request,
+            // The client is told why before the connection is closed
+            Err(err) => {
+                let error = format!("Failed to decode request: {}", err);
+                warn!("Bad request for service {}: {}", service, err);
+                let _ = tcpros::write_service_response::<S::Response, _>(&mut stream, Err(error.as_slice()));
+                return;
+            },
+        };
+        let written = match (*handler)(request) {
+            Ok(response) => tcpros::write_service_response(&mut stream, Ok(&response)),
+            Err(error) => tcpros::write_service_response::<S::Response, _>(&mut stream, Err(error.as_slice())),
+        };
+        if let Err(err) = written {
+            warn!("Failed to send response of service {}: {}", service, err);
+            return;
+        }
+        if !persistent {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::old_io::TcpStream;
+    use http::Url;
+    use msg::{RosService, std_msgs};
+    use tcpros;
+    use tcpros::ConnectionHeader;
+    use ros::test_util::{start_master, Echo};
+    use super::{ServiceServer, ServiceServerConfig};
+
+    fn connect(server: &ServiceServer<Echo>, persistent: bool) -> (TcpStream, ConnectionHeader) {
+        let url = Url::parse(server.uri()).unwrap();
+        let mut stream = TcpStream::connect(("127.0.0.1", url.port)).unwrap();
+        let mut header = ConnectionHeader::new();
+        header.callerid = Some("/client".to_string());
+        header.service = Some("/echo".to_string());
+        header.md5sum = Some(Echo::md5sum().to_string());
+        header.persistent = Some(persistent);
+        tcpros::write_header(&mut stream, &header).unwrap();
+        let reply = tcpros::read_header(&mut stream, tcpros::MAX_HEADER_BYTES).unwrap();
+        (stream, reply)
+    }
+
+    fn call(stream: &mut TcpStream, data: &str) -> Result<Result<std_msgs::String, String>, String> {
+        let request = std_msgs::String {data: data.to_string()};
+        stream.write_all(tcpros::encode_message(&request).as_slice()).unwrap();
+        tcpros::read_service_response(stream, tcpros::MAX_MESSAGE_BYTES)
+    }
+
+    #[test]
+    fn test_service_server() {
+        let master = start_master();
+        let master_proxy = master.proxy("/server");
+
+        let server = ServiceServer::<Echo>::new(&master_proxy, "/echo", |request: std_msgs::String| {
+            match request.data.as_slice() {
+                "" => Err("empty request".to_string()),
+                data => Ok(std_msgs::String {data: format!("echo: {}", data)}),
+            }
+        }).unwrap();
+        assert!(server.uri().starts_with("rosrpc://127.0.0.1:"));
+        assert_eq!(master.calls(2), vec![("registerService".to_string(), server.uri().to_string())]);
+
+        // A persistent connection answers several requests
+        let (mut stream, reply) = connect(&server, true);
+        assert_eq!(reply.error, None);
+        assert_eq!(reply.topic_type, Some("test/Echo".to_string()));
+        assert_eq!(call(&mut stream, "hi"), Ok(Ok(std_msgs::String {data: "echo: hi".to_string()})));
+        assert_eq!(call(&mut stream, ""), Ok(Err("empty request".to_string())));
+        assert_eq!(call(&mut stream, "again"), Ok(Ok(std_msgs::String {data: "echo: again".to_string()})));
+
+        // Other connections are closed after one request
+        let (mut stream, _) = connect(&server, false);
+        assert!(call(&mut stream, "once").unwrap().is_ok());
+        assert!(stream.read_u8().is_err());
+
+        let uri = server.uri().to_string();
+        drop(server);
+        assert_eq!(master.calls(2)[1], ("unregisterService".to_string(), uri));
+        master.shutdown();
+    }
+
+    #[test]
+    fn test_bad_header() {
+        let mut header = ConnectionHeader::new();
+        header.service = Some("/echo".to_string());
+        header.md5sum = Some("wrong".to_string());
+        let reply = super::check_client_header::<Echo>("/server", "/echo", &header);
+        assert!(reply.unwrap_err().error.unwrap().starts_with("md5sums don't match"));
+
+        header.md5sum = Some("*".to_string());
+        assert!(super::check_client_header::<Echo>("/server", "/other", &header).is_err());
+        assert!(super::check_client_header::<Echo>("/server", "/echo", &header).is_ok());
+    }
+
+    #[test]
+    fn test_max_message_bytes() {
+        let master = start_master();
+        let master_proxy = master.proxy("/server");
+
+        let mut config = ServiceServerConfig::new();
+        config.max_message_bytes = 12;
+        let server = ServiceServer::<Echo>::with_config(&master_proxy, "/echo", config,
+            |request: std_msgs::String| Ok(request)).unwrap();
+
+        // "12345678" takes 12 bytes with its length
+        let (mut stream, _) = connect(&server, true);
+        assert!(call(&mut stream, "12345678").unwrap().is_ok());
+        // Longer requests aren't read, so the connection is just closed
+        assert!(call(&mut stream, "123456789").is_err());
+
+        drop(server);
+        master.shutdown();
+    }
+
+    #[test]
+    fn test_malformed_request() {
+        let master = start_master();
+        let master_proxy = master.proxy("/server");
+        let server = ServiceServer::<Echo>::new(&master_proxy, "/echo", |request: std_msgs::String| Ok(request)).unwrap();
+
+        // Too short for the string's length, so the client gets an error
+        let (mut stream, _) = connect(&server, true);
+        stream.write_all(b"\x03\x00\x00\x00abc").unwrap();
+        let response: Result<Result<std_msgs::String, String>, String> =
+            tcpros::read_service_response(&mut stream, tcpros::MAX_MESSAGE_BYTES);
+        assert!(response.unwrap().unwrap_err().starts_with("Failed to decode request"));
+        assert!(stream.read_u8().is_err());
+
+        drop(server);
+        master.shutdown();
+    }
+}
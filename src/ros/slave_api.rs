@@ -0,0 +1,298 @@
+use std::cmp;
+use std::i32;
+use std::sync::Arc;
+use libc;
+use xmlrpc::{HandlesXmlrpcRequests, Request, Response, Value, ToValue};
+use ros::negotiation::{choose_protocol, Protocol, ProtocolOffer};
+use ros::common::{make_response, STATUS_ERROR, STATUS_FAILURE, STATUS_SUCCESS};
+use ros::param_cache::ParamCache;
+use ros::registry::{Registry, Direction};
+
+type ShutdownCallback = Arc<Box<Fn(&str) + Send + Sync>>;
+
+/// Handler for the ROS Slave API, which the master and other nodes call on a
+/// node. Serve it with run_xmlrpc_server at the node's caller_api.
+#[derive(Clone)]
+pub struct SlaveApi {
+    master_uri: String,
+    /// Host name or address that other nodes can reach this node at
+    host: String,
+    registry: Registry,
+    param_cache: Option<ParamCache>,
+    shutdown_callback: Option<ShutdownCallback>,
+}
+
+impl SlaveApi {
+    pub fn new(master_uri: &str, host: &str, registry: Registry) -> SlaveApi {
+        SlaveApi {
+            master_uri: master_uri.to_string(),
+            host: host.to_string(),
+            registry: registry,
+            param_cache: None,
+            shutdown_callback: None,
+        }
+    }
+
+    /// Pass paramUpdate calls on to a ParamCache. Without one they are
+    /// ignored.
+    pub fn set_param_cache(&mut self, param_cache: ParamCache) {
+        self.param_cache = Some(param_cache);
+    }
+
+    /// Call callback with the reason when a shutdown is requested. It is
+    /// called from the XMLRPC server's handler, so it shouldn't wait for the
+    /// server to finish (use ShutdownTrigger rather than ServerHandle).
+    pub fn set_shutdown_callback<F>(&mut self, callback: F)
+            where F: Fn(&str) + Send + Sync + 'static {
+        let callback = Box::new(callback) as Box<Fn(&str) + Send + Sync>;
+        self.shutdown_callback = Some(Arc::new(callback));
+    }
+
+    fn get_bus_stats(&self) -> Response {
+        let connections = self.registry.connections();
+
+        let publish_stats: Vec<Value> = self.registry.publications().into_iter().map(|(topic, _)| {
+            let topic_connections: Vec<_> = connections.iter()
+                .filter(|c| c.topic == topic && c.direction == Direction::Outbound).collect();
+            let bytes_sent = topic_connections.iter().fold(0, |total, c| total + c.bytes);
+            Value::Array(vec![
+                Value::String(topic.clone()),
+                counter_value(bytes_sent),
+                Value::Array(topic_connections.iter().map(|c| Value::Array(vec![
+                    Value::Int(c.id),
+                    counter_value(c.bytes),
+                    counter_value(c.messages),
+                    Value::Boolean(true)])).collect())])
+        }).collect();
+
+        let subscribe_stats: Vec<Value> = self.registry.subscriptions().into_iter().map(|(topic, _)| {
+            Value::Array(vec![
+                Value::String(topic.clone()),
+                Value::Array(connections.iter()
+                    .filter(|c| c.topic == topic && c.direction == Direction::Inbound)
+                    .map(|c| Value::Array(vec![
+                        Value::Int(c.id),
+                        counter_value(c.bytes),
+                        counter_value(c.dropped),
+                        Value::Boolean(true)])).collect())])
+        }).collect();
+
+        // Services don't record statistics
+        let service_stats = Value::Array(vec![Value::Int(0), Value::Int(0), Value::Int(0)]);
+
+        make_response(STATUS_SUCCESS, "", Value::Array(vec![
+            Value::Array(publish_stats), Value::Array(subscribe_stats), service_stats]))
+    }
+
+    fn get_bus_info(&self) -> Response {
+        let bus_info: Vec<Value> = self.registry.connections().into_iter().map(|c| Value::Array(vec![
+            Value::Int(c.id),
+            Value::String(c.destination_id),
+            Value::String(match c.direction {
+                Direction::Inbound => "i".to_string(),
+                Direction::Outbound => "o".to_string(),
+            }),
+            Value::String(c.transport),
+            Value::String(c.topic),
+            Value::Boolean(true)])).collect();
+        make_response(STATUS_SUCCESS, "", Value::Array(bus_info))
+    }
+
+    fn shutdown(&self, params: &[Value]) -> Response {
+        let message = match params.get(1) {
+            Some(&Value::String(ref message)) => message.clone(),
+            _ => "".to_string(),
+        };
+        match self.shutdown_callback {
+            Some(ref callback) => {
+                (**callback)(message.as_slice());
+                make_response(STATUS_SUCCESS, "Shutting down", Value::Int(0))
+            },
+            None => make_response(STATUS_FAILURE, "Shutdown not supported", Value::Int(0)),
+        }
+    }
+
+    fn param_update(&self, request: &Request) -> Response {
+        match self.param_cache {
+            Some(ref param_cache) => param_cache.handle_request(request),
+            None => make_response(STATUS_SUCCESS, "", Value::Int(0)),
+        }
+    }
+
+    fn publisher_update(&self, request: &Request) -> Response {
+        let (_, topic, publishers) = match validate_xmlrpc_request!(request, (String, String, [String])) {
+            Ok(params) => params,
+            Err(err) => return make_response(STATUS_ERROR, err.as_slice(), Value::Int(0)),
+        };
+        match self.registry.update_publishers(topic.as_slice(), publishers.as_slice()) {
+            true => make_response(STATUS_SUCCESS, "", Value::Int(0)),
+            false => make_response(STATUS_FAILURE,
+                format!("Not a subscriber of [{}]", topic).as_slice(), Value::Int(0)),
+        }
+    }
+
+    fn request_topic(&self, params: &[Value]) -> Response {
+        let (topic, protocols) = match (params.get(1), params.get(2)) {
+            (Some(&Value::String(ref topic)), Some(&Value::Array(ref protocols))) => (topic, protocols),
+            _ => return make_response(STATUS_ERROR, "Expected (caller_id, topic, protocols)", Value::Int(0)),
+        };
+        let tcpros_port = match self.registry.tcpros_port(topic.as_slice()) {
+            Some(port) => port,
+            None => return make_response(STATUS_FAILURE,
+                format!("Not a publisher of [{}]", topic).as_slice(), Value::Int(0)),
+        };
+        let udpros_handler = self.registry.udpros_handler(topic.as_slice());
+
+        let chosen = choose_protocol(protocols.as_slice(), |offer: &ProtocolOffer| match *offer {
+            ProtocolOffer::Tcpros => Ok(Some(Protocol::Tcpros {host: self.host.clone(), port: tcpros_port})),
+            ProtocolOffer::Udpros {..} => match udpros_handler {
+                Some(ref handler) => (**handler)(self.host.as_slice(), offer).map(|protocol| Some(protocol)),
+                None => Ok(None),
+            },
+        });
+        match chosen {
+            Ok(Some(protocol)) => make_response(STATUS_SUCCESS, "", protocol.to_value()),
+            Ok(None) => make_response(STATUS_FAILURE, "No supported protocols", Value::Int(0)),
+            Err(err) => make_response(STATUS_FAILURE, err.as_slice(), Value::Int(0)),
+        }
+    }
+}
+
+/// XMLRPC only has 32 bit ints, so counters that have grown past that are
+/// reported as i32::MAX rather than wrapping around to negative numbers.
+fn counter_value(count: u64) -> Value {
+    Value::Int(cmp::min(count, i32::MAX as u64) as i32)
+}
+
+fn topic_list(topics: Vec<(String, String)>) -> Value {
+    Value::Array(topics.into_iter()
+        .map(|(topic, topic_type)| Value::Array(vec![Value::String(topic), Value::String(topic_type)]))
+        .collect())
+}
+
+impl HandlesXmlrpcRequests for SlaveApi {
+    fn handle_request(&self, request: &Request) -> Response {
+        // Every method takes the caller_id as its first parameter
+        match request.params.first() {
+            Some(&Value::String(_)) => (),
+            _ => return make_response(STATUS_ERROR, "Expected caller_id as first parameter", Value::Int(0)),
+        };
+
+        let params = request.params.as_slice();
+        match request.method_name.as_slice() {
+            "getBusStats" => self.get_bus_stats(),
+            "getBusInfo" => self.get_bus_info(),
+            "getMasterUri" => make_response(STATUS_SUCCESS, "", self.master_uri.to_value()),
+            "shutdown" => self.shutdown(params),
+            "getPid" => make_response(STATUS_SUCCESS, "", Value::Int(unsafe { libc::getpid() } as i32)),
+            "getSubscriptions" => make_response(STATUS_SUCCESS, "", topic_list(self.registry.subscriptions())),
+            "getPublications" => make_response(STATUS_SUCCESS, "", topic_list(self.registry.publications())),
+            "paramUpdate" => self.param_update(request),
+            "publisherUpdate" => self.publisher_update(request),
+            "requestTopic" => self.request_topic(params),
+            name => make_response(STATUS_ERROR, format!("Unknown method {}", name).as_slice(), Value::Int(0)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::i32;
+    use std::sync::{Arc, Mutex};
+    use xmlrpc::{HandlesXmlrpcRequests, Request, Value};
+    use ros::common::make_response;
+    use ros::registry::{Registry, Direction};
+    use super::{SlaveApi, counter_value};
+
+    fn request(method_name: &str, mut params: Vec<Value>) -> Request {
+        params.insert(0, Value::String("/caller".to_string()));
+        Request {method_name: method_name.to_string(), params: params}
+    }
+
+    fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    #[test]
+    fn test_topics() {
+        let registry = Registry::new();
+        let slave = SlaveApi::new("http://master:11311/", "talker-host", registry.clone());
+        registry.add_publication("/chatter", "std_msgs/String", 4321);
+
+        assert_eq!(slave.handle_request(&request("getPublications", vec![])), make_response(1, "",
+            Value::Array(vec![Value::Array(vec![string("/chatter"), string("std_msgs/String")])])));
+        assert_eq!(slave.handle_request(&request("getSubscriptions", vec![])),
+            make_response(1, "", Value::Array(vec![])));
+        assert_eq!(slave.handle_request(&request("getMasterUri", vec![])),
+            make_response(1, "", string("http://master:11311/")));
+
+        let protocols = Value::Array(vec![Value::Array(vec![string("TCPROS")])]);
+        assert_eq!(slave.handle_request(&request("requestTopic", vec![string("/chatter"), protocols])),
+            make_response(1, "", Value::Array(vec![string("TCPROS"), string("talker-host"), Value::Int(4321)])));
+        let protocols = Value::Array(vec![Value::Array(vec![string("UDPROS")])]);
+        assert_eq!(slave.handle_request(&request("requestTopic", vec![string("/chatter"), protocols])),
+            make_response(0, "No supported protocols", Value::Int(0)));
+    }
+
+    #[test]
+    fn test_publisher_update() {
+        let registry = Registry::new();
+        let slave = SlaveApi::new("http://master:11311/", "localhost", registry.clone());
+        registry.add_subscription("/chatter", "std_msgs/String", |_: &[String]| ());
+
+        let publishers = Value::Array(vec![string("http://talker:1234/")]);
+        assert_eq!(slave.handle_request(&request("publisherUpdate", vec![string("/chatter"), publishers])),
+            make_response(1, "", Value::Int(0)));
+        assert_eq!(registry.publisher_uris("/chatter"), vec!["http://talker:1234/".to_string()]);
+
+        let id = registry.add_connection("/chatter", Direction::Inbound, "/talker", "TCPROS");
+        assert_eq!(slave.handle_request(&request("getBusInfo", vec![])), make_response(1, "",
+            Value::Array(vec![Value::Array(vec![Value::Int(id), string("/talker"), string("i"),
+                string("TCPROS"), string("/chatter"), Value::Boolean(true)])])));
+    }
+
+    #[test]
+    fn test_bus_stats() {
+        let registry = Registry::new();
+        let slave = SlaveApi::new("http://master:11311/", "localhost", registry.clone());
+        registry.add_publication("/chatter", "std_msgs/String", 4321);
+        let id = registry.add_connection("/chatter", Direction::Outbound, "/listener", "TCPROS");
+        for _ in 0..3 {
+            registry.record_message(id, 1 << 30);
+        }
+
+        let max = Value::Int(i32::MAX);
+        assert_eq!(slave.handle_request(&request("getBusStats", vec![])), make_response(1, "",
+            Value::Array(vec![
+                Value::Array(vec![Value::Array(vec![string("/chatter"), max.clone(),
+                    Value::Array(vec![Value::Array(vec![Value::Int(id), max.clone(), Value::Int(3),
+                        Value::Boolean(true)])])])]),
+                Value::Array(vec![]),
+                Value::Array(vec![Value::Int(0), Value::Int(0), Value::Int(0)])])));
+    }
+
+    #[test]
+    fn test_counter_value() {
+        assert_eq!(counter_value(0), Value::Int(0));
+        assert_eq!(counter_value(i32::MAX as u64), Value::Int(i32::MAX));
+        assert_eq!(counter_value(i32::MAX as u64 + 1), Value::Int(i32::MAX));
+        assert_eq!(counter_value(1 << 40), Value::Int(i32::MAX));
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let mut slave = SlaveApi::new("http://master:11311/", "localhost", Registry::new());
+        let reasons = Arc::new(Mutex::new(vec![]));
+        let callback_reasons = reasons.clone();
+        slave.set_shutdown_callback(move |reason: &str| {
+            callback_reasons.lock().unwrap().push(reason.to_string());
+        });
+
+        slave.handle_request(&request("shutdown", vec![string("test over")]));
+        assert_eq!(*reasons.lock().unwrap(), vec!["test over".to_string()]);
+
+        let bad_request = Request {method_name: "getPid".to_string(), params: vec![]};
+        assert_eq!(slave.handle_request(&bad_request),
+            make_response(-1, "Expected caller_id as first parameter", Value::Int(0)));
+    }
+}
@@ -0,0 +1,652 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::old_io::{IoErrorKind, MemReader};
+use std::old_io::net::ip::SocketAddr;
+use std::old_io::net::udp::UdpSocket;
+use std::old_io::timer::sleep;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{sync_channel, Receiver};
+use std::thread;
+use std::time::Duration;
+use http::Url;
+use msg::{RosMessage, read_bytes};
+use tcpros;
+use tcpros::{ConnectionHeader, SocketOptions, TcpConnection};
+use udpros;
+use udpros::Reassembler;
+use ros::master_proxy::MasterProxy;
+use ros::negotiation::{register_subscriber, request_topic, Protocol, ProtocolOffer};
+use ros::queue::{MessageQueue, DropPolicy};
+use ros::registry::{Registry, Direction};
+
+/// How long to wait before reconnecting to a publisher whose connection
+/// was lost.
+const RECONNECT_DELAY_MS: i64 = 1000;
+
+/// How often threads waiting on UDPROS connections check whether they
+/// should stop.
+const UDPROS_POLL_MS: i64 = 100;
+
+/// Largest UDPROS datagram that can be received.
+const MAX_DATAGRAM_BYTES: usize = 65536;
+
+/// Options for a Subscriber.
+#[derive(Debug, Clone)]
+pub struct SubscriberConfig {
+    /// Number of received messages that can wait for the callback. When
+    /// the queue is full, messages are dropped according to drop_policy,
+    /// so that a slow callback can't make messages pile up in memory.
+    /// 0 means the queue is unbounded.
+    pub queue_size: usize,
+    pub drop_policy: DropPolicy,
+    /// Ask publishers to turn on TCP_NODELAY, so that small messages are
+    /// sent straight away instead of being batched up.
+    pub tcp_nodelay: bool,
+    /// Options for the sockets of connections to publishers.
+    pub socket: SocketOptions,
+    /// Ask publishers for UDPROS connections, falling back to TCPROS. Any
+    /// message with a lost datagram is dropped.
+    pub udpros: bool,
+    /// Largest UDPROS datagram for publishers to send, in bytes
+    pub max_datagram_size: usize,
+    /// Largest message that will be read from a TCPROS connection, in
+    /// bytes. The connection is closed if a publisher sends a longer one.
+    pub max_message_bytes: usize,
+}
+
+impl SubscriberConfig {
+    pub fn new() -> SubscriberConfig {
+        SubscriberConfig {
+            queue_size: 100,
+            drop_policy: DropPolicy::DropOldest,
+            tcp_nodelay: false,
+            socket: SocketOptions::new(),
+            udpros: false,
+            max_datagram_size: 1500,
+            max_message_bytes: tcpros::MAX_MESSAGE_BYTES,
+        }
+    }
+}
+
+/// Messages waiting for the callback, along with the connections they were
+/// received on.
+type IncomingQueue<M> = MessageQueue<(i32, M)>;
+
+struct SubscriberState {
+    shutting_down: bool,
+    /// Publishers that we have a connection thread for
+    active: BTreeSet<String>,
+    /// Open connections, by publisher
+    connections: BTreeMap<String, PublisherConnection>,
+    /// UDPROS connections, by the connection id in their datagrams
+    udp_connections: HashMap<u32, UdpConnection>,
+}
+
+struct PublisherConnection {
+    /// Kept so that the connection can be closed on shutdown. None for
+    /// UDPROS connections.
+    stream: Option<TcpConnection>,
+    latching: bool,
+}
+
+struct UdpConnection {
+    /// The registry's id for the connection
+    connection_id: i32,
+    /// Where the publisher sends datagrams from, taken from the first one,
+    /// since that needn't be the host it gave in requestTopic
+    peer: Option<SocketAddr>,
+    reassembler: Reassembler,
+}
+
+/// Subscribes to a topic, connecting to each of its publishers and calling
+/// a callback with every message received. Publishers are found through the
+/// master, and the subscription is added to the node's registry so that the
+/// slave API passes on publisherUpdate calls. Unsubscribes when dropped.
+pub struct Subscriber<M> {
+    core: SubscriberCore,
+    queue: Arc<IncomingQueue<M>>,
+}
+
+/// The parts of a Subscriber which don't depend on the message type.
+struct SubscriberCore {
+    topic: String,
+    master: MasterProxy,
+    registry: Registry,
+    state: Arc<Mutex<SubscriberState>>,
+    /// Closes the incoming queue, which stops the callback thread
+    close_queue: Box<Fn() + Send + Sync>,
+}
+
+/// Makes connections to publishers, and reads messages from them into the
+/// incoming queue.
+struct Connector<M> {
+    caller_id: String,
+    topic: String,
+    registry: Registry,
+    state: Arc<Mutex<SubscriberState>>,
+    queue: Arc<IncomingQueue<M>>,
+    tcp_nodelay: bool,
+    socket: SocketOptions,
+    max_message_bytes: usize,
+    /// Protocols to offer publishers, in order of preference
+    offers: Vec<ProtocolOffer>,
+}
+
+impl<M: RosMessage> Subscriber<M> {
+    /// Subscribe to a topic, calling callback with each message received.
+    pub fn new<F>(master: &MasterProxy, registry: &Registry, topic: &str, callback: F)
+            -> Result<Subscriber<M>, String>
+            where F: Fn(M) + Send + Sync + 'static {
+        Subscriber::with_config(master, registry, topic, SubscriberConfig::new(), callback)
+    }
+
+    /// Subscribe to a topic with the given options. Messages from all
+    /// publishers go through one queue, and the callback is called with
+    /// them in order from a single thread.
+    pub fn with_config<F>(master: &MasterProxy, registry: &Registry, topic: &str, config: SubscriberConfig,
+            callback: F) -> Result<Subscriber<M>, String>
+            where F: Fn(M) + Send + Sync + 'static {
+        let queue = Arc::new(MessageQueue::new(config.queue_size, config.drop_policy));
+        {
+            let queue = queue.clone();
+            thread::spawn(move || {
+                while let Some((_, message)) = queue.pop() {
+                    callback(message);
+                }
+            });
+        }
+
+        let caller_id = master.caller_id();
+        let mut offers = vec![ProtocolOffer::Tcpros];
+        let udp_socket = match config.udpros {
+            true => {
+                let (socket, host, port) = try!(bind_udpros(master.caller_api()));
+                offers.insert(0, ProtocolOffer::Udpros {
+                    header: subscriber_header::<M>(caller_id, topic, false),
+                    host: host,
+                    port: port,
+                    max_datagram_size: config.max_datagram_size as i32,
+                });
+                Some(socket)
+            },
+            false => None,
+        };
+
+        let state = Arc::new(Mutex::new(SubscriberState {
+            shutting_down: false,
+            active: BTreeSet::new(),
+            connections: BTreeMap::new(),
+            udp_connections: HashMap::new(),
+        }));
+        let connector = Arc::new(Connector {
+            caller_id: master.caller_id().to_string(),
+            topic: topic.to_string(),
+            registry: registry.clone(),
+            state: state.clone(),
+            queue: queue.clone(),
+            tcp_nodelay: config.tcp_nodelay,
+            socket: config.socket.clone(),
+            max_message_bytes: config.max_message_bytes,
+            offers: offers.clone(),
+        });
+        if let Some(socket) = udp_socket {
+            let connector = connector.clone();
+            thread::spawn(move || connector.receive_datagrams(socket));
+        }
+
+        let result = register_subscriber(master, registry, topic, M::msg_type(), offers,
+            move |uri: &str, protocol: Protocol| {
+                Connector::start(connector.clone(), uri, protocol);
+            });
+        match result {
+            Ok(_) => {
+                let close_queue = queue.clone();
+                Ok(Subscriber {
+                    core: SubscriberCore {
+                        topic: topic.to_string(),
+                        master: master.clone(),
+                        registry: registry.clone(),
+                        state: state,
+                        close_queue: Box::new(move || close_queue.close()) as Box<Fn() + Send + Sync>,
+                    },
+                    queue: queue,
+                })
+            },
+            Err(err) => {
+                queue.close();
+                state.lock().unwrap().shutting_down = true;
+                Err(format!("Failed to subscribe to {}: {}", topic, err))
+            },
+        }
+    }
+
+    /// Subscribe to a topic, and receive messages from a channel. The
+    /// channel holds no messages itself, so messages wait in the
+    /// subscriber's queue, and are dropped according to config, until they
+    /// are received.
+    pub fn with_channel(master: &MasterProxy, registry: &Registry, topic: &str, config: SubscriberConfig)
+            -> Result<(Subscriber<M>, Receiver<M>), String> {
+        let (tx, rx) = sync_channel(0);
+        // Senders are not Sync, so the callback shares it through a Mutex
+        let tx = Mutex::new(tx);
+        let subscriber = try!(Subscriber::with_config(master, registry, topic, config, move |message: M| {
+            let _ = tx.lock().unwrap().send(message);
+        }));
+        Ok((subscriber, rx))
+    }
+
+    pub fn topic(&self) -> &str {
+        self.core.topic.as_slice()
+    }
+
+    /// Number of publishers that we are connected to.
+    pub fn num_publishers(&self) -> usize {
+        self.core.state.lock().unwrap().connections.len()
+    }
+
+    /// XMLRPC URIs of the publishers that we are connected to.
+    pub fn publisher_uris(&self) -> Vec<String> {
+        self.core.state.lock().unwrap().connections.keys().map(|uri| uri.clone()).collect()
+    }
+
+    /// Whether the connection to a publisher is latched, i.e. the publisher
+    /// sends its last message when we connect. None if we aren't connected
+    /// to the publisher.
+    pub fn is_latched(&self, publisher_uri: &str) -> Option<bool> {
+        self.core.state.lock().unwrap().connections.get(&publisher_uri.to_string()).map(|c| c.latching)
+    }
+
+    /// Number of messages dropped because the queue was full.
+    pub fn dropped_messages(&self) -> u64 {
+        self.queue.dropped()
+    }
+}
+
+impl Drop for SubscriberCore {
+    fn drop(&mut self) {
+        self.registry.remove_subscription(self.topic.as_slice());
+        {
+            let mut state = self.state.lock().unwrap();
+            state.shutting_down = true;
+            // Closing the streams makes the connection threads exit
+            for (_, connection) in state.connections.iter_mut() {
+                if let Some(ref mut stream) = connection.stream {
+                    let _ = stream.close_read();
+                }
+            }
+        }
+        (*self.close_queue)();
+        match self.master.unregister_subscriber(self.topic.as_slice()) {
+            Ok(_) => (),
+            Err(err) => warn!("Failed to unregister subscriber of {}: {}", self.topic, err),
+        };
+    }
+}
+
+impl<M: RosMessage> Connector<M> {
+    /// Start a thread to read from a publisher, unless there already is one.
+    fn start(connector: Arc<Connector<M>>, uri: &str, protocol: Protocol) {
+        {
+            let mut state = connector.state.lock().unwrap();
+            if state.shutting_down || state.active.contains(&uri.to_string()) {
+                return;
+            }
+            state.active.insert(uri.to_string());
+        }
+        let uri = uri.to_string();
+        thread::spawn(move || {
+            connector.run(uri.as_slice(), protocol);
+            connector.state.lock().unwrap().active.remove(&uri);
+        });
+    }
+
+    /// Read from a publisher, reconnecting when the connection is lost, for
+    /// as long as it is a publisher of the topic.
+    fn run(&self, uri: &str, protocol: Protocol) {
+        let mut protocol = Some(protocol);
+        loop {
+            if let Some(protocol) = protocol.take() {
+                match self.read_from(uri, protocol) {
+                    Ok(_) => debug!("Publisher {} of {} closed its connection", uri, self.topic),
+                    Err(err) => warn!("Connection to publisher {} of {} failed: {}", uri, self.topic, err),
+                };
+            }
+
+            if self.state.lock().unwrap().shutting_down ||
+                    !self.registry.publisher_uris(self.topic.as_slice()).contains(&uri.to_string()) {
+                return;
+            }
+            sleep(Duration::milliseconds(RECONNECT_DELAY_MS));
+
+            protocol = match request_topic(uri, self.caller_id.as_slice(), self.topic.as_slice(),
+                    self.offers.as_slice()) {
+                Ok(protocol) => Some(protocol),
+                Err(err) => {
+                    warn!("requestTopic for {} to {} failed: {}", self.topic, uri, err);
+                    None
+                },
+            };
+        }
+    }
+
+    /// Connect to a publisher, exchange headers, and read messages until
+    /// the connection is closed.
+    fn read_from(&self, uri: &str, protocol: Protocol) -> Result<(), String> {
+        // The socket options are set before connecting, since the receive
+        // buffer size decides the TCP window scale
+        let mut stream = match protocol {
+            Protocol::Tcpros {ref host, port} =>
+                try!(self.socket.connect(host.as_slice(), port, self.tcp_nodelay)),
+            Protocol::Udpros {connection_id, header, ..} =>
+                return self.wait_for_datagrams(uri, connection_id, header),
+        };
+
+        let header = subscriber_header::<M>(self.caller_id.as_slice(), self.topic.as_slice(), self.tcp_nodelay);
+        if let Err(err) = tcpros::write_header(&mut stream, &header) {
+            return Err(format!("Failed to send connection header: {}", err));
+        }
+        let reply = try!(tcpros::read_header(&mut stream, tcpros::MAX_HEADER_BYTES));
+        if let Some(error) = reply.error {
+            return Err(format!("Publisher rejected connection: {}", error));
+        }
+
+        {
+            let mut state = self.state.lock().unwrap();
+            if state.shutting_down {
+                return Ok(());
+            }
+            state.connections.insert(uri.to_string(), PublisherConnection {
+                stream: Some(stream.clone()),
+                latching: reply.latching.unwrap_or(false),
+            });
+        }
+        let publisher_id = reply.callerid.unwrap_or("".to_string());
+        let connection_id = self.registry.add_connection(self.topic.as_slice(), Direction::Inbound,
+            publisher_id.as_slice(), "TCPROS");
+
+        let result = self.read_messages(&mut stream, connection_id);
+
+        self.registry.remove_connection(connection_id);
+        self.state.lock().unwrap().connections.remove(&uri.to_string());
+        result
+    }
+
+    fn read_messages(&self, stream: &mut TcpConnection, connection_id: i32) -> Result<(), String> {
+        loop {
+            let message_length = match stream.read_le_u32() {
+                Ok(length) => length as usize,
+                // The connection was closed between messages
+                Err(_) => return Ok(()),
+            };
+            if message_length > self.max_message_bytes {
+                return Err(format!("Message is too long ({} bytes, limit is {})",
+                    message_length, self.max_message_bytes));
+            }
+            let bytes = match read_bytes(stream, message_length) {
+                Ok(bytes) => bytes,
+                Err(err) => return Err(format!("Failed to read message: {}", err)),
+            };
+            self.registry.record_message(connection_id, message_length + 4);
+
+            let message = try!(tcpros::decode_message(bytes));
+            self.enqueue(connection_id, message);
+        }
+    }
+
+    fn enqueue(&self, connection_id: i32, message: M) {
+        // The dropped message may have come from another publisher
+        if let Some((dropped_id, _)) = self.queue.push((connection_id, message)) {
+            self.registry.record_dropped(dropped_id);
+        }
+    }
+
+    /// Receive messages from a publisher over UDPROS, until it closes the
+    /// connection or stops being a publisher of the topic. The datagrams are
+    /// read by receive_datagrams.
+    fn wait_for_datagrams(&self, uri: &str, udpros_id: i32, reply: ConnectionHeader) -> Result<(), String> {
+        if let Some(error) = reply.error {
+            return Err(format!("Publisher rejected connection: {}", error));
+        }
+        let key = udpros_id as u32;
+        let publisher_id = reply.callerid.unwrap_or("".to_string());
+        let connection_id = self.registry.add_connection(self.topic.as_slice(), Direction::Inbound,
+            publisher_id.as_slice(), "UDPROS");
+        {
+            let mut state = self.state.lock().unwrap();
+            // Each publisher picks its own ids, so they can clash. Asking
+            // again gets a new one.
+            if state.udp_connections.contains_key(&key) {
+                drop(state);
+                self.registry.remove_connection(connection_id);
+                return Err(format!("UDPROS connection id {} is already used by another publisher", key));
+            }
+            state.connections.insert(uri.to_string(), PublisherConnection {
+                stream: None,
+                latching: reply.latching.unwrap_or(false),
+            });
+            state.udp_connections.insert(key, UdpConnection {
+                connection_id: connection_id,
+                peer: None,
+                reassembler: Reassembler::new(),
+            });
+        }
+
+        loop {
+            sleep(Duration::milliseconds(UDPROS_POLL_MS));
+            {
+                let state = self.state.lock().unwrap();
+                // The connection is removed when the publisher closes it, and
+                // its id may since have been taken by another publisher
+                let open = state.udp_connections.get(&key).map(|c| c.connection_id) == Some(connection_id);
+                if state.shutting_down || !open {
+                    break;
+                }
+            }
+            if !self.registry.publisher_uris(self.topic.as_slice()).contains(&uri.to_string()) {
+                break;
+            }
+        }
+
+        self.registry.remove_connection(connection_id);
+        let mut state = self.state.lock().unwrap();
+        if state.udp_connections.get(&key).map(|c| c.connection_id) == Some(connection_id) {
+            state.udp_connections.remove(&key);
+        }
+        state.connections.remove(&uri.to_string());
+        Ok(())
+    }
+
+    /// Read datagrams from the socket which all UDPROS publishers send to,
+    /// until the subscriber is dropped.
+    fn receive_datagrams(&self, mut socket: UdpSocket) {
+        socket.set_read_timeout(Some(UDPROS_POLL_MS as u64));
+        let mut buf = [0u8; MAX_DATAGRAM_BYTES];
+        loop {
+            if self.state.lock().unwrap().shutting_down {
+                return;
+            }
+            match socket.recv_from(&mut buf) {
+                Ok((length, addr)) => self.handle_datagram(buf.slice_to(length), addr),
+                Err(ref err) if err.kind == IoErrorKind::TimedOut => (),
+                Err(err) => {
+                    warn!("Failed to receive UDPROS datagram for {}: {}", self.topic, err);
+                    return;
+                },
+            };
+        }
+    }
+
+    fn handle_datagram(&self, datagram: &[u8], publisher_addr: SocketAddr) {
+        let header = match udpros::DatagramHeader::decode(datagram) {
+            Ok(header) => header,
+            Err(err) => {
+                debug!("Bad UDPROS datagram for {}: {}", self.topic, err);
+                return;
+            },
+        };
+        let key = header.connection_id;
+        let (connection_id, result, newly_dropped) = {
+            let mut state = self.state.lock().unwrap();
+            match state.udp_connections.get_mut(&key) {
+                Some(connection) => {
+                    // Datagrams with the same id from anywhere else aren't
+                    // from this publisher
+                    match connection.peer {
+                        Some(peer) if peer != publisher_addr => return,
+                        Some(_) => (),
+                        None => connection.peer = Some(publisher_addr),
+                    }
+                    let dropped = connection.reassembler.dropped();
+                    let result = connection.reassembler.add(datagram);
+                    (connection.connection_id, result, connection.reassembler.dropped() - dropped)
+                },
+                // Not a connection of ours, or one which has been closed
+                None => return,
+            }
+        };
+        for _ in 0..newly_dropped {
+            self.registry.record_dropped(connection_id);
+        }
+
+        match result {
+            Ok(Some(bytes)) => {
+                self.registry.record_message(connection_id, bytes.len());
+                let length = bytes.len();
+                match tcpros::read_message(&mut MemReader::new(bytes), length) {
+                    Ok(message) => self.enqueue(connection_id, message),
+                    Err(err) => warn!("Bad UDPROS message for {}: {}", self.topic, err),
+                };
+            },
+            Ok(None) => (),
+            Err(err) => {
+                debug!("UDPROS connection for {} closed: {}", self.topic, err);
+                self.state.lock().unwrap().udp_connections.remove(&key);
+            },
+        };
+    }
+}
+
+/// The connection header that a subscriber sends to publishers.
+fn subscriber_header<M: RosMessage>(caller_id: &str, topic: &str, tcp_nodelay: bool) -> ConnectionHeader {
+    let mut header = ConnectionHeader::new();
+    header.callerid = Some(caller_id.to_string());
+    header.topic = Some(topic.to_string());
+    header.topic_type = Some(M::msg_type().to_string());
+    header.md5sum = Some(M::md5sum().to_string());
+    header.message_definition = Some(M::msg_definition().to_string());
+    if tcp_nodelay {
+        header.tcp_nodelay = Some(true);
+    }
+    header
+}
+
+/// Bind a socket for publishers to send UDPROS datagrams to, on the host of
+/// the node's slave API. Returns the socket, host and port.
+fn bind_udpros(caller_api: &str) -> Result<(UdpSocket, String, u16), String> {
+    let host = try!(Url::parse(caller_api)).host;
+    let mut socket = match UdpSocket::bind("0.0.0.0:0") {
+        Ok(socket) => socket,
+        Err(err) => return Err(format!("Failed to bind UDPROS socket: {}", err)),
+    };
+    match socket.socket_name() {
+        Ok(addr) => Ok((socket, host, addr.port)),
+        Err(err) => Err(format!("Failed to get address of UDPROS socket: {}", err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::old_io::timer::sleep;
+    use std::time::Duration;
+    use msg::std_msgs;
+    use ros::publisher::{Publisher, PublisherConfig};
+    use ros::registry::Registry;
+    use ros::test_util::{start_master, FakeSlave};
+    use super::{subscriber_header, Subscriber, SubscriberConfig};
+
+    #[test]
+    fn test_subscriber_header() {
+        let header = subscriber_header::<std_msgs::String>("/listener", "/chatter", true);
+        assert!(header.encode().windows(13).any(|field| field == b"tcp_nodelay=1"));
+        let header = subscriber_header::<std_msgs::String>("/listener", "/chatter", false);
+        assert_eq!(header.tcp_nodelay, None);
+    }
+
+    #[test]
+    fn test_subscribe() {
+        // The publishing node
+        let publisher_registry = Registry::new();
+        let publisher = Publisher::<std_msgs::String>::new("/talker", "/chatter", &publisher_registry).unwrap();
+        let slave = FakeSlave::start("127.0.0.1", publisher_registry);
+
+        let master = start_master();
+        slave.proxy(&master, "/talker").register_publisher("/chatter", "std_msgs/String").unwrap();
+
+        // The subscribing node
+        let master_proxy = master.proxy("/listener");
+        let registry = Registry::new();
+        let (subscriber, messages) =
+            Subscriber::<std_msgs::String>::with_channel(&master_proxy, &registry, "/chatter",
+                SubscriberConfig::new()).unwrap();
+
+        // Wait for the connection
+        for _ in 0..100 {
+            if subscriber.num_publishers() > 0 {
+                break;
+            }
+            sleep(Duration::milliseconds(10));
+        }
+        assert_eq!(subscriber.num_publishers(), 1);
+        assert_eq!(publisher.num_subscribers(), 1);
+        assert_eq!(subscriber.is_latched(slave.uri().as_slice()), Some(false));
+
+        let message = std_msgs::String {data: "hello".to_string()};
+        publisher.publish(&message);
+        assert_eq!(messages.recv().unwrap(), message);
+
+        drop(subscriber);
+        slave.shutdown();
+        master.shutdown();
+    }
+
+    #[test]
+    fn test_subscribe_udpros() {
+        let publisher_registry = Registry::new();
+        let mut publisher_config = PublisherConfig::new();
+        publisher_config.udpros = true;
+        let publisher = Publisher::<std_msgs::String>::with_config("/talker", "/chatter", &publisher_registry,
+            publisher_config).unwrap();
+        // The datagrams come from 127.0.0.1 rather than the host given in
+        // requestTopic
+        let slave = FakeSlave::start("127.0.0.2", publisher_registry.clone());
+
+        let master = start_master();
+        slave.proxy(&master, "/talker").register_publisher("/chatter", "std_msgs/String").unwrap();
+
+        let master_proxy = master.proxy("/listener");
+        let registry = Registry::new();
+        let mut config = SubscriberConfig::new();
+        config.udpros = true;
+        // Small enough that messages are split across datagrams
+        config.max_datagram_size = 20;
+        let (subscriber, messages) =
+            Subscriber::<std_msgs::String>::with_channel(&master_proxy, &registry, "/chatter", config).unwrap();
+
+        for _ in 0..100 {
+            if subscriber.num_publishers() > 0 {
+                break;
+            }
+            sleep(Duration::milliseconds(10));
+        }
+        assert_eq!(subscriber.num_publishers(), 1);
+        assert_eq!(registry.connections()[0].transport.as_slice(), "UDPROS");
+        assert_eq!(publisher_registry.connections()[0].transport.as_slice(), "UDPROS");
+
+        let message = std_msgs::String {data: "a message which takes several datagrams".to_string()};
+        publisher.publish(&message);
+        assert_eq!(messages.recv().unwrap(), message);
+
+        drop(subscriber);
+        slave.shutdown();
+        master.shutdown();
+    }
+}
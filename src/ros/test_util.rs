@@ -0,0 +1,252 @@
+//! Fixtures shared by the tests of the ros module.
+
+use std::collections::BTreeMap;
+use std::old_io::TcpListener;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use http::{ServerConfig, ServerHandle};
+use msg::{RosService, std_msgs};
+use xmlrpc;
+use xmlrpc::{Request, Response, Value};
+use ros::common::make_response;
+use ros::master_proxy::MasterProxy;
+use ros::registry::Registry;
+use ros::slave_api::SlaveApi;
+
+/// Service which takes a string and answers with another.
+pub struct Echo;
+
+impl RosService for Echo {
+    type Request = std_msgs::String;
+    type Response = std_msgs::String;
+
+    fn srv_type() -> &'static str {
+        "test/Echo"
+    }
+
+    fn md5sum() -> &'static str {
+        "0123456789abcdef0123456789abcdef"
+    }
+}
+
+/// A master API server which answers each call with a function of the
+/// request, and records the calls made to it.
+pub struct FakeMaster {
+    calls: Arc<Mutex<Vec<Request>>>,
+    server: ServerHandle,
+}
+
+#[derive(Clone)]
+struct FakeMasterHandler {
+    calls: Arc<Mutex<Vec<Request>>>,
+    respond: Arc<Box<Fn(&Request) -> Response + Send + Sync>>,
+}
+
+impl xmlrpc::HandlesXmlrpcRequests for FakeMasterHandler {
+    fn handle_request(&self, request: &Request) -> Response {
+        self.calls.lock().unwrap().push(request.clone());
+        (**self.respond)(request)
+    }
+}
+
+impl FakeMaster {
+    /// Start a master on any free port of localhost.
+    pub fn start<F>(respond: F) -> FakeMaster
+            where F: Fn(&Request) -> Response + Send + Sync + 'static {
+        let calls = Arc::new(Mutex::new(vec![]));
+        let handler = FakeMasterHandler {
+            calls: calls.clone(),
+            respond: Arc::new(Box::new(respond) as Box<Fn(&Request) -> Response + Send + Sync>),
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = xmlrpc::run_xmlrpc_server(listener, ServerConfig::new(1), handler).unwrap();
+        FakeMaster {calls: calls, server: server}
+    }
+
+    pub fn uri(&self) -> String {
+        format!("http://{}/", self.server.socket_addr())
+    }
+
+    /// A proxy for a node whose slave API isn't running.
+    pub fn proxy(&self, caller_id: &str) -> MasterProxy {
+        MasterProxy::new(self.uri().as_slice(), caller_id, "http://127.0.0.1:1/")
+    }
+
+    /// The method name of each call so far, and its string parameter at
+    /// index param, or "" if it doesn't have one.
+    pub fn calls(&self, param: usize) -> Vec<(String, String)> {
+        self.calls.lock().unwrap().iter().map(|request| {
+            (request.method_name.clone(), string_param(request, param))
+        }).collect()
+    }
+
+    pub fn shutdown(self) {
+        self.server.shutdown(Duration::seconds(5)).unwrap();
+    }
+}
+
+/// The string parameter of a request at index, or "" if it doesn't have one.
+fn string_param(request: &Request, index: usize) -> String {
+    match request.params.get(index) {
+        Some(&Value::String(ref value)) => value.clone(),
+        _ => "".to_string(),
+    }
+}
+
+/// What the master started by start_master knows about.
+#[derive(Default)]
+struct MasterState {
+    /// (caller id, caller API) of the publishers of each topic
+    publishers: BTreeMap<String, Vec<(String, String)>>,
+    /// (caller id, caller API) of the subscribers to each topic
+    subscribers: BTreeMap<String, Vec<(String, String)>>,
+    /// (caller id, service API) of the provider of each service
+    services: BTreeMap<String, (String, String)>,
+    /// Parameters in a flat map, without namespaces
+    params: BTreeMap<String, Value>,
+}
+
+/// Start a master which keeps track of registrations and parameters like
+/// the real one, but doesn't send publisherUpdate or paramUpdate calls.
+pub fn start_master() -> FakeMaster {
+    let state = Mutex::new(MasterState::default());
+    FakeMaster::start(move |request: &Request| {
+        let mut state = state.lock().unwrap();
+        let caller_id = string_param(request, 0);
+        let name = string_param(request, 1);
+        match request.method_name.as_slice() {
+            "registerPublisher" => {
+                register(&mut state.publishers, name.as_slice(), caller_id, string_param(request, 3));
+                make_response(1, "", caller_apis(state.subscribers.get(&name)))
+            },
+            "unregisterPublisher" => {
+                let removed = unregister(&mut state.publishers, name.as_slice(), string_param(request, 2));
+                make_response(1, "", Value::Int(removed))
+            },
+            "registerSubscriber" => {
+                register(&mut state.subscribers, name.as_slice(), caller_id, string_param(request, 3));
+                make_response(1, "", caller_apis(state.publishers.get(&name)))
+            },
+            "unregisterSubscriber" => {
+                let removed = unregister(&mut state.subscribers, name.as_slice(), string_param(request, 2));
+                make_response(1, "", Value::Int(removed))
+            },
+            "getSystemState" => {
+                let services = state.services.iter().map(|(service, &(ref caller_id, _))| {
+                    Value::Array(vec![Value::String(service.clone()),
+                        Value::Array(vec![Value::String(caller_id.clone())])])
+                }).collect();
+                make_response(1, "", Value::Array(vec![
+                    caller_ids(&state.publishers), caller_ids(&state.subscribers), Value::Array(services)]))
+            },
+            "registerService" => {
+                state.services.insert(name, (caller_id, string_param(request, 2)));
+                make_response(1, "", Value::Int(1))
+            },
+            "unregisterService" => {
+                let registered = match state.services.get(&name) {
+                    Some(&(_, ref service_api)) => *service_api == string_param(request, 2),
+                    None => false,
+                };
+                if registered {
+                    state.services.remove(&name);
+                }
+                make_response(1, "", Value::Int(registered as i32))
+            },
+            "lookupService" => match state.services.get(&name) {
+                Some(&(_, ref service_api)) => make_response(1, "", Value::String(service_api.clone())),
+                None => make_response(-1, "no provider", Value::String("".to_string())),
+            },
+            "setParam" => {
+                let value = request.params.get(2).map(|value| value.clone()).unwrap_or(Value::Int(0));
+                state.params.insert(name, value);
+                make_response(1, "", Value::Int(0))
+            },
+            "getParam" => match state.params.get(&name) {
+                Some(value) => make_response(1, "", value.clone()),
+                None => make_response(-1, "Parameter is not set", Value::Int(0)),
+            },
+            "hasParam" => make_response(1, "", Value::Boolean(state.params.contains_key(&name))),
+            "searchParam" => match state.params.contains_key(&name) {
+                true => make_response(1, "", Value::String(name)),
+                false => make_response(-1, "Cannot find parameter", Value::String("".to_string())),
+            },
+            "subscribeParam" => {
+                let key = string_param(request, 2);
+                let value = state.params.get(&key).map(|value| value.clone())
+                    .unwrap_or(Value::Struct(BTreeMap::new()));
+                make_response(1, "", value)
+            },
+            "unsubscribeParam" => make_response(1, "", Value::Int(1)),
+            _ => make_response(-1, "unknown method", Value::Int(0)),
+        }
+    })
+}
+
+fn register(registrations: &mut BTreeMap<String, Vec<(String, String)>>, name: &str, caller_id: String,
+        caller_api: String) {
+    if !registrations.contains_key(name) {
+        registrations.insert(name.to_string(), vec![]);
+    }
+    let callers = registrations.get_mut(name).unwrap();
+    if !callers.iter().any(|&(_, ref api)| *api == caller_api) {
+        callers.push((caller_id, caller_api));
+    }
+}
+
+/// Remove a caller's registration, and return how many were removed.
+fn unregister(registrations: &mut BTreeMap<String, Vec<(String, String)>>, name: &str, caller_api: String)
+        -> i32 {
+    match registrations.get_mut(name) {
+        Some(callers) => {
+            let before = callers.len();
+            callers.retain(|&(_, ref api)| *api != caller_api);
+            (before - callers.len()) as i32
+        },
+        None => 0,
+    }
+}
+
+fn caller_apis(callers: Option<&Vec<(String, String)>>) -> Value {
+    Value::Array(callers.map(|callers| {
+        callers.iter().map(|&(_, ref api)| Value::String(api.clone())).collect()
+    }).unwrap_or(vec![]))
+}
+
+/// The caller ids of each name's registrations, as in getSystemState.
+fn caller_ids(registrations: &BTreeMap<String, Vec<(String, String)>>) -> Value {
+    Value::Array(registrations.iter().map(|(name, callers)| {
+        let ids = callers.iter().map(|&(ref id, _)| Value::String(id.clone())).collect();
+        Value::Array(vec![Value::String(name.clone()), Value::Array(ids)])
+    }).collect())
+}
+
+/// The slave API of a node, serving requestTopic for the publications in
+/// its registry.
+pub struct FakeSlave {
+    server: ServerHandle,
+}
+
+impl FakeSlave {
+    /// Start the slave API on any free port of localhost. host is the host
+    /// it tells subscribers to connect to.
+    pub fn start(host: &str, registry: Registry) -> FakeSlave {
+        let slave_api = SlaveApi::new("http://localhost:11311/", host, registry);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = xmlrpc::run_xmlrpc_server(listener, ServerConfig::new(1), slave_api).unwrap();
+        FakeSlave {server: server}
+    }
+
+    pub fn uri(&self) -> String {
+        format!("http://{}/", self.server.socket_addr())
+    }
+
+    /// A proxy for the node, for registering its publishers.
+    pub fn proxy(&self, master: &FakeMaster, caller_id: &str) -> MasterProxy {
+        MasterProxy::new(master.uri().as_slice(), caller_id, self.uri().as_slice())
+    }
+
+    pub fn shutdown(self) {
+        self.server.shutdown(Duration::seconds(5)).unwrap();
+    }
+}
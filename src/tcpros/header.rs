@@ -0,0 +1,216 @@
+use std::collections::BTreeMap;
+use std::old_io::IoResult;
+use std::str;
+use msg::read_bytes;
+
+/// Default limit on the size of a connection header. Headers include the
+/// full message definition, which can run to tens of kilobytes for large
+/// message types.
+pub const MAX_HEADER_BYTES: usize = 1 << 20;
+
+/// A TCPROS connection header, which each end sends when a connection is
+/// opened. Fields are None if they weren't present.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct ConnectionHeader {
+    pub callerid: Option<String>,
+    pub topic: Option<String>,
+    /// The "type" field, e.g. "std_msgs/String"
+    pub topic_type: Option<String>,
+    pub md5sum: Option<String>,
+    pub message_definition: Option<String>,
+    pub latching: Option<bool>,
+    pub tcp_nodelay: Option<bool>,
+    pub service: Option<String>,
+    pub persistent: Option<bool>,
+    pub error: Option<String>,
+    /// Any other fields, by name
+    pub other: BTreeMap<String, String>,
+}
+
+impl ConnectionHeader {
+    pub fn new() -> ConnectionHeader {
+        Default::default()
+    }
+
+    /// Encode the header's fields, without the leading header length.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut fields = vec![];
+        {
+            let mut add_field = |name: &str, value: &Option<String>| {
+                if let Some(ref value) = *value {
+                    fields.push(format!("{}={}", name, value));
+                }
+            };
+            add_field("callerid", &self.callerid);
+            add_field("topic", &self.topic);
+            add_field("type", &self.topic_type);
+            add_field("md5sum", &self.md5sum);
+            add_field("message_definition", &self.message_definition);
+            add_field("latching", &self.latching.map(encode_bool));
+            add_field("tcp_nodelay", &self.tcp_nodelay.map(encode_bool));
+            add_field("service", &self.service);
+            add_field("persistent", &self.persistent.map(encode_bool));
+            add_field("error", &self.error);
+        }
+        for (name, value) in self.other.iter() {
+            fields.push(format!("{}={}", name, value));
+        }
+
+        let mut bytes = vec![];
+        for field in fields.iter() {
+            // Writing to a Vec can't fail
+            bytes.write_le_u32(field.len() as u32).unwrap();
+            bytes.write_str(field.as_slice()).unwrap();
+        }
+        bytes
+    }
+
+    /// Decode a header's fields, not including the leading header length.
+    pub fn decode(bytes: &[u8]) -> Result<ConnectionHeader, String> {
+        let mut header = ConnectionHeader::new();
+        let mut pos = 0;
+        while pos < bytes.len() {
+            if bytes.len() - pos < 4 {
+                return Err("Connection header field length is truncated".to_string());
+            }
+            let field_length = (bytes[pos] as usize) | ((bytes[pos + 1] as usize) << 8)
+                | ((bytes[pos + 2] as usize) << 16) | ((bytes[pos + 3] as usize) << 24);
+            pos += 4;
+            if field_length > bytes.len() - pos {
+                return Err(format!("Connection header field length {} is longer than the header",
+                    field_length));
+            }
+
+            let field = match str::from_utf8(bytes.slice(pos, pos + field_length)) {
+                Ok(field) => field,
+                Err(_) => return Err("Connection header field is not valid UTF-8".to_string()),
+            };
+            try!(header.set_field(field));
+            pos += field_length;
+        }
+        Ok(header)
+    }
+
+    /// Set a field from its encoded "name=value" form.
+    fn set_field(&mut self, field: &str) -> Result<(), String> {
+        let (name, value) = match field.find('=') {
+            Some(i) => (field.slice_to(i), field.slice_from(i + 1).to_string()),
+            None => return Err(format!("Connection header field has no '=': {}", field)),
+        };
+        match name {
+            "callerid" => self.callerid = Some(value),
+            "topic" => self.topic = Some(value),
+            "type" => self.topic_type = Some(value),
+            "md5sum" => self.md5sum = Some(value),
+            "message_definition" => self.message_definition = Some(value),
+            "latching" => self.latching = Some(try!(decode_bool(name, value.as_slice()))),
+            "tcp_nodelay" => self.tcp_nodelay = Some(try!(decode_bool(name, value.as_slice()))),
+            "service" => self.service = Some(value),
+            "persistent" => self.persistent = Some(try!(decode_bool(name, value.as_slice()))),
+            "error" => self.error = Some(value),
+            _ => {self.other.insert(name.to_string(), value);},
+        };
+        Ok(())
+    }
+}
+
+fn encode_bool(x: bool) -> String {
+    match x {
+        true => "1".to_string(),
+        false => "0".to_string(),
+    }
+}
+
+fn decode_bool(name: &str, value: &str) -> Result<bool, String> {
+    match value {
+        "1" => Ok(true),
+        "0" => Ok(false),
+        _ => Err(format!("Bad value for connection header field {}: {}", name, value)),
+    }
+}
+
+/// Read a connection header, including its leading length. Fails if the
+/// header is longer than max_bytes.
+pub fn read_header<R: Reader>(stream: &mut R, max_bytes: usize) -> Result<ConnectionHeader, String> {
+    let header_length = match stream.read_le_u32() {
+        Ok(header_length) => header_length as usize,
+        Err(err) => return Err(format!("Failed to read connection header length: {}", err)),
+    };
+    if header_length > max_bytes {
+        return Err(format!("Connection header is too long ({} bytes, limit is {})",
+            header_length, max_bytes));
+    }
+    let bytes = match read_bytes(stream, header_length) {
+        Ok(bytes) => bytes,
+        Err(err) => return Err(format!("Failed to read connection header: {}", err)),
+    };
+    ConnectionHeader::decode(bytes.as_slice())
+}
+
+/// Write a connection header, including its leading length.
+pub fn write_header<W: Writer>(stream: &mut W, header: &ConnectionHeader) -> IoResult<()> {
+    let bytes = header.encode();
+    try!(stream.write_le_u32(bytes.len() as u32));
+    stream.write_all(bytes.as_slice())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::old_io::MemReader;
+    use super::{ConnectionHeader, read_header, write_header};
+
+    #[test]
+    fn test_round_trip() {
+        let mut header = ConnectionHeader::new();
+        header.callerid = Some("/talker".to_string());
+        header.topic = Some("/chatter".to_string());
+        header.topic_type = Some("std_msgs/String".to_string());
+        header.md5sum = Some("992ce8a1687cec8c8bd883ec73ca41d1".to_string());
+        header.message_definition = Some("string data\n".to_string());
+        header.latching = Some(true);
+        header.tcp_nodelay = Some(false);
+        header.other.insert("probe".to_string(), "1".to_string());
+
+        let mut bytes = vec![];
+        write_header(&mut bytes, &header).unwrap();
+        let mut reader = MemReader::new(bytes);
+        assert_eq!(read_header(&mut reader, 1000), Ok(header));
+
+        let mut error_header = ConnectionHeader::new();
+        error_header.error = Some("md5sums don't match".to_string());
+        assert_eq!(ConnectionHeader::decode(error_header.encode().as_slice()), Ok(error_header));
+    }
+
+    #[test]
+    fn test_decode() {
+        // "topic=/foo" followed by "a==b"
+        let bytes = b"\x0a\x00\x00\x00topic=/foo\x04\x00\x00\x00a==b";
+        let header = ConnectionHeader::decode(bytes).unwrap();
+        assert_eq!(header.topic, Some("/foo".to_string()));
+        assert_eq!(header.other.get(&"a".to_string()), Some(&"=b".to_string()));
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        // Field length runs past the end of the header
+        assert!(ConnectionHeader::decode(b"\x0b\x00\x00\x00topic=/foo").is_err());
+        // Truncated field length
+        assert!(ConnectionHeader::decode(b"\x01\x00").is_err());
+        // No '='
+        assert!(ConnectionHeader::decode(b"\x05\x00\x00\x00topic").is_err());
+        // Bad boolean
+        assert!(ConnectionHeader::decode(b"\x0a\x00\x00\x00latching=x").is_err());
+    }
+
+    #[test]
+    fn test_size_limit() {
+        let mut header = ConnectionHeader::new();
+        header.message_definition = Some("int32 x\n".to_string());
+        let mut bytes = vec![];
+        write_header(&mut bytes, &header).unwrap();
+        let length = bytes.len() - 4;
+
+        assert!(read_header(&mut MemReader::new(bytes.clone()), length).is_ok());
+        assert!(read_header(&mut MemReader::new(bytes), length - 1).is_err());
+    }
+}
@@ -0,0 +1,66 @@
+use std::old_io::MemReader;
+use msg::{RosField, RosMessage, read_bytes};
+
+/// Default limit on the size of a message read from a connection, which
+/// subscribers and services can change in their configs.
+pub const MAX_MESSAGE_BYTES: usize = 128 * 1024 * 1024;
+
+/// Serialize a message, with the leading message length.
+pub fn encode_message<M: RosMessage>(message: &M) -> Vec<u8> {
+    let mut body = vec![];
+    // Writing to a Vec can't fail
+    message.encode(&mut body).unwrap();
+
+    let mut bytes = Vec::with_capacity(body.len() + 4);
+    bytes.write_le_u32(body.len() as u32).unwrap();
+    bytes.push_all(body.as_slice());
+    bytes
+}
+
+/// Read a length-prefixed message. Fails if the message is longer than
+/// max_bytes.
+pub fn read_message<M: RosMessage, R: Reader>(stream: &mut R, max_bytes: usize) -> Result<M, String> {
+    let message_length = match stream.read_le_u32() {
+        Ok(message_length) => message_length as usize,
+        Err(err) => return Err(format!("Failed to read message length: {}", err)),
+    };
+    if message_length > max_bytes {
+        return Err(format!("Message is too long ({} bytes, limit is {})", message_length, max_bytes));
+    }
+    let bytes = match read_bytes(stream, message_length) {
+        Ok(bytes) => bytes,
+        Err(err) => return Err(format!("Failed to read message: {}", err)),
+    };
+    decode_message(bytes)
+}
+
+/// Deserialize a message, without the leading message length. Fails unless
+/// all of the bytes are used.
+pub fn decode_message<M: RosMessage>(bytes: Vec<u8>) -> Result<M, String> {
+    let mut reader = MemReader::new(bytes);
+    let message = try!(RosField::decode(&mut reader));
+    match reader.eof() {
+        true => Ok(message),
+        false => Err("Message is longer than its type's definition".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::old_io::MemReader;
+    use msg::std_msgs;
+    use super::{encode_message, read_message};
+
+    #[test]
+    fn test_round_trip() {
+        let message = std_msgs::String {data: "hello".to_string()};
+        let bytes = encode_message(&message);
+        assert_eq!(bytes.as_slice(), b"\x09\x00\x00\x00\x05\x00\x00\x00hello");
+
+        let read: Result<std_msgs::String, String> = read_message(&mut MemReader::new(bytes.clone()), 100);
+        assert_eq!(read, Ok(message));
+
+        let read: Result<std_msgs::String, String> = read_message(&mut MemReader::new(bytes), 8);
+        assert!(read.is_err());
+    }
+}
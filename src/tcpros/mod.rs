@@ -0,0 +1,13 @@
+//! The TCPROS transport for topics and services.
+
+pub use tcpros::header::{ConnectionHeader, read_header, write_header, MAX_HEADER_BYTES};
+pub use tcpros::message::{encode_message, decode_message, read_message, MAX_MESSAGE_BYTES};
+pub use tcpros::service::{write_service_response, read_service_response};
+pub use tcpros::socket::{SocketOptions, TcpConnection};
+#[cfg(test)]
+pub use tcpros::socket::nodelay;
+
+mod header;
+mod message;
+mod service;
+mod socket;
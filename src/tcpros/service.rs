@@ -0,0 +1,79 @@
+use std::old_io::IoResult;
+use msg::{RosMessage, read_bytes};
+use tcpros::message::{encode_message, read_message};
+
+/// Write a service response: an ok byte of 1 followed by the response
+/// message, or an ok byte of 0 followed by an error string.
+pub fn write_service_response<M: RosMessage, W: Writer>(stream: &mut W, response: Result<&M, &str>)
+        -> IoResult<()> {
+    match response {
+        Ok(message) => {
+            try!(stream.write_u8(1));
+            stream.write_all(encode_message(message).as_slice())
+        },
+        Err(error) => {
+            try!(stream.write_u8(0));
+            try!(stream.write_le_u32(error.len() as u32));
+            stream.write_str(error)
+        },
+    }
+}
+
+/// Read a service response. The outer result fails if the response can't
+/// be read, and the inner one if the server sent an error.
+pub fn read_service_response<M: RosMessage, R: Reader>(stream: &mut R, max_bytes: usize)
+        -> Result<Result<M, String>, String> {
+    let ok = match stream.read_u8() {
+        Ok(ok) => ok,
+        Err(err) => return Err(format!("Failed to read service response: {}", err)),
+    };
+    if ok != 0 {
+        return Ok(Ok(try!(read_message(stream, max_bytes))));
+    }
+
+    let error_length = match stream.read_le_u32() {
+        Ok(length) => length as usize,
+        Err(err) => return Err(format!("Failed to read service error length: {}", err)),
+    };
+    if error_length > max_bytes {
+        return Err(format!("Service error is too long ({} bytes)", error_length));
+    }
+    let bytes = match read_bytes(stream, error_length) {
+        Ok(bytes) => bytes,
+        Err(err) => return Err(format!("Failed to read service error: {}", err)),
+    };
+    match String::from_utf8(bytes) {
+        Ok(error) => Ok(Err(error)),
+        Err(_) => Err("Service error is not valid UTF-8".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::old_io::MemReader;
+    use msg::std_msgs;
+    use super::{write_service_response, read_service_response};
+
+    #[test]
+    fn test_round_trip() {
+        let message = std_msgs::String {data: "hello".to_string()};
+        let mut bytes = vec![];
+        write_service_response(&mut bytes, Ok(&message)).unwrap();
+        assert_eq!(bytes.as_slice(), b"\x01\x09\x00\x00\x00\x05\x00\x00\x00hello");
+        assert_eq!(read_service_response(&mut MemReader::new(bytes), 100), Ok(Ok(message)));
+
+        let mut bytes = vec![];
+        write_service_response::<std_msgs::String, _>(&mut bytes, Err("failed")).unwrap();
+        assert_eq!(bytes.as_slice(), b"\x00\x06\x00\x00\x00failed");
+        let response: Result<Result<std_msgs::String, String>, String> =
+            read_service_response(&mut MemReader::new(bytes), 100);
+        assert_eq!(response, Ok(Err("failed".to_string())));
+    }
+
+    #[test]
+    fn test_truncated() {
+        let response: Result<Result<std_msgs::String, String>, String> =
+            read_service_response(&mut MemReader::new(b"\x01\x09\x00".to_vec()), 100);
+        assert!(response.is_err());
+    }
+}
@@ -0,0 +1,309 @@
+use libc;
+use libc::{c_int, c_void, size_t, socklen_t};
+use std::i32;
+use std::mem;
+use std::old_io;
+use std::old_io::{IoError, IoErrorKind, IoResult, TcpStream};
+use std::old_io::net::ip::{IpAddr, SocketAddr, ToSocketAddr};
+use std::os;
+use std::os::unix::{AsRawFd, Fd};
+use std::sync::Arc;
+
+#[cfg(target_os = "linux")]
+const SO_SNDBUF: c_int = 7;
+#[cfg(target_os = "linux")]
+const SO_RCVBUF: c_int = 8;
+#[cfg(not(target_os = "linux"))]
+const SO_SNDBUF: c_int = 0x1001;
+#[cfg(not(target_os = "linux"))]
+const SO_RCVBUF: c_int = 0x1002;
+
+const IPPROTO_TCP: c_int = 6;
+const TCP_NODELAY: c_int = 1;
+const SHUT_RD: c_int = 0;
+
+/// Options for the sockets of a topic's connections. Options which are None
+/// are left at the system defaults.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct SocketOptions {
+    /// Size of the kernel's send buffer, in bytes
+    pub send_buffer_size: Option<usize>,
+    /// Size of the kernel's receive buffer, in bytes. Only sizes set before
+    /// connecting can raise the TCP window scale, so for outgoing
+    /// connections use connect rather than apply.
+    pub receive_buffer_size: Option<usize>,
+    /// Turn on TCP keepalive, with probes after the connection has been idle
+    /// for this many seconds
+    pub keepalive: Option<usize>,
+}
+
+impl SocketOptions {
+    pub fn new() -> SocketOptions {
+        Default::default()
+    }
+
+    /// Set the options on a connection.
+    pub fn apply(&self, stream: &mut TcpStream) -> Result<(), String> {
+        try!(self.set_buffer_sizes(stream.as_raw_fd()));
+        if let Some(seconds) = self.keepalive {
+            if let Err(err) = stream.set_keepalive(Some(seconds)) {
+                return Err(format!("Failed to turn on keepalive: {}", err));
+            }
+        }
+        Ok(())
+    }
+
+    /// Connect to host:port, with the options (and TCP_NODELAY, if nodelay
+    /// is set) set on the socket before connecting.
+    pub fn connect(&self, host: &str, port: u16, nodelay: bool) -> Result<TcpConnection, String> {
+        let addrs = match (host, port).to_socket_addr_all() {
+            Ok(addrs) => addrs,
+            Err(err) => return Err(format!("Failed to resolve {}: {}", host, err)),
+        };
+        let mut last_err = format!("No addresses found for {}", host);
+        for addr in addrs.iter() {
+            match self.connect_addr(*addr, nodelay) {
+                Ok(connection) => return Ok(connection),
+                Err(err) => last_err = err,
+            }
+        }
+        Err(last_err)
+    }
+
+    fn connect_addr(&self, addr: SocketAddr, nodelay: bool) -> Result<TcpConnection, String> {
+        let family = match addr.ip {
+            IpAddr::Ipv4Addr(..) => libc::AF_INET,
+            IpAddr::Ipv6Addr(..) => libc::AF_INET6,
+        };
+        let fd = unsafe { libc::socket(family, libc::SOCK_STREAM, 0) };
+        if fd < 0 {
+            return Err(format!("Failed to create socket: {}", os::last_os_error()));
+        }
+        // Closes the socket if anything below fails
+        let connection = TcpConnection {socket: Arc::new(Socket {fd: fd})};
+
+        try!(self.set_buffer_sizes(fd));
+        if let Some(seconds) = self.keepalive {
+            try!(set_option(fd, libc::SOL_SOCKET, libc::SO_KEEPALIVE, 1));
+            try!(set_keepalive_idle(fd, seconds));
+        }
+        if nodelay {
+            try!(set_option(fd, IPPROTO_TCP, TCP_NODELAY, 1));
+        }
+
+        let result = unsafe {
+            match addr.ip {
+                IpAddr::Ipv4Addr(a, b, c, d) => {
+                    let mut sockaddr: libc::sockaddr_in = mem::zeroed();
+                    sockaddr.sin_family = libc::AF_INET as libc::sa_family_t;
+                    sockaddr.sin_port = addr.port.to_be();
+                    sockaddr.sin_addr.s_addr =
+                        ((a as u32) << 24 | (b as u32) << 16 | (c as u32) << 8 | d as u32).to_be();
+                    libc::connect(fd, &sockaddr as *const libc::sockaddr_in as *const libc::sockaddr,
+                        mem::size_of::<libc::sockaddr_in>() as socklen_t)
+                },
+                IpAddr::Ipv6Addr(a, b, c, d, e, f, g, h) => {
+                    let mut sockaddr: libc::sockaddr_in6 = mem::zeroed();
+                    sockaddr.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+                    sockaddr.sin6_port = addr.port.to_be();
+                    sockaddr.sin6_addr.s6_addr = [a.to_be(), b.to_be(), c.to_be(), d.to_be(),
+                        e.to_be(), f.to_be(), g.to_be(), h.to_be()];
+                    libc::connect(fd, &sockaddr as *const libc::sockaddr_in6 as *const libc::sockaddr,
+                        mem::size_of::<libc::sockaddr_in6>() as socklen_t)
+                },
+            }
+        };
+        if result != 0 {
+            return Err(format!("Failed to connect to {}: {}", addr, os::last_os_error()));
+        }
+        Ok(connection)
+    }
+
+    fn set_buffer_sizes(&self, fd: Fd) -> Result<(), String> {
+        if let Some(size) = self.send_buffer_size {
+            try!(set_buffer_size(fd, SO_SNDBUF, size));
+        }
+        if let Some(size) = self.receive_buffer_size {
+            try!(set_buffer_size(fd, SO_RCVBUF, size));
+        }
+        Ok(())
+    }
+}
+
+/// A socket, which is closed when dropped.
+struct Socket {
+    fd: Fd,
+}
+
+impl Drop for Socket {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd); }
+    }
+}
+
+/// A TCP connection made by SocketOptions::connect. old_io's TcpStream can
+/// only be created already connected, so this does the same with libc.
+/// Clones share the socket.
+#[derive(Clone)]
+pub struct TcpConnection {
+    socket: Arc<Socket>,
+}
+
+impl TcpConnection {
+    /// Stop reading from the connection, which makes any blocked reads, in
+    /// this or other clones, return.
+    pub fn close_read(&mut self) -> IoResult<()> {
+        match unsafe { libc::shutdown(self.socket.fd, SHUT_RD) } {
+            0 => Ok(()),
+            _ => Err(IoError::last_error()),
+        }
+    }
+}
+
+impl AsRawFd for TcpConnection {
+    fn as_raw_fd(&self) -> Fd {
+        self.socket.fd
+    }
+}
+
+impl Reader for TcpConnection {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        loop {
+            let result = unsafe {
+                libc::read(self.socket.fd, buf.as_mut_ptr() as *mut c_void, buf.len() as size_t)
+            };
+            if result > 0 {
+                return Ok(result as usize);
+            } else if result == 0 {
+                return Err(old_io::standard_error(IoErrorKind::EndOfFile));
+            } else if os::errno() as c_int != libc::EINTR {
+                return Err(IoError::last_error());
+            }
+        }
+    }
+}
+
+impl Writer for TcpConnection {
+    fn write_all(&mut self, buf: &[u8]) -> IoResult<()> {
+        let mut written = 0;
+        while written < buf.len() {
+            let remaining = buf.slice_from(written);
+            let result = unsafe {
+                libc::write(self.socket.fd, remaining.as_ptr() as *const c_void, remaining.len() as size_t)
+            };
+            if result >= 0 {
+                written += result as usize;
+            } else if os::errno() as c_int != libc::EINTR {
+                return Err(IoError::last_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+fn set_buffer_size(fd: Fd, option: c_int, size: usize) -> Result<(), String> {
+    // setsockopt takes an int, so larger sizes would wrap around
+    if size > i32::MAX as usize {
+        return Err(format!("Socket buffer size {} is too large", size));
+    }
+    set_option(fd, libc::SOL_SOCKET, option, size as c_int)
+        .map_err(|err| format!("Failed to set socket buffer size to {}: {}", size, err))
+}
+
+#[cfg(target_os = "linux")]
+fn set_keepalive_idle(fd: Fd, seconds: usize) -> Result<(), String> {
+    const TCP_KEEPIDLE: c_int = 4;
+    set_option(fd, IPPROTO_TCP, TCP_KEEPIDLE, seconds as c_int)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_keepalive_idle(fd: Fd, seconds: usize) -> Result<(), String> {
+    const TCP_KEEPALIVE: c_int = 0x10;
+    set_option(fd, IPPROTO_TCP, TCP_KEEPALIVE, seconds as c_int)
+}
+
+fn set_option(fd: Fd, level: c_int, option: c_int, value: c_int) -> Result<(), String> {
+    let result = unsafe {
+        libc::setsockopt(fd, level, option, &value as *const c_int as *const c_void,
+            mem::size_of::<c_int>() as socklen_t)
+    };
+    match result {
+        0 => Ok(()),
+        _ => Err(format!("setsockopt failed: {}", os::last_os_error())),
+    }
+}
+
+/// Whether TCP_NODELAY is set on a socket.
+#[cfg(test)]
+pub fn nodelay(fd: Fd) -> bool {
+    get_option(fd, IPPROTO_TCP, TCP_NODELAY) != 0
+}
+
+#[cfg(test)]
+fn get_option(fd: Fd, level: c_int, option: c_int) -> c_int {
+    let mut value: c_int = 0;
+    let mut length = mem::size_of::<c_int>() as socklen_t;
+    let result = unsafe {
+        libc::getsockopt(fd, level, option, &mut value as *mut c_int as *mut c_void, &mut length)
+    };
+    assert_eq!(result, 0);
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use libc;
+    use std::old_io::{Acceptor, Listener, TcpListener, TcpStream};
+    use std::os::unix::AsRawFd;
+    use super::{SocketOptions, get_option, nodelay, SO_SNDBUF, SO_RCVBUF};
+
+    #[test]
+    fn test_apply() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut acceptor = listener.listen().unwrap();
+        let addr = acceptor.socket_name().unwrap();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let _server_stream = acceptor.accept().unwrap();
+
+        let mut options = SocketOptions::new();
+        options.send_buffer_size = Some(32768);
+        options.receive_buffer_size = Some(65536);
+        options.keepalive = Some(30);
+        assert_eq!(options.apply(&mut stream), Ok(()));
+
+        // Some systems round the sizes up, or double them for bookkeeping
+        assert!(get_option(stream.as_raw_fd(), libc::SOL_SOCKET, SO_SNDBUF) >= 32768);
+        assert!(get_option(stream.as_raw_fd(), libc::SOL_SOCKET, SO_RCVBUF) >= 65536);
+
+        // Sizes which don't fit in an int are rejected rather than wrapped
+        options.receive_buffer_size = Some(1 << 32);
+        assert!(options.apply(&mut stream).is_err());
+    }
+
+    #[test]
+    fn test_connect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut acceptor = listener.listen().unwrap();
+        let addr = acceptor.socket_name().unwrap();
+
+        let mut options = SocketOptions::new();
+        options.receive_buffer_size = Some(65536);
+        options.keepalive = Some(30);
+        let mut connection = options.connect("127.0.0.1", addr.port, true).unwrap();
+        let mut server_stream = acceptor.accept().unwrap();
+        assert!(get_option(connection.as_raw_fd(), libc::SOL_SOCKET, SO_RCVBUF) >= 65536);
+        assert!(nodelay(connection.as_raw_fd()));
+
+        connection.write_all(b"ping").unwrap();
+        assert_eq!(server_stream.read_exact(4).unwrap().as_slice(), b"ping");
+        server_stream.write_all(b"pong").unwrap();
+        assert_eq!(connection.read_exact(4).unwrap().as_slice(), b"pong");
+
+        // Closing a clone for reading ends reads on the original
+        connection.clone().close_read().unwrap();
+        assert!(connection.read_u8().is_err());
+
+        assert!(options.connect("127.0.0.1", addr.port, false).is_ok());
+        drop(acceptor);
+    }
+}
@@ -0,0 +1,237 @@
+/// Size of the header at the start of every UDPROS datagram.
+pub const DATAGRAM_HEADER_BYTES: usize = 8;
+
+/// Kind of a UDPROS datagram.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Opcode {
+    /// First block of a message
+    Data0,
+    /// Later block of a message
+    DataN,
+    Ping,
+    /// The connection has been closed
+    Err,
+}
+
+impl Opcode {
+    fn to_u8(self) -> u8 {
+        match self {
+            Opcode::Data0 => 0,
+            Opcode::DataN => 1,
+            Opcode::Ping => 2,
+            Opcode::Err => 3,
+        }
+    }
+
+    fn from_u8(x: u8) -> Result<Opcode, String> {
+        match x {
+            0 => Ok(Opcode::Data0),
+            1 => Ok(Opcode::DataN),
+            2 => Ok(Opcode::Ping),
+            3 => Ok(Opcode::Err),
+            x => Err(format!("Unknown UDPROS opcode {}", x)),
+        }
+    }
+}
+
+/// The header at the start of each datagram.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct DatagramHeader {
+    pub connection_id: u32,
+    pub opcode: Opcode,
+    /// Sequence number of the message, which wraps around
+    pub message_id: u8,
+    /// For Data0, the number of blocks in the message. For DataN, the index
+    /// of this block.
+    pub block: u16,
+}
+
+impl DatagramHeader {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(DATAGRAM_HEADER_BYTES);
+        // Writing to a Vec can't fail
+        bytes.write_le_u32(self.connection_id).unwrap();
+        bytes.push(self.opcode.to_u8());
+        bytes.push(self.message_id);
+        bytes.write_le_u16(self.block).unwrap();
+        bytes
+    }
+
+    /// Decode the header at the start of a datagram.
+    pub fn decode(datagram: &[u8]) -> Result<DatagramHeader, String> {
+        if datagram.len() < DATAGRAM_HEADER_BYTES {
+            return Err(format!("UDPROS datagram is too short ({} bytes)", datagram.len()));
+        }
+        let connection_id = (datagram[0] as u32) | ((datagram[1] as u32) << 8)
+            | ((datagram[2] as u32) << 16) | ((datagram[3] as u32) << 24);
+        Ok(DatagramHeader {
+            connection_id: connection_id,
+            opcode: try!(Opcode::from_u8(datagram[4])),
+            message_id: datagram[5],
+            block: (datagram[6] as u16) | ((datagram[7] as u16) << 8),
+        })
+    }
+}
+
+/// Split a serialized message (including its length) into datagrams of at
+/// most max_datagram_size bytes.
+pub fn fragment(connection_id: u32, message_id: u8, message: &[u8], max_datagram_size: usize)
+        -> Result<Vec<Vec<u8>>, String> {
+    if max_datagram_size <= DATAGRAM_HEADER_BYTES {
+        return Err(format!("UDPROS datagram size {} is too small", max_datagram_size));
+    }
+    let block_size = max_datagram_size - DATAGRAM_HEADER_BYTES;
+    let num_blocks = (message.len() + block_size - 1) / block_size;
+    if num_blocks > 0xffff {
+        return Err(format!("Message is too long for UDPROS ({} bytes)", message.len()));
+    }
+
+    let mut datagrams = vec![];
+    for (i, block) in message.chunks(block_size).enumerate() {
+        let header = DatagramHeader {
+            connection_id: connection_id,
+            opcode: if i == 0 { Opcode::Data0 } else { Opcode::DataN },
+            message_id: message_id,
+            block: if i == 0 { num_blocks as u16 } else { i as u16 },
+        };
+        let mut datagram = header.encode();
+        datagram.push_all(block);
+        datagrams.push(datagram);
+    }
+    Ok(datagrams)
+}
+
+/// A message which has been partly received.
+struct PartialMessage {
+    message_id: u8,
+    num_blocks: u16,
+    next_block: u16,
+    bytes: Vec<u8>,
+}
+
+/// Puts messages back together from the datagrams of a connection. If a
+/// datagram is lost or arrives out of order, the message it belongs to is
+/// dropped.
+pub struct Reassembler {
+    partial: Option<PartialMessage>,
+    dropped: u64,
+}
+
+impl Reassembler {
+    pub fn new() -> Reassembler {
+        Reassembler {partial: None, dropped: 0}
+    }
+
+    /// Add a datagram. Returns the message if this completed one, or an
+    /// error if the publisher closed the connection.
+    pub fn add(&mut self, datagram: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        let header = try!(DatagramHeader::decode(datagram));
+        let block = datagram.slice_from(DATAGRAM_HEADER_BYTES);
+        match header.opcode {
+            Opcode::Ping => Ok(None),
+            Opcode::Err => Err("Publisher closed the UDPROS connection".to_string()),
+            Opcode::Data0 => {
+                if self.partial.take().is_some() {
+                    self.dropped += 1;
+                }
+                if header.block == 0 {
+                    return Err("UDPROS message has no blocks".to_string());
+                }
+                self.partial = Some(PartialMessage {
+                    message_id: header.message_id,
+                    num_blocks: header.block,
+                    next_block: 1,
+                    bytes: block.to_vec(),
+                });
+                Ok(self.take_complete())
+            },
+            Opcode::DataN => {
+                let in_sequence = match self.partial {
+                    Some(ref partial) =>
+                        partial.message_id == header.message_id && partial.next_block == header.block,
+                    None => false,
+                };
+                if !in_sequence {
+                    if self.partial.take().is_some() {
+                        self.dropped += 1;
+                    }
+                    return Ok(None);
+                }
+                if let Some(ref mut partial) = self.partial {
+                    partial.bytes.push_all(block);
+                    partial.next_block += 1;
+                }
+                Ok(self.take_complete())
+            },
+        }
+    }
+
+    /// Number of messages dropped because datagrams were lost.
+    pub fn dropped(&self) -> u64 {
+        self.dropped
+    }
+
+    fn take_complete(&mut self) -> Option<Vec<u8>> {
+        let complete = match self.partial {
+            Some(ref partial) => partial.next_block == partial.num_blocks,
+            None => false,
+        };
+        match complete {
+            true => self.partial.take().map(|partial| partial.bytes),
+            false => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DatagramHeader, Opcode, Reassembler, fragment};
+
+    #[test]
+    fn test_header() {
+        let header = DatagramHeader {connection_id: 0x01020304, opcode: Opcode::DataN, message_id: 7, block: 0x0102};
+        let bytes = header.encode();
+        assert_eq!(bytes, vec![4, 3, 2, 1, 1, 7, 2, 1]);
+        assert_eq!(DatagramHeader::decode(bytes.as_slice()), Ok(header));
+        assert!(DatagramHeader::decode(&[4, 3, 2, 1, 9, 7, 2, 1]).is_err());
+        assert!(DatagramHeader::decode(&[4, 3, 2, 1]).is_err());
+    }
+
+    #[test]
+    fn test_fragment() {
+        let message: Vec<u8> = (0..25).collect();
+        let datagrams = fragment(5, 1, message.as_slice(), 18).unwrap();
+        assert_eq!(datagrams.len(), 3);
+        assert_eq!(DatagramHeader::decode(datagrams[0].as_slice()).unwrap().block, 3);
+        assert_eq!(DatagramHeader::decode(datagrams[2].as_slice()).unwrap().block, 2);
+        assert_eq!(datagrams[2].len(), 8 + 5);
+
+        let mut reassembler = Reassembler::new();
+        assert_eq!(reassembler.add(datagrams[0].as_slice()), Ok(None));
+        assert_eq!(reassembler.add(datagrams[1].as_slice()), Ok(None));
+        assert_eq!(reassembler.add(datagrams[2].as_slice()), Ok(Some(message)));
+
+        assert!(fragment(5, 1, &[0; 10], 8).is_err());
+    }
+
+    #[test]
+    fn test_lost_datagrams() {
+        let first = fragment(5, 1, &[1; 20], 18).unwrap();
+        let second = fragment(5, 2, &[2; 20], 18).unwrap();
+        let mut reassembler = Reassembler::new();
+
+        // The second block of the first message is lost
+        assert_eq!(reassembler.add(first[0].as_slice()), Ok(None));
+        assert_eq!(reassembler.add(second[0].as_slice()), Ok(None));
+        assert_eq!(reassembler.add(second[1].as_slice()), Ok(Some(vec![2; 20])));
+        assert_eq!(reassembler.dropped(), 1);
+
+        // A block arrives without the start of its message
+        assert_eq!(reassembler.add(first[1].as_slice()), Ok(None));
+
+        let ping = DatagramHeader {connection_id: 5, opcode: Opcode::Ping, message_id: 0, block: 0};
+        assert_eq!(reassembler.add(ping.encode().as_slice()), Ok(None));
+        let err = DatagramHeader {connection_id: 5, opcode: Opcode::Err, message_id: 0, block: 0};
+        assert!(reassembler.add(err.encode().as_slice()).is_err());
+    }
+}
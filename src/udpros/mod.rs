@@ -0,0 +1,9 @@
+//! The UDPROS transport for topics.
+//!
+//! Messages are sent as datagrams, split into blocks if they don't fit in
+//! one. Lost datagrams aren't resent, so UDPROS suits subscribers which can
+//! tolerate losing messages in return for lower latency.
+
+pub use udpros::datagram::{DatagramHeader, Opcode, Reassembler, fragment, DATAGRAM_HEADER_BYTES};
+
+mod datagram;
@@ -8,6 +8,7 @@
 //! and match the XML spec: http://www.w3.org/TR/REC-xml/
 
 use regex;
+use std::char;
 
 /// An XML element. An element in an XML document is defined by a start and
 /// end tag, and may have text or other elements inside of it. There is also
@@ -81,7 +82,7 @@ fn parse_element(input_str: &str) -> Result<(Element, &str), String> {
     remaining_str = match get_text_token(remaining_str) {
         None => remaining_str,
         Some((Token::Text(text), new_remaining_str)) => {
-            element.text = text;
+            element.text = try!(unescape_text(text.as_slice()));
             new_remaining_str
         },
         Some((_, _)) => panic!("Bad token type returned"),
@@ -127,7 +128,7 @@ pub fn serialize_xml(element: &Element) -> String {
 }
 
 fn serialize_element(element: &Element) -> String {
-    let mut result = format!("<{}>{}", element.name, element.text);
+    let mut result = format!("<{}>{}", element.name, escape_text(element.text.as_slice()));
 
     for child_element in element.children.iter() {
         result = result + serialize_element(child_element).as_slice();
@@ -137,6 +138,82 @@ fn serialize_element(element: &Element) -> String {
     result
 }
 
+/// Replace the characters that can't appear in the text of an element with
+/// references to them.
+pub fn escape_text(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// Replace the entity and character references in the text of an element
+/// with the characters they stand for.
+fn unescape_text(text: &str) -> Result<String, String> {
+    let mut result = String::with_capacity(text.len());
+    let mut remaining_str = text;
+    loop {
+        let start_i = match remaining_str.find('&') {
+            None => {
+                result.push_str(remaining_str);
+                return Ok(result);
+            },
+            Some(i) => i,
+        };
+        result.push_str(remaining_str.slice_to(start_i));
+        remaining_str = remaining_str.slice_from(start_i + 1);
+        let end_i = match remaining_str.find(';') {
+            None => return Err(format!("Unterminated reference in text ({})", text)),
+            Some(i) => i,
+        };
+        result.push(try!(decode_reference(remaining_str.slice_to(end_i))));
+        remaining_str = remaining_str.slice_from(end_i + 1);
+    }
+}
+
+/// Decode the name of a reference, without the & and ;, e.g. "amp" or "#60".
+fn decode_reference(name: &str) -> Result<char, String> {
+    let code = match name {
+        "amp" => return Ok('&'),
+        "lt" => return Ok('<'),
+        "gt" => return Ok('>'),
+        "quot" => return Ok('"'),
+        "apos" => return Ok('\''),
+        _ if name.starts_with("#x") => parse_char_code(name.slice_from(2), 16),
+        _ if name.starts_with("#") => parse_char_code(name.slice_from(1), 10),
+        _ => None,
+    };
+    match code.and_then(char::from_u32) {
+        Some(c) => Ok(c),
+        None => Err(format!("Unknown reference (&{};)", name)),
+    }
+}
+
+/// Parse the digits of a character reference.
+fn parse_char_code(digits: &str, radix: u32) -> Option<u32> {
+    if digits.is_empty() {
+        return None;
+    }
+    let mut code = 0u32;
+    for c in digits.chars() {
+        code = code * radix + match c.to_digit(radix) {
+            Some(digit) => digit,
+            None => return None,
+        };
+        // Stop before overflowing, since no character is this large anyway
+        if code > char::MAX as u32 {
+            return None;
+        }
+    }
+    Some(code)
+}
+
 fn get_pi_token(input_str: &str) -> Option<(Token, &str)> {
     let pi_re = regex!("<[?][^>]*[?]>");
     match pi_re.captures(input_str) {
@@ -182,7 +259,7 @@ fn get_remaining_string<'a>(caps: &regex::Captures, input_str: &'a str) -> &'a s
 #[cfg(test)]
 mod tests {
     use super::{get_pi_token, get_stag_token, get_etag_token, get_text_token, parse_xml};
-    use super::{Token, make_element, serialize_xml};
+    use super::{Token, make_element, serialize_xml, escape_text, unescape_text};
 
     #[test]
     fn test_get_pi_token() {
@@ -333,4 +410,38 @@ mod tests {
             Ok(parsed_element) => assert_eq!(parsed_element, element),
         };
     }
+
+    #[test]
+    fn test_escape_text() {
+        assert_eq!(escape_text("a < b && c > d").as_slice(), "a &lt; b &amp;&amp; c &gt; d");
+        assert_eq!(escape_text("<robot name=\"a&b\"/>").as_slice(),
+            "&lt;robot name=\"a&amp;b\"/&gt;");
+        assert_eq!(escape_text("plain").as_slice(), "plain");
+    }
+
+    #[test]
+    fn test_unescape_text() {
+        assert_eq!(unescape_text("&lt;a&gt; &amp; &quot;b&quot; &apos;c&apos;"),
+            Ok("<a> & \"b\" 'c'".to_string()));
+        assert_eq!(unescape_text("&#60;&#x3E;&#x3e;&#233;"), Ok("<>>\u{e9}".to_string()));
+        assert_eq!(unescape_text("no references"), Ok("no references".to_string()));
+
+        assert!(unescape_text("a & b").is_err());
+        assert!(unescape_text("&unknown;").is_err());
+        assert!(unescape_text("&#;").is_err());
+        assert!(unescape_text("&#xZZ;").is_err());
+        assert!(unescape_text("&#xD800;").is_err());
+        assert!(unescape_text("&#99999999999;").is_err());
+    }
+
+    #[test]
+    fn test_escaped_text_round_trip() {
+        let element = make_element("string", "<robot name=\"a&b\"> 'x' & y", vec![]);
+        let serialized_xml = serialize_xml(&element);
+        assert!(!serialized_xml.as_slice().contains("<robot"));
+        assert_eq!(parse_xml(serialized_xml.as_slice()), Ok(element));
+
+        assert_eq!(parse_xml("<a>&lt;b&gt;</a>"), Ok(make_element("a", "<b>", vec![])));
+        assert!(parse_xml("<a>b & c</a>").is_err());
+    }
 }
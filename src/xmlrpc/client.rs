@@ -1,19 +1,26 @@
-use http::post;
+use http::{post_with_config, ClientConfig};
 use xmlrpc::parser;
-use xmlrpc::{Request, Response, Value};
+use xmlrpc::{Request, Response};
+use xml::escape_text;
+use xmlrpc::common::serialize_value;
 
+#[derive(Clone)]
 pub struct Client {
-    pub server_uri: String
+    /// URI of the server, e.g. "http://localhost:11311/". May include
+    /// credentials for HTTP Basic authentication.
+    pub server_uri: String,
+    pub http_config: ClientConfig,
 }
 
 impl Client {
+    pub fn new(server_uri: &str) -> Client {
+        Client {server_uri: server_uri.to_string(), http_config: ClientConfig::new()}
+    }
+
     pub fn execute_request(&self, request: &Request) -> Result<Response, String> {
-        let request_str = match serialize_request(request) {
-            Err(_) => return Err("Failed to serialize request".to_string()),
-            Ok(r) => r,
-        };
+        let request_str = try!(serialize_request(request));
 
-        match post(self.server_uri.as_slice(), request_str.as_slice()) {
+        match post_with_config(self.server_uri.as_slice(), request_str.as_slice(), &self.http_config) {
             Err(err) => Err(err),
             Ok((_, response_body)) => match parser::parse_response(response_body.as_slice()) {
                 Ok(response) => Ok(response),
@@ -26,13 +33,7 @@ impl Client {
 fn serialize_request(request: &Request) -> Result<String, String> {
     let mut param_str = "".to_string();
     for param in request.params.iter() {
-        match param {
-            &Value::String(ref val) => {
-                param_str = param_str + format!(
-                  "<param><value><string>{}</string></value></param>", val).as_slice();
-            },
-            other_val => return Err(format!("Don't know how to serialize XMLRPC value {:?}", other_val)),
-        };
+        param_str = param_str + format!("<param>{}</param>", try!(serialize_value(param))).as_slice();
     };
 
     Ok(format!(
@@ -42,6 +43,6 @@ fn serialize_request(request: &Request) -> Result<String, String> {
     <params>\n\
       {}\n\
     </params>\n\
-    </methodCall>\n", request.method_name, param_str))
+    </methodCall>\n", escape_text(request.method_name.as_slice()), param_str))
 }
 
@@ -1,22 +1,235 @@
-#[derive(Debug, PartialEq)]
+use std::collections::BTreeMap;
+use http;
+use xml::escape_text;
+
+#[derive(Debug, PartialEq, Clone)]
 pub enum Value {
     Int (i32),
     Boolean (bool),
     String (String),
     Double (f64),
     Array (Vec<Value>),
-    // Not handling dateTime.iso8601 base64 or struct types
+    Struct (BTreeMap<String, Value>),
+    Base64 (Vec<u8>),
+    // Not handling dateTime.iso8601 type
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum Response {
     Success {param: Value},
     Fault {fault_code: i32, fault_string: String},
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub struct Request {
     pub method_name: String,
     pub params: Vec<Value>,
 }
 
+
+/// Serialize a value to an XMLRPC <value> element. Fails if the value
+/// contains a NaN or infinite double, which XMLRPC has no way to represent.
+pub fn serialize_value(value: &Value) -> Result<String, String> {
+    Ok(match *value {
+        Value::Int(x) => format!("<value><i4>{}</i4></value>", x),
+        Value::Boolean(x) => format!("<value><boolean>{}</boolean></value>", if x {1} else {0}),
+        Value::String(ref x) => format!("<value><string>{}</string></value>", escape_text(x.as_slice())),
+        Value::Double(x) => {
+            if !x.is_finite() {
+                return Err(format!("Can't serialize {} as an XMLRPC double", x));
+            }
+            format!("<value><double>{}</double></value>", x)
+        },
+        Value::Array(ref values) => {
+            let mut result = "<value><array><data>".to_string();
+            for v in values.iter() {
+                result = result + try!(serialize_value(v)).as_slice();
+            }
+            result + "</data></array></value>"
+        },
+        Value::Struct(ref members) => {
+            let mut result = "<value><struct>".to_string();
+            for (name, v) in members.iter() {
+                result = result + format!("<member><name>{}</name>{}</member>",
+                    escape_text(name.as_slice()), try!(serialize_value(v))).as_slice();
+            }
+            result + "</struct></value>"
+        },
+        Value::Base64(ref x) => format!("<value><base64>{}</base64></value>", http::base64_encode(x.as_slice())),
+    })
+}
+
+/// Types that can be decoded from an XMLRPC value.
+pub trait FromValue {
+    fn from_value(value: &Value) -> Result<Self, String>;
+}
+
+/// Types that can be encoded as an XMLRPC value.
+pub trait ToValue {
+    fn to_value(&self) -> Value;
+}
+
+impl FromValue for Value {
+    fn from_value(value: &Value) -> Result<Value, String> {
+        Ok(value.clone())
+    }
+}
+
+impl FromValue for i32 {
+    fn from_value(value: &Value) -> Result<i32, String> {
+        match *value {
+            Value::Int(x) => Ok(x),
+            ref x => Err(format!("Expected int, found {:?}", x)),
+        }
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: &Value) -> Result<bool, String> {
+        match *value {
+            Value::Boolean(x) => Ok(x),
+            ref x => Err(format!("Expected boolean, found {:?}", x)),
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: &Value) -> Result<String, String> {
+        match *value {
+            Value::String(ref x) => Ok(x.clone()),
+            ref x => Err(format!("Expected string, found {:?}", x)),
+        }
+    }
+}
+
+impl FromValue for f64 {
+    fn from_value(value: &Value) -> Result<f64, String> {
+        match *value {
+            Value::Double(x) => Ok(x),
+            // Integer-valued parameters are commonly written without a
+            // decimal point, so accept them where a double is expected.
+            Value::Int(x) => Ok(x as f64),
+            ref x => Err(format!("Expected double, found {:?}", x)),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for Vec<T> {
+    fn from_value(value: &Value) -> Result<Vec<T>, String> {
+        match *value {
+            Value::Array(ref values) => {
+                let mut result = Vec::with_capacity(values.len());
+                for v in values.iter() {
+                    result.push(try!(FromValue::from_value(v)));
+                }
+                Ok(result)
+            },
+            ref x => Err(format!("Expected array, found {:?}", x)),
+        }
+    }
+}
+
+impl<T: FromValue> FromValue for BTreeMap<String, T> {
+    fn from_value(value: &Value) -> Result<BTreeMap<String, T>, String> {
+        match *value {
+            Value::Struct(ref members) => {
+                let mut result = BTreeMap::new();
+                for (name, v) in members.iter() {
+                    let decoded = try!(FromValue::from_value(v).map_err(|err: String|
+                        format!("In member {}: {}", name, err)));
+                    result.insert(name.clone(), decoded);
+                }
+                Ok(result)
+            },
+            ref x => Err(format!("Expected struct, found {:?}", x)),
+        }
+    }
+}
+
+impl ToValue for Value {
+    fn to_value(&self) -> Value {
+        self.clone()
+    }
+}
+
+impl ToValue for i32 {
+    fn to_value(&self) -> Value {
+        Value::Int(*self)
+    }
+}
+
+impl ToValue for bool {
+    fn to_value(&self) -> Value {
+        Value::Boolean(*self)
+    }
+}
+
+impl ToValue for String {
+    fn to_value(&self) -> Value {
+        Value::String(self.clone())
+    }
+}
+
+impl<'a> ToValue for &'a str {
+    fn to_value(&self) -> Value {
+        Value::String(self.to_string())
+    }
+}
+
+impl ToValue for f64 {
+    fn to_value(&self) -> Value {
+        Value::Double(*self)
+    }
+}
+
+impl<T: ToValue> ToValue for Vec<T> {
+    fn to_value(&self) -> Value {
+        Value::Array(self.iter().map(|v| v.to_value()).collect())
+    }
+}
+
+impl<T: ToValue> ToValue for BTreeMap<String, T> {
+    fn to_value(&self) -> Value {
+        Value::Struct(self.iter().map(|(name, v)| (name.clone(), v.to_value())).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use super::{Value, FromValue, ToValue};
+
+    #[test]
+    fn test_from_value() {
+        assert_eq!(FromValue::from_value(&Value::Int(3)), Ok(3i32));
+        assert_eq!(FromValue::from_value(&Value::Int(3)), Ok(3.0f64));
+        assert_eq!(FromValue::from_value(&Value::Boolean(true)), Ok(true));
+        assert!(<i32 as FromValue>::from_value(&Value::String("3".to_string())).is_err());
+
+        let list = Value::Array(vec![Value::String("a".to_string()), Value::String("b".to_string())]);
+        assert_eq!(FromValue::from_value(&list), Ok(vec!["a".to_string(), "b".to_string()]));
+        assert!(<Vec<i32> as FromValue>::from_value(&list).is_err());
+
+        let mut inner = BTreeMap::new();
+        inner.insert("x".to_string(), Value::Double(1.5));
+        let mut outer = BTreeMap::new();
+        outer.insert("pos".to_string(), Value::Struct(inner));
+        let decoded: BTreeMap<String, BTreeMap<String, f64>> =
+            FromValue::from_value(&Value::Struct(outer)).unwrap();
+        let pos = decoded.get(&"pos".to_string()).unwrap();
+        assert_eq!(pos.get(&"x".to_string()), Some(&1.5));
+    }
+
+    #[test]
+    fn test_to_value() {
+        assert_eq!(3i32.to_value(), Value::Int(3));
+        assert_eq!("foo".to_value(), Value::String("foo".to_string()));
+        assert_eq!(vec![true, false].to_value(),
+            Value::Array(vec![Value::Boolean(true), Value::Boolean(false)]));
+
+        let mut map = BTreeMap::new();
+        map.insert("a".to_string(), 2.5f64);
+        let value = map.to_value();
+        assert_eq!(FromValue::from_value(&value), Ok(map));
+    }
+}
@@ -1,50 +1,76 @@
+#[macro_export]
 macro_rules! validate_xmlrpc_value_recursive {
     ( $v:ident, ( $($T:tt),* ) ) => (
         match $v {
-            &Value::Array(ref a) => {
+            &$crate::xmlrpc::Value::Array(ref a) => {
                 // Create a tuple whose elements are recursively filled in
-                let mut ii: isize = -1;
-                ( $(
-                    if ii >= a.len() as isize {
+                let mut ii: usize = 0;
+                let tuple = ( $(
+                    if ii >= a.len() {
                         return Err("Not enough elements in array".to_string());
                     } else {
+                        let ref e = a[ii];
                         ii += 1;
-                        let ref e = a[ii as usize];
                         validate_xmlrpc_value_recursive!(e, $T)
                     }
-                ),* )
+                ),* );
+                if ii < a.len() {
+                    return Err("Too many elements in array".to_string());
+                }
+                tuple
+            },
+            _ => return Err("Expected array, found something else".to_string()),
+        }
+    );
+    ( $v:ident, [ $T:tt ] ) => (
+        match $v {
+            &$crate::xmlrpc::Value::Array(ref a) => {
+                // Create a vector of elements that all have the same type
+                let mut elements = Vec::new();
+                for e in a.iter() {
+                    elements.push(validate_xmlrpc_value_recursive!(e, $T));
+                }
+                elements
             },
             _ => return Err("Expected array, found something else".to_string()),
         }
     );
     ( $v:ident, i32 ) => ( match $v {
-        &Value::Int(ref x) => x.clone(),
+        &$crate::xmlrpc::Value::Int(ref x) => x.clone(),
         _ => return Err("Expected int; found something else".to_string()),
     } );
+    ( $v:ident, bool ) => ( match $v {
+        &$crate::xmlrpc::Value::Boolean(ref x) => x.clone(),
+        _ => return Err("Expected boolean; found something else".to_string()),
+    } );
     ( $v:ident, String ) => ( match $v {
-        &Value::String(ref x) => x.clone(),
+        &$crate::xmlrpc::Value::String(ref x) => x.clone(),
         _ => return Err("Expected string; found something else".to_string()),
     } );
     ( $v:ident, f64 ) => ( match $v {
-        &Value::Double(ref x) => x.clone(),
+        &$crate::xmlrpc::Value::Double(ref x) => x.clone(),
         _ => return Err("Expected double; found something else".to_string()),
     } );
 }
 
 /// Just calls the macro that does the real parsing, and wraps the result in Ok()
 /// since we expect a Result<...> at the top level.
+#[macro_export]
 macro_rules! validate_xmlrpc_value_top_level {
     ( $v:ident, $T:tt ) => (
         Ok(validate_xmlrpc_value_recursive!($v, $T))
     );
 }
 
-/// Validate an xmlrpc value using a type specified as nested tuples.
+/// Validate an xmlrpc value using a type specified as nested tuples. Arrays
+/// whose elements all have the same type can be given as [T], and are
+/// returned as a Vec<T>.
+#[macro_export]
 macro_rules! validate_xmlrpc_value {
     ( $v:ident, $T:tt ) => (
         {
             // From here on down, we pass references
-            let v_ref: &Value = & $v;
+            let v_ref: &$crate::xmlrpc::Value = & $v;
 
             // Wrap the validation call in a closure so that we can return errors
             // when we want to stop validating early.
@@ -54,20 +80,25 @@ macro_rules! validate_xmlrpc_value {
 }
 
 /// Validate an xmlrpc response using a type specified as nested tuples.
+#[macro_export]
 macro_rules! validate_xmlrpc_response {
     ( $response:ident, $T:tt ) => (
         match $response {
-            Response::Fault {fault_code, fault_string} => Err(format!(
+            $crate::xmlrpc::Response::Fault {fault_code, fault_string} => Err(format!(
                 "Fault: {} ({})", fault_string, fault_code)),
-            Response::Success {param} => validate_xmlrpc_value!(param, $T),
+            $crate::xmlrpc::Response::Success {param} => validate_xmlrpc_value!(param, $T),
         }
     );
 }
 
 /// Validate an xmlrpc request using a type specified as nested tuples.
+#[macro_export]
 macro_rules! validate_xmlrpc_request {
     ( $request:ident, $T:tt ) => (
-        validate_xmlrpc_value!(xmlrpc::Value::Array($request.params), $T),
+        {
+            let params = $crate::xmlrpc::Value::Array($request.params.clone());
+            validate_xmlrpc_value!(params, $T)
+        }
     );
 }
 
@@ -126,4 +157,35 @@ mod tests {
             Err(_) => {},
         }
     }
+
+    #[test]
+    fn test_validate_xmlrpc_value_list() {
+        let v = Value::Array(vec![
+            Value::Array(vec![Value::String("/foo".to_string()), Value::Boolean(true)]),
+            Value::Array(vec![Value::String("/bar".to_string()), Value::Boolean(false)]),
+            ]);
+
+        let x = validate_xmlrpc_value!(v, [(String, bool)]);
+        assert_eq!(x, Ok(vec![("/foo".to_string(), true), ("/bar".to_string(), false)]));
+    }
+
+    #[test]
+    fn test_validate_xmlrpc_value_wrong_length() {
+        let v = Value::Array(vec![Value::Int(1i32)]);
+        assert!(validate_xmlrpc_value!(v, (i32, i32)).is_err());
+
+        let v = Value::Array(vec![Value::Int(1i32), Value::Int(2i32), Value::Int(3i32)]);
+        assert!(validate_xmlrpc_value!(v, (i32, i32)).is_err());
+    }
+
+    #[test]
+    fn test_validate_xmlrpc_request() {
+        let request = Request {
+            method_name: "getPid".to_string(),
+            params: vec![Value::String("/caller".to_string())],
+        };
+
+        let x = validate_xmlrpc_request!(request, [String]);
+        assert_eq!(x, Ok(vec!["/caller".to_string()]));
+    }
 }
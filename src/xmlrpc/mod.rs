@@ -1,11 +1,12 @@
 pub use xmlrpc::client::Client;
-pub use xmlrpc::server::{run_xmlrpc_server, HandlesXmlrpcRequests};
-pub use xmlrpc::common::{Request, Response, Value};
+pub use xmlrpc::server::{run_xmlrpc_server, HandlesXmlrpcRequests, RequestHandler};
+pub use xmlrpc::common::{Request, Response, Value, FromValue, ToValue};
 
+#[macro_use]
+mod macros;
 mod client;
 mod server;
 mod parser;
 mod common;
-mod macros;
 
 
@@ -1,3 +1,5 @@
+use std::collections::BTreeMap;
+use http;
 use xml;
 use xmlrpc::{Value, Request, Response};
 
@@ -8,10 +10,33 @@ fn parse_int(s: &str) -> Result<Value, String> {
     }
 }
 
+fn parse_boolean(s: &str) -> Result<Value, String> {
+    match s.trim() {
+        "0" => Ok(Value::Boolean(false)),
+        "1" => Ok(Value::Boolean(true)),
+        _ => Err(format!("String cannot be parsed to boolean ({})", s)),
+    }
+}
+
 fn parse_string(s: &str) -> Result<Value, String> {
     Ok(Value::String(s.to_string()))
 }
 
+fn parse_double(s: &str) -> Result<Value, String> {
+    match s.trim().parse::<f64>() {
+        Ok(x) if x.is_finite() => Ok(Value::Double(x)),
+        Ok(_) => Err(format!("Double must be finite ({})", s)),
+        Err(_) => Err(format!("String cannot be parsed to double ({})", s)),
+    }
+}
+
+fn parse_base64(s: &str) -> Result<Value, String> {
+    match http::base64_decode(s) {
+        Ok(x) => Ok(Value::Base64(x)),
+        Err(err) => Err(format!("String cannot be parsed as base64 ({})", err)),
+    }
+}
+
 fn parse_array(element: &xml::Element) -> Result<Value, String> {
     match element.children.len() {
         1 => parse_array_data(&element.children[0]),
@@ -30,16 +55,38 @@ fn parse_array_data(element: &xml::Element) -> Result<Value, String> {
     Ok(Value::Array(array))
 }
 
+fn parse_struct(element: &xml::Element) -> Result<Value, String> {
+    let mut members = BTreeMap::new();
+    for child in element.children.iter() {
+        let (name, value) = try!(parse_member(child));
+        members.insert(name, value);
+    }
+    Ok(Value::Struct(members))
+}
+
+/// Parse a <member> of a struct, which has a <name> and a <value>
+fn parse_member(element: &xml::Element) -> Result<(String, Value), String> {
+    if element.name != "member" {
+        return Err(format!("Expected <member>, found <{}>", element.name));
+    }
+    if element.children.len() != 2 || element.children[0].name != "name" {
+        return Err("Expected <name> and <value> in <member> element".to_string());
+    }
+    Ok((element.children[0].text.clone(), try!(parse_value(&element.children[1]))))
+}
+
 /// Parse an XMLRPC data element (e.g. <string>, <int> ...)
 fn parse_value_data(element: &xml::Element) -> Result<Value, String> {
     match element.name.as_slice() {
         "i4" => parse_int(element.text.as_slice()),
         "int" => parse_int(element.text.as_slice()),
-        //"boolean" => parse_boolean(data_element.text),
+        "boolean" => parse_boolean(element.text.as_slice()),
         "string" => parse_string(element.text.as_slice()),
-        //"double" => parse_double(element.text),
+        "double" => parse_double(element.text.as_slice()),
         "array" => parse_array(element),
-        // Currently not handling dateTime.iso8601 base64 or struct types
+        "struct" => parse_struct(element),
+        "base64" => parse_base64(element.text.as_slice()),
+        // Currently not handling dateTime.iso8601 type
         x => Err(format!("Found unknown xmlrpc datatype ({})", x)),
     }
 }
@@ -48,6 +95,8 @@ fn parse_value_data(element: &xml::Element) -> Result<Value, String> {
 fn parse_value(element: &xml::Element) -> Result<Value, String> {
     match element.name.as_slice() {
         "value" => match element.children.len() {
+            // A value with no type element is a string
+            0 => parse_string(element.text.as_slice()),
             1 => parse_value_data(&element.children[0]),
             x => Err(format!("Bad number of children for <value> element ({})", x)),
         },
@@ -125,8 +174,10 @@ pub fn parse_response(response_str: &str) -> Result<Response, String> {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::BTreeMap;
     use xml;
     use xmlrpc::{Request, Response, Value};
+    use xmlrpc::common::serialize_value;
     use super::{parse_request, parse_response, parse_value};
 
     #[test]
@@ -235,5 +286,96 @@ mod tests {
             Err(err) => return assert!(false, err),
         };
     }
-}
 
+    #[test]
+    fn test_serialize_and_parse_value() {
+        let val = Value::Array(vec![
+            Value::Int(-3),
+            Value::Boolean(true),
+            Value::Boolean(false),
+            Value::String("foo".to_string()),
+            Value::Double(2.5),
+            Value::Array(vec![Value::String("".to_string())])]);
+
+        let value_element = match xml::parse_xml(serialize_value(&val).unwrap().as_slice()) {
+            Ok(el) => el,
+            Err(err) => return assert!(false, err),
+        };
+
+        match parse_value(&value_element) {
+            Ok(parsed_val) => assert_eq!(parsed_val, val),
+            Err(err) => assert!(false, err),
+        };
+    }
+
+    #[test]
+    fn test_serialize_and_parse_special_characters() {
+        let mut members = BTreeMap::new();
+        members.insert("<a&b>".to_string(), Value::String("<robot name=\"a&b\"/>".to_string()));
+        members.insert("c".to_string(), Value::Array(vec![Value::String("x < y > z & 'w'".to_string())]));
+        let val = Value::Struct(members);
+
+        let serialized = serialize_value(&val).unwrap();
+        assert!(!serialized.as_slice().contains("<robot"));
+        let value_element = xml::parse_xml(serialized.as_slice()).unwrap();
+        assert_eq!(parse_value(&value_element), Ok(val));
+
+        // References written by other implementations
+        let value_element = xml::parse_xml(
+            "<value><string>&quot;&apos;&#60;&#x3e;&amp;</string></value>").unwrap();
+        assert_eq!(parse_value(&value_element), Ok(Value::String("\"'<>&".to_string())));
+    }
+
+    #[test]
+    fn test_serialize_non_finite_double() {
+        use std::f64;
+
+        assert!(serialize_value(&Value::Double(f64::NAN)).is_err());
+        assert!(serialize_value(&Value::Double(f64::INFINITY)).is_err());
+        assert!(serialize_value(&Value::Array(vec![Value::Double(f64::NEG_INFINITY)])).is_err());
+        assert_eq!(serialize_value(&Value::Double(-0.5)),
+            Ok("<value><double>-0.5</double></value>".to_string()));
+
+        for s in ["NaN", "nan", "inf", "-inf"].iter() {
+            let xml_str = format!("<value><double>{}</double></value>", s);
+            let value_element = xml::parse_xml(xml_str.as_slice()).unwrap();
+            assert_eq!(parse_value(&value_element), Err(format!("Double must be finite ({})", s)));
+        }
+    }
+
+    #[test]
+    fn test_parse_struct() {
+        let struct_str =
+        "<value><struct>\n\
+        <member><name>a</name><value><int>1</int></value></member>\n\
+        <member><name>b</name><value><struct></struct></value></member>\n\
+        </struct></value>\n";
+
+        let mut members = BTreeMap::new();
+        members.insert("a".to_string(), Value::Int(1));
+        members.insert("b".to_string(), Value::Struct(BTreeMap::new()));
+        let correct_val = Value::Struct(members);
+
+        let value_element = xml::parse_xml(struct_str).unwrap();
+        assert_eq!(parse_value(&value_element), Ok(correct_val.clone()));
+
+        let value_element = xml::parse_xml(serialize_value(&correct_val).unwrap().as_slice()).unwrap();
+        assert_eq!(parse_value(&value_element), Ok(correct_val));
+    }
+
+    #[test]
+    fn test_parse_base64() {
+        let value_element = xml::parse_xml("<value><base64>AAEC/w==</base64></value>").unwrap();
+        assert_eq!(parse_value(&value_element), Ok(Value::Base64(vec![0, 1, 2, 255])));
+
+        let value = Value::Base64(b"callerid=/talker".to_vec());
+        let value_element = xml::parse_xml(serialize_value(&value).unwrap().as_slice()).unwrap();
+        assert_eq!(parse_value(&value_element), Ok(value));
+    }
+
+    #[test]
+    fn test_parse_untyped_value() {
+        let value_element = xml::parse_xml("<value>some text</value>").unwrap();
+        assert_eq!(parse_value(&value_element), Ok(Value::String("some text".to_string())));
+    }
+}
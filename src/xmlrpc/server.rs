@@ -1,32 +1,22 @@
 use std::old_io::TcpListener;
 use http;
 use xmlrpc::parser;
-use xmlrpc::common::{Request, Response, Value};
+use xmlrpc::common::{Request, Response, serialize_value};
 
 pub fn run_xmlrpc_server<H: HandlesXmlrpcRequests>(
     listener: TcpListener,
-    num_threads: usize,
+    config: http::ServerConfig,
     xmlrpc_request_handler: H,
-    ) -> Result<(), String>
+    ) -> Result<http::ServerHandle, String>
 {
-    let handler = RequestHandler {xmlrpc_request_handler: xmlrpc_request_handler};
-
-    match http::run_http_server(listener, num_threads, handler) {
-        Ok(_) => Ok(()),
-        Err(_) => panic!("HTTP server died"),
-    }
+    let handler = RequestHandler::new(xmlrpc_request_handler);
+    http::run_http_server(listener, config, handler)
 }
 
 fn serialize_response(response: &Response) -> Result<String, String> {
     match *response {
         Response::Fault {fault_code: _, fault_string: _} => return Err(format!("Don't know how to serialize fault responses")),
         Response::Success {ref param} => {
-            let param_str = match param {
-                &Value::String(ref val) => format!(
-                    "<param><value><string>{}</string></value></param>", val),
-                other_val => return Err(format!("Don't know how to serialize XMLRPC value {:?}", other_val)),
-            };
-
             Ok(format!(
                 "<?xml version=\"1.0\"?>\n\
                 <methodResponse>\n\
@@ -35,34 +25,52 @@ fn serialize_response(response: &Response) -> Result<String, String> {
                   {}\n\
                   </param>\n\
                 </params>\n\
-                </methodResponse>\n", param_str))
+                </methodResponse>\n", try!(serialize_value(param))))
         },
     }
 }
 
 /// Handles HTTP requests by parsing out the XMLRPC request, and calling
-/// the user supplied callback on it.
+/// the user supplied callback on it. Can be added to an http::Router to
+/// serve XMLRPC alongside other HTTP handlers.
 #[derive(Clone)]
-struct RequestHandler<H: HandlesXmlrpcRequests> {
+pub struct RequestHandler<H: HandlesXmlrpcRequests> {
     xmlrpc_request_handler: H,
 }
 
+impl<H: HandlesXmlrpcRequests> RequestHandler<H> {
+    pub fn new(xmlrpc_request_handler: H) -> RequestHandler<H> {
+        RequestHandler {xmlrpc_request_handler: xmlrpc_request_handler}
+    }
+}
+
 impl<H: HandlesXmlrpcRequests> http::HandlesHttpRequests for RequestHandler<H> {
-    fn handle_request(&self, _: &http::RequestHeader, body: &str) -> (i32, String) {
-        println!("==== Got xmlrpc request:\n{}----\n", body);
+    fn handle_request(&self, header: &http::RequestHeader, body: &str) -> (i32, String) {
+        let (status, _, body) = self.handle_request_with_headers(header, body);
+        (status, body)
+    }
+
+    fn handle_request_with_headers(&self, header: &http::RequestHeader, body: &str)
+            -> (i32, Vec<(String, String)>, String) {
+        if header.method.as_slice() != "POST" {
+            return (405, vec![("Allow".to_string(), "POST".to_string())],
+                format!("XMLRPC requests must be POSTs, not {}", header.method));
+        }
+
+        debug!("Got xmlrpc request:\n{}", body);
 
         match parser::parse_request(body) {
             Err(err) => {
                 // TODO: Return a fault response to client
-                panic!(format!("Unable to parse incoming xmlrpc request:\n{}", err))
+                (400, vec![], format!("Unable to parse incoming xmlrpc request:\n{}", err))
             },
             Ok(request) => {
                 let response = self.xmlrpc_request_handler.handle_request(&request);
                 match serialize_response(&response) {
-                    Err(err) => panic!(format!("Unable to serialize xmlrpc response:\n{}", err)),
+                    Err(err) => (500, vec![], format!("Unable to serialize xmlrpc response:\n{}", err)),
                     Ok(response_str) => {
-                        println!("response:\n{}====\n", response_str);
-                        (200, response_str)
+                        debug!("Sending xmlrpc response:\n{}", response_str);
+                        (200, vec![("Content-Type".to_string(), "text/xml".to_string())], response_str)
                     },
                 }
             },
@@ -73,3 +81,34 @@ impl<H: HandlesXmlrpcRequests> http::HandlesHttpRequests for RequestHandler<H> {
 pub trait HandlesXmlrpcRequests: Sync + Send + Clone {
     fn handle_request(&self, request: &Request) -> Response;
 }
+
+#[cfg(test)]
+mod tests {
+    use http;
+    use http::HandlesHttpRequests;
+    use xmlrpc::{Request, Response, Value};
+    use super::{HandlesXmlrpcRequests, RequestHandler};
+
+    #[derive(Clone)]
+    struct OkHandler;
+
+    impl HandlesXmlrpcRequests for OkHandler {
+        fn handle_request(&self, _: &Request) -> Response {
+            Response::Success {param: Value::Int(1)}
+        }
+    }
+
+    #[test]
+    fn test_non_post_not_allowed() {
+        let header = http::RequestHeader {
+            method: "GET".to_string(),
+            request_uri: "/".to_string(),
+            http_version: "HTTP/1.1".to_string(),
+            content_length: 0,
+            authorization: None,
+        };
+        let (status, headers, _) = RequestHandler::new(OkHandler).handle_request_with_headers(&header, "");
+        assert_eq!(status, 405);
+        assert_eq!(headers, vec![("Allow".to_string(), "POST".to_string())]);
+    }
+}
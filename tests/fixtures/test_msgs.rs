@@ -0,0 +1,146 @@
+// Generated from ROS message and service definitions, do not edit.
+
+#[allow(dead_code, non_upper_case_globals, non_camel_case_types)]
+pub mod test_msgs {
+
+    #[derive(Debug, PartialEq, Clone)]
+    pub struct Inner {
+        pub value: i32,
+    }
+
+    impl ::ros_rust::msg::RosField for Inner {
+        fn encode<W: ::std::old_io::Writer>(&self, stream: &mut W) -> ::std::old_io::IoResult<()> {
+            try!(::ros_rust::msg::RosField::encode(&self.value, stream));
+            Ok(())
+        }
+
+        fn decode<R: ::std::old_io::Reader>(stream: &mut R) -> ::std::result::Result<Inner, ::std::string::String> {
+            Ok(Inner {
+                value: try!(::ros_rust::msg::RosField::decode(stream)),
+            })
+        }
+    }
+
+    impl ::ros_rust::msg::RosMessage for Inner {
+        fn msg_type() -> &'static str {
+            "test_msgs/Inner"
+        }
+
+        fn md5sum() -> &'static str {
+            "b3087778e93fcd34cc8d65bc54e850d1"
+        }
+
+        fn msg_definition() -> &'static str {
+            "int32 value\n"
+        }
+    }
+
+    pub const ARRAYS_HALF: f32 = 0.5;
+    pub const ARRAYS_MINUS_HALF: f64 = -0.5;
+    #[derive(Debug, PartialEq, Clone)]
+    pub struct Arrays {
+        pub small: [u8; 4],
+        pub covariance: ::std::vec::Vec<f64>,
+        pub block: ::std::vec::Vec<u8>,
+        pub names: ::std::vec::Vec<::std::string::String>,
+        pub inners: ::std::vec::Vec<Inner>,
+        pub values: ::std::vec::Vec<i32>,
+    }
+
+    impl ::ros_rust::msg::RosField for Arrays {
+        fn encode<W: ::std::old_io::Writer>(&self, stream: &mut W) -> ::std::old_io::IoResult<()> {
+            try!(::ros_rust::msg::RosField::encode(&self.small, stream));
+            if self.covariance.len() != 36 {
+                return Err(::std::old_io::IoError {
+                    kind: ::std::old_io::IoErrorKind::InvalidInput,
+                    desc: "Fixed length array has the wrong length",
+                    detail: Some(format!("Arrays.covariance has {} elements instead of 36", self.covariance.len())),
+                });
+            }
+            for element in self.covariance.iter() {
+                try!(::ros_rust::msg::RosField::encode(element, stream));
+            }
+            if self.block.len() != 64 {
+                return Err(::std::old_io::IoError {
+                    kind: ::std::old_io::IoErrorKind::InvalidInput,
+                    desc: "Fixed length array has the wrong length",
+                    detail: Some(format!("Arrays.block has {} elements instead of 64", self.block.len())),
+                });
+            }
+            for element in self.block.iter() {
+                try!(::ros_rust::msg::RosField::encode(element, stream));
+            }
+            if self.names.len() != 2 {
+                return Err(::std::old_io::IoError {
+                    kind: ::std::old_io::IoErrorKind::InvalidInput,
+                    desc: "Fixed length array has the wrong length",
+                    detail: Some(format!("Arrays.names has {} elements instead of 2", self.names.len())),
+                });
+            }
+            for element in self.names.iter() {
+                try!(::ros_rust::msg::RosField::encode(element, stream));
+            }
+            if self.inners.len() != 2 {
+                return Err(::std::old_io::IoError {
+                    kind: ::std::old_io::IoErrorKind::InvalidInput,
+                    desc: "Fixed length array has the wrong length",
+                    detail: Some(format!("Arrays.inners has {} elements instead of 2", self.inners.len())),
+                });
+            }
+            for element in self.inners.iter() {
+                try!(::ros_rust::msg::RosField::encode(element, stream));
+            }
+            try!(::ros_rust::msg::RosField::encode(&self.values, stream));
+            Ok(())
+        }
+
+        fn decode<R: ::std::old_io::Reader>(stream: &mut R) -> ::std::result::Result<Arrays, ::std::string::String> {
+            Ok(Arrays {
+                small: try!(::ros_rust::msg::RosField::decode(stream)),
+                covariance: {
+                    let mut elements = ::std::vec::Vec::with_capacity(36);
+                    for _ in 0..36 {
+                        elements.push(try!(::ros_rust::msg::RosField::decode(stream)));
+                    }
+                    elements
+                },
+                block: {
+                    let mut elements = ::std::vec::Vec::with_capacity(64);
+                    for _ in 0..64 {
+                        elements.push(try!(::ros_rust::msg::RosField::decode(stream)));
+                    }
+                    elements
+                },
+                names: {
+                    let mut elements = ::std::vec::Vec::with_capacity(2);
+                    for _ in 0..2 {
+                        elements.push(try!(::ros_rust::msg::RosField::decode(stream)));
+                    }
+                    elements
+                },
+                inners: {
+                    let mut elements = ::std::vec::Vec::with_capacity(2);
+                    for _ in 0..2 {
+                        elements.push(try!(::ros_rust::msg::RosField::decode(stream)));
+                    }
+                    elements
+                },
+                values: try!(::ros_rust::msg::RosField::decode(stream)),
+            })
+        }
+    }
+
+    impl ::ros_rust::msg::RosMessage for Arrays {
+        fn msg_type() -> &'static str {
+            "test_msgs/Arrays"
+        }
+
+        fn md5sum() -> &'static str {
+            "3287207b93ca0841c9ac3be6fff5a4b2"
+        }
+
+        fn msg_definition() -> &'static str {
+            "# Fixed length arrays, some too long to be Rust arrays\nfloat32 HALF=.5\nfloat64 MINUS_HALF=-.5\nuint8[4] small\nfloat64[36] covariance\nuint8[64] block\nstring[2] names\nInner[2] inners\nint32[] values\n\n================================================================================\nMSG: test_msgs/Inner\nint32 value\n"
+        }
+    }
+}
@@ -0,0 +1,75 @@
+//! Compiles code from the message generator, and checks that messages
+//! survive being sent and received. The generated code is checked in as
+//! tests/fixtures/test_msgs.rs; test_fixture_is_current fails, printing the
+//! new code, when the generator's output changes.
+
+#![feature(io)]
+extern crate ros_rust;
+
+use std::old_io::MemReader;
+use ros_rust::genmsg::{generate, parse_msg, Package};
+use ros_rust::msg::{RosField, RosMessage};
+use ros_rust::tcpros;
+use msgs::test_msgs::{Arrays, Inner, ARRAYS_HALF, ARRAYS_MINUS_HALF};
+
+mod msgs {
+    include!("fixtures/test_msgs.rs");
+}
+
+fn packages() -> Vec<Package> {
+    vec![Package {
+        name: "test_msgs".to_string(),
+        messages: vec![
+            parse_msg("test_msgs", "Inner", "int32 value\n").unwrap(),
+            parse_msg("test_msgs", "Arrays", "# Fixed length arrays, some too long to be Rust arrays\n\
+                float32 HALF=.5\nfloat64 MINUS_HALF=-.5\nuint8[4] small\nfloat64[36] covariance\n\
+                uint8[64] block\nstring[2] names\nInner[2] inners\nint32[] values\n").unwrap(),
+        ],
+        services: vec![],
+    }]
+}
+
+fn arrays() -> Arrays {
+    Arrays {
+        small: [1, 2, 3, 4],
+        covariance: (0..36).map(|i| i as f64 / 2.0).collect(),
+        block: vec![7; 64],
+        names: vec!["a".to_string(), "bc".to_string()],
+        inners: vec![Inner {value: 1}, Inner {value: -1}],
+        values: vec![5, 6, 7],
+    }
+}
+
+#[test]
+fn test_fixture_is_current() {
+    let code = generate(packages().as_slice()).unwrap();
+    assert!(code.as_slice() == include_str!("fixtures/test_msgs.rs"),
+        "tests/fixtures/test_msgs.rs is out of date, it should be:\n{}", code);
+}
+
+#[test]
+fn test_round_trip() {
+    let message = arrays();
+    let bytes = tcpros::encode_message(&message);
+    // Fixed length arrays are sent without their lengths
+    assert_eq!(bytes.len(), 4 + 4 + 36 * 8 + 64 + (4 + 1) + (4 + 2) + 2 * 4 + (4 + 3 * 4));
+    let decoded: Arrays = tcpros::read_message(&mut MemReader::new(bytes), tcpros::MAX_MESSAGE_BYTES).unwrap();
+    assert_eq!(decoded, message);
+
+    // Vecs standing in for fixed length arrays have to have the right length
+    let mut bytes: Vec<u8> = vec![];
+    let mut message = arrays();
+    message.covariance.pop();
+    assert!(message.encode(&mut bytes).is_err());
+    let mut message = arrays();
+    message.inners.push(Inner {value: 2});
+    assert!(message.encode(&mut bytes).is_err());
+}
+
+#[test]
+fn test_constants() {
+    assert_eq!(ARRAYS_HALF, 0.5);
+    assert_eq!(ARRAYS_MINUS_HALF, -0.5);
+    assert_eq!(Arrays::msg_type(), "test_msgs/Arrays");
+    assert!(Arrays::msg_definition().contains("MSG: test_msgs/Inner\nint32 value\n"));
+}